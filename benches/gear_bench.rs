@@ -0,0 +1,104 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use gear_trading::hff::account::OrderFill;
+use gear_trading::hff::agents::{Agent, AgentInventory, AgentPL, GearHedger};
+use gear_trading::hff::quote::Tick;
+use gear_trading::{ExtrapMode, Gear, GearRange};
+
+// gear with n contiguous ranges between 1.0 and 2.0
+fn multi_range(n: usize) -> Gear {
+    let width = 1.0 / n as f64;
+    Gear {
+        p_0: 1.0,
+        g_0: 1.0,
+        g_i: (0..n)
+            .map(|i| GearRange {
+                p_start: 1.0 + i as f64 * width,
+                g_start: 1.0 - 2.0 * i as f64 / n as f64,
+                p_end: 1.0 + (i + 1) as f64 * width,
+                g_end: 1.0 - 2.0 * (i + 1) as f64 / n as f64,
+            })
+            .collect(),
+        p_n: 2.0,
+        g_n: -1.0,
+        extrap: ExtrapMode::Clamp,
+    }
+}
+
+// slow oscillation inside the gear ranges
+fn prices(n: usize) -> Vec<f64> {
+    (0..n).map(|i| 1.5 + 0.4 * (i as f64 / 500.0).sin()).collect()
+}
+
+fn gear_g(c: &mut Criterion) {
+    let prices = prices(10000);
+    let mut group = c.benchmark_group("Gear::g");
+    for n in [1, 10, 100] {
+        let gear = multi_range(n);
+        group.bench_with_input(BenchmarkId::new("linear", n), &gear, |b, gear| {
+            b.iter(|| prices.iter().map(|x| gear.g_linear(black_box(*x))).sum::<f64>())
+        });
+        group.bench_with_input(BenchmarkId::new("binary", n), &gear, |b, gear| {
+            b.iter(|| prices.iter().map(|x| gear.g(black_box(*x))).sum::<f64>())
+        });
+        group.bench_with_input(BenchmarkId::new("hint", n), &gear, |b, gear| {
+            b.iter(|| {
+                let mut hint = 0;
+                prices.iter().map(|x| gear.g_with_hint(black_box(*x), &mut hint)).sum::<f64>()
+            })
+        });
+    }
+    group.finish();
+}
+
+// agent with a position at 1.0000, grid at 0.9990 and 1.0010
+fn hedger() -> GearHedger {
+    let mut agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 100000.0, f64::MAX);
+    agent.next_exposure(&Tick { time: 0, bid: 0.9990, ask: 0.9990 });
+    agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+    agent
+}
+
+fn hedger_next_exposure(c: &mut Criterion) {
+    let agent = hedger();
+    let mut group = c.benchmark_group("GearHedger::next_exposure");
+    for (name, price) in [("below", 0.9970), ("within", 0.9990), ("above", 1.0010)] {
+        let tick = Tick { time: 0, bid: price, ask: price };
+        group.bench_function(name, |b| {
+            b.iter_batched_ref(|| agent.clone(), |a| a.next_exposure(black_box(&tick)), criterion::BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+fn agent_pl(c: &mut Criterion) {
+    // 10000 alternating trades around 1.0
+    let mut pl = AgentPL::flat();
+    for i in 0..10000 {
+        let price = 1.0 + 0.001 * (i % 7) as f64;
+        if i % 3 == 0 { pl.sell(price, 1000) } else { pl.buy(price, 1000) }
+    }
+    c.bench_function("AgentPL::buy", |b| {
+        b.iter_batched_ref(|| pl.clone(), |p| p.buy(black_box(1.0010), 1000), criterion::BatchSize::SmallInput)
+    });
+    c.bench_function("AgentPL::sell", |b| {
+        b.iter_batched_ref(|| pl.clone(), |p| p.sell(black_box(1.0010), 1000), criterion::BatchSize::SmallInput)
+    });
+}
+
+fn inventory_next_exposure(c: &mut Criterion) {
+    let tick = Tick { time: 0, bid: 0.9970, ask: 0.9970 };
+    let mut group = c.benchmark_group("AgentInventory::next_exposure");
+    for n in [10, 100, 1000] {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        for i in 0..n {
+            inventory.agents.insert(format!("agent_{}", i), hedger());
+        }
+        group.bench_with_input(BenchmarkId::from_parameter(n), &tick, |b, tick| {
+            b.iter(|| inventory.next_exposure(black_box(tick)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, gear_g, hedger_next_exposure, agent_pl, inventory_next_exposure);
+criterion_main!(benches);
@@ -32,8 +32,8 @@ fn main() {
         })
         .flatten().unwrap();
 
-    let agent = serde_json::from_str::<GAgent>(args.agent.as_str()).ok().unwrap();
-    hedger.agents.insert(args.name.clone(), agent.build().unwrap());
+    let agent = build_from_json_str(args.agent.as_str()).unwrap_or_else(|e| panic!("Cannot build the agent: {}", e));
+    hedger.agents.insert(args.name.clone(), agent);
 
     println!("{}", serde_json::to_string(&hedger).unwrap());
 }
\ No newline at end of file
@@ -151,7 +151,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         let now = Utc::now().timestamp();
 
         // check account positions
-        let positions = client.get_open_positions().await.unwrap().to_position_vec();
+        let positions = client.get_open_positions().await.unwrap();
         //println!("{:?}", positions);
 
         // compare target exposure with actual
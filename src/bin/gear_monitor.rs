@@ -0,0 +1,123 @@
+extern crate gear_trading;
+
+use clap::Parser;
+use crossterm::cursor::MoveTo;
+use crossterm::execute;
+use crossterm::terminal::{Clear, ClearType};
+use notify::{RecursiveMode, Watcher};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use gear_trading::hff::agents::*;
+use gear_trading::hff::quote::Tick;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Name of the hedger file to watch
+    #[arg(short = 'f', long)]
+    file: String,
+
+    /// Current price, to estimate the unrealized PL
+    #[arg(short = 'p', long)]
+    price: f64,
+
+    /// Instrument of the price, needed only when the agents trade more than one
+    #[arg(short = 'i', long)]
+    instrument: Option<String>,
+
+    /// Seconds at least between two redraws, the writes of a burst are drawn once
+    #[arg(short = 'r', long, default_value_t = 5)]
+    refresh_secs: u64,
+}
+
+// dashboard of the agents trading instrument
+fn dashboard(file: &str, inventory: &AgentInventory<GearHedger>, instrument: &str, price: f64) -> String {
+    let tick = Tick { time: 0, bid: price, ask: price };
+    let mut traded: AgentInventory<GearHedger> = AgentInventory::new();
+    traded.agents = inventory
+        .agents
+        .iter()
+        .filter(|(_, agent)| agent.instrument == instrument || agent.instrument.is_empty())
+        .map(|(name, agent)| (name.clone(), agent.clone()))
+        .collect();
+    let inventory = &traded;
+    let summary = inventory.summarize(instrument, &tick);
+
+    let mut out = String::new();
+    out.push_str(&format!("{} at {}\n\n", file, chrono::Utc::now().to_rfc3339()));
+    out.push_str(&format!("instrument     {:>14}\n", instrument));
+    out.push_str(&format!("price          {:>14.5}\n", price));
+    out.push_str(&format!("exposure       {:>14}\n", summary.total_exposure));
+    out.push_str(&format!("realized PL    {:>14.2}\n", summary.realized_pl));
+    out.push_str(&format!("unrealized PL  {:>14.2}\n", summary.unrealized_pl));
+    out.push_str(&format!("total PL       {:>14.2}\n", summary.realized_pl + summary.unrealized_pl));
+    out.push_str(&format!("active agents  {:>7} / {}\n\n", summary.active_count, summary.agent_count));
+
+    let mut names: Vec<&String> = inventory.agents.keys().collect();
+    names.sort();
+    out.push_str(&format!("{:<20} {:>6} {:>10} {:>12}\n", "agent", "active", "exposure", "realized PL"));
+    for name in names {
+        let agent = &inventory.agents[name];
+        out.push_str(&format!(
+            "{:<20} {:>6} {:>10} {:>12.2}\n",
+            name,
+            agent.active,
+            agent.exposure(),
+            agent.agentPL.cum_profit
+        ));
+    }
+    out
+}
+
+// the instrument of the price: the one given, or the one all the agents trade
+fn instrument_of(inventory: &AgentInventory<GearHedger>, instrument: Option<&String>) -> Result<String, String> {
+    match (instrument, inventory.instruments().as_slice()) {
+        (Some(instrument), _) => Ok(instrument.clone()),
+        (None, []) => Ok(String::new()),
+        (None, [instrument]) => Ok(instrument.clone()),
+        (None, instruments) => Err(format!("the agents trade {}, choose one with --instrument", instruments.join(", "))),
+    }
+}
+
+fn draw(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    // the trader may be writing the file, a partial read is retried on the next change
+    let inventory = serde_json::from_str::<AgentInventory<GearHedger>>(&fs::read_to_string(&args.file)?)?;
+    let instrument = instrument_of(&inventory, args.instrument.as_ref())?;
+    let mut stdout = io::stdout();
+    execute!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+    write!(stdout, "{}", dashboard(&args.file, &inventory, &instrument, args.price))?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let delay = Duration::from_secs(args.refresh_secs.max(1));
+    let path = Path::new(&args.file);
+
+    // the directory is watched, a file replaced by a rename is still seen
+    let (sender, changes) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    let mut changed = true;
+    loop {
+        if changed {
+            if let Err(e) = draw(&args) {
+                eprintln!("Cannot show the inventory in {}: {}", args.file, e);
+            }
+        }
+        let event = changes.recv()?;
+        changed = event.is_ok_and(|e| e.paths.iter().any(|p| p.file_name() == path.file_name()));
+        if changed {
+            // let a burst of writes settle, then draw once
+            thread::sleep(delay);
+            while changes.try_recv().is_ok() {}
+        }
+    }
+}
@@ -0,0 +1,24 @@
+extern crate gear_trading;
+
+use clap::Parser;
+use std::fs;
+use gear_trading::hff::agents::*;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Golang strategy file, a json array of CL / Coastline configurations
+    #[arg(short = 'f', long)]
+    golang_file: String,
+}
+
+// print the inventory of the agents of a golang strategy file
+fn main() {
+    let args = Args::parse();
+
+    let golang = fs::read_to_string(&args.golang_file)
+        .unwrap_or_else(|e| panic!("Cannot read {}: {}", args.golang_file, e));
+    let inventory = import_golang(&golang).unwrap_or_else(|e| panic!("Cannot import {}: {}", args.golang_file, e));
+
+    println!("{}", inventory.to_json().unwrap());
+}
@@ -43,12 +43,12 @@ fn main() {
     let agent1 = hedger.agents.get(name1).unwrap();
     let agent2 = hedger.agents.get(name2).unwrap();
 
-    let agent = agent1.merge_flat(agent2);
+    let agent = agent1.merge_flat(agent2).unwrap_or_else(|e| panic!("Cannot merge {} and {}: {}", name1, name2, e));
     hedger.agents.insert(outname, agent);
     hedger.agents.remove(name1);
     hedger.agents.remove(name2);
 
-    let hedger_str = serde_json::to_string(&hedger).ok().unwrap();
+    let hedger_str = hedger.to_json().unwrap();
     println!("{}", hedger_str);
 
 }
\ No newline at end of file
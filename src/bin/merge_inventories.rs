@@ -45,7 +45,7 @@ fn main() {
         hedger1.agents.insert(String::from(a.0), xx);
     });
     
-    let hedger_str = serde_json::to_string(&hedger1).ok().unwrap();
+    let hedger_str = hedger1.to_json().unwrap();
     println!("{}", hedger_str);
 
 }
\ No newline at end of file
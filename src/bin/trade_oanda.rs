@@ -5,7 +5,6 @@ use std::fs;
 use std::{thread, time};
 
 use chrono::DateTime;
-use chrono::Utc;
 use error_chain::error_chain;
 use serde::Deserialize;
 use serde_json::json;
@@ -13,9 +12,11 @@ use std::env;
 //use reqwest::Client;
 use gear_trading::hff::account::*;
 use gear_trading::hff::agents::*;
+use gear_trading::hff::instrument::Instrument;
 use gear_trading::hff::quote::Tick;
-use gear_trading::oanda::client::Client;
-use gear_trading::oanda::*;
+use gear_trading::oanda::client::{Client, OandaApi};
+use gear_trading::oanda::replay::ReplayClient;
+use gear_trading::oanda::runner::{Heartbeat, StepOutcome, TradingRunner};
 
 use std::error::Error;
 use tokio::main;
@@ -46,6 +47,73 @@ struct Args {
 
     #[clap(long)]
     clean: bool,
+
+    /// Instrument of the agents of files written before instrument tagging
+    #[arg(long, default_value = "EUR_USD")]
+    default_instrument: String,
+
+    /// Hard limit on the account position in units, orders are clamped to it
+    #[arg(long)]
+    max_position: Option<i64>,
+
+    /// Refuse to trade from a hedger file written more than this many seconds ago
+    #[arg(long)]
+    max_state_age: Option<i64>,
+
+    /// Trade even if the hedger file is older than max_state_age
+    #[clap(long)]
+    force_resume: bool,
+
+    /// Allow trading on a live (real money) Oanda account
+    #[clap(long)]
+    live: bool,
+
+    /// Trade against the bars of a Lean YYYYMMDD.zip file or directory instead of Oanda
+    #[arg(long)]
+    replay: Option<String>,
+
+    /// Replay speed as a multiple of real time, as fast as possible if not set
+    #[arg(long)]
+    replay_speed: Option<f64>,
+
+    /// Do not trade on ticks with a wider spread, for all the agents
+    #[arg(long)]
+    max_spread: Option<f64>,
+
+    /// Log exposure, PL and last tick every N cycles without trade
+    #[arg(long)]
+    heartbeat_cycles: Option<u64>,
+
+    /// Log exposure, PL and last tick every N seconds without trade
+    #[arg(long)]
+    heartbeat_secs: Option<u64>,
+
+    /// Size the agent given with --agent so a pip at its max exposure is worth this much account currency
+    #[arg(long)]
+    risk_per_pip: Option<f64>,
+}
+
+// run the trading loop, printing the inventory after each trade
+async fn run<C: OandaApi>(mut runner: TradingRunner<C>, dry: bool, delay: time::Duration) {
+    loop {
+        if dry {
+            break;
+        }
+        // control loop timing
+        if runner.iteration() != 0 && !delay.is_zero() {
+            thread::sleep(delay);
+        }
+
+        match runner.step().await {
+            StepOutcome::Finished => break,
+            StepOutcome::Traded(_) => {
+                runner.inventory_mut().stamp(chrono::Utc::now().timestamp());
+                let hedger_str = runner.inventory().to_json().unwrap();
+                println!("{}", hedger_str);
+            }
+            _ => {}
+        }
+    }
 }
 
 #[tokio::main]
@@ -67,17 +135,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
     if hedger_opt.is_none() {}
 
     let delay = time::Duration::from_secs(15);
-    let mut iter = 0;
 
-    let oanda_url = env::var("OANDA_URL")?;
-    let oanda_account = env::var("OANDA_ACCOUNT")?;
-    let oanda_api_key = env::var("OANDA_API_KEY")?;
+    // replay runs the same loop on historical bars, without an Oanda account
+    let client = match args.replay {
+        Some(_) => None,
+        None => {
+            let oanda_url = env::var("OANDA_URL")?;
+            let oanda_account = env::var("OANDA_ACCOUNT")?;
+            let oanda_api_key = env::var("OANDA_API_KEY")?;
+
+            let client = Client::new(
+                oanda_url.clone(),
+                oanda_account.clone(),
+                oanda_api_key.clone(),
+            );
+            if client.is_live() && !args.live && !args.dry {
+                return Err(format!("{} is a live account, use --live to trade it", oanda_url).into());
+            }
+            Some(client)
+        }
+    };
 
-    let client = Client::new(
-        oanda_url.clone(),
-        oanda_account.clone(),
-        oanda_api_key.clone(),
-    );
     let mut hedger =
         hedger_opt.unwrap_or_else(|| {
             let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
@@ -85,82 +163,73 @@ async fn main() -> Result<(), Box<dyn Error>> {
         });
 
     if args.agent.is_some() && args.name.is_some() {
-        let agent = serde_json::from_str::<GAgent>(args.agent.unwrap().as_str()).ok().unwrap().build();
-        hedger.agents.insert(args.name.unwrap().clone(), agent.unwrap());
-    }
-
-    if args.clean {
-        hedger.agents.retain(|_name, agent| agent.active);
-    }
-
-    let hedger_str = serde_json::to_string(&hedger).ok().unwrap();
-    println!("{}", hedger_str);
-
-    loop {
-        if args.dry {
-            break;
-        }
-        // control loop counts and timing
-        if iter != 0 {
-            thread::sleep(delay);
-        }
-        iter = iter + 1;
-        if iter > 10000 {
-            break;
+        let mut agent = build_from_json_str(args.agent.unwrap().as_str())?;
+        if let Some(risk_per_pip) = args.risk_per_pip {
+            let name = if agent.instrument.is_empty() { &args.default_instrument } else { &agent.instrument };
+            let instrument = Instrument::from_name(name).ok_or(format!("{} is not an instrument name", name))?;
+            // the quote currency is taken as the account currency without Oanda conversions (replay)
+            let quote_rate = match &client {
+                Some(client) => client
+                    .get_pricing(instrument.name.clone())
+                    .await
+                    .and_then(|p| p.home_conversions())
+                    .map_or(1.0, |c| c.quote_home.loss),
+                None => 1.0,
+            };
+            agent.size_for_pip_risk(&instrument, risk_per_pip, quote_rate);
         }
+        hedger.agents.insert(args.name.unwrap().clone(), agent);
+    }
 
-        // get the market tick
-        let tick_opt = client
-            .get_pricing(String::from("EUR_USD"))
-            .await
-            .map(|x| x.get_tick());
-        if tick_opt.is_none() {
-            continue;
+    hedger.tag_untagged(&args.default_instrument);
+    if args.max_spread.is_some() {
+        for agent in hedger.agents.values_mut() {
+            agent.max_spread = args.max_spread;
         }
-        let tick = tick_opt.unwrap();
-
-        // time now
-        let now = Utc::now().timestamp();
+    }
 
-        // check account positions
-        let positions_opt = client.get_open_positions().await.map(|x| x.to_position_vec());
-        if positions_opt.is_none() {
-            continue;
+    let now = chrono::Utc::now().timestamp();
+    if let Some(max_state_age) = args.max_state_age {
+        if args.hedger_file.is_some() && !args.dry && !args.force_resume && hedger.is_stale(now, max_state_age) {
+            return Err(format!(
+                "Hedger file written at {:?} is older than {} seconds, check the positions and use --force-resume",
+                hedger.saved_at, max_state_age
+            )
+            .into());
         }
-        let positions = positions_opt.unwrap();
-        //println!("{:?}", positions);
-
-        // compare target exposure with actual
-        let target_exposure = hedger.next_exposure(&tick);
-        let account_exposure = positions.iter().filter(|p| p.instrument == "EUR_USD").last().map_or_else(|| 0, |p| p.units);
-        //println!("Target Exposure: {}", target_exposure);
-        //println!("Actual Exposure: {}", account_exposure);
+    }
+    hedger.stamp(now);
 
-        // no trade
-        if target_exposure == account_exposure {
+    if args.clean {
+        hedger.agents.retain(|_name, agent| agent.active);
+    }
 
-            continue;
-        }
+    // the runner trades a single instrument, the one of all the agents
+    let instrument = match hedger.instruments().as_slice() {
+        [] => args.default_instrument.clone(),
+        [instrument] => instrument.clone(),
+        instruments => return Err(format!("the agents trade more than one instrument: {}", instruments.join(", ")).into()),
+    };
 
-        // create order
-        let order = OrderRequest::new(target_exposure - account_exposure, "EUR_USD".to_string());
-
-        eprintln!("Trading : {} to reach {} at price", target_exposure - account_exposure, target_exposure);
-
-        client.post_order_request(&order).await.map_or(
-            eprintln!("Cannot get the Post Order to Oanda, will try again next cycle"),
-            |order_fill| {
-                order_fill.get_order_fill().map_or(
-                    eprintln!("Cannot get the OrderFill from response, will try again next cycle"),
-                    |of| {
-                        hedger.update_on_fill(&of);
-                        let hedger_str = serde_json::to_string(&hedger).ok().unwrap();
-                        println!("{}", hedger_str);
-                    },
-                );
-            },
-        );
+    let hedger_str = hedger.to_json().unwrap();
+    println!("{}", hedger_str);
 
+    let heartbeat = Heartbeat::new(args.heartbeat_cycles, args.heartbeat_secs.map(time::Duration::from_secs));
+
+    if let Some(client) = client {
+        let runner = TradingRunner::new(client, instrument, hedger, 10000)
+            .with_max_position(args.max_position)
+            .with_heartbeat(heartbeat);
+        run(runner, args.dry, delay).await;
+    } else if let Some(replay) = args.replay {
+        let client = ReplayClient::from_path(instrument.clone(), std::path::Path::new(&replay))?
+            .with_speed(args.replay_speed);
+        // one bar per iteration, the loop ends with the data
+        let bars = client.len() as u64;
+        let runner = TradingRunner::new(client, instrument, hedger, bars)
+            .with_max_position(args.max_position)
+            .with_heartbeat(heartbeat);
+        run(runner, args.dry, time::Duration::ZERO).await;
     }
 
     Ok(())
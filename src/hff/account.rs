@@ -3,9 +3,10 @@ pub struct Position {
     pub instrument: String,
     pub units: i64,
     pub price: Option<f64>,
+    pub unrealized_pl: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct OrderFill {
     pub price: f64,
     pub units: i64,
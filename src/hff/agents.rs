@@ -1,12 +1,40 @@
+//! Agents turning ticks into target exposures, and the inventories holding them.
+//!
+//! With the `toml` feature, agent definitions and inventories can also be read and written as TOML,
+//! with the same fields as the JSON files (TOML has no null: fields that are null in JSON are left out).
+//!
+//! ```toml
+//! [Buy]
+//! price0 = 1.05
+//! price1 = 1.1
+//! scale = 0.001
+//! exposure = 10000.0
+//!
+//! # or, with an optional field left out (None)
+//! [CL]
+//! direction = 1
+//! price = 1.08
+//! scale = 0.001
+//! size = 1000.0
+//! imax = 10.0
+//! ```
+
 use serde::{Deserialize, Serialize};
 
-use super::super::{Gear, GearRange};
-use super::account::OrderFill;
-use super::quote::Tick;
-use std::collections::HashMap;
+use super::super::{finite, Gear, MathError};
+use super::account::{OrderFill, Position};
+use super::dyninventory::TaggedAgent;
+use super::instrument::{HomeConversions, Instrument};
+use super::pairagent::PairAgent;
+use super::quote::{Bar, Tick};
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
+use std::sync::Arc;
 
+// serde is implemented below, to keep unknown variants of newer files as GAgent::Unknown
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(remote = "Self")]
+#[non_exhaustive]
 pub enum GAgent {
     OHLC {
         open: f64,
@@ -17,6 +45,16 @@ pub enum GAgent {
         exposure: f64,
         target: Option<f64>,
     },
+    // OHLC leaning into the move: short below, long above the zero price
+    OHLCMomentum {
+        open: f64,
+        high: f64,
+        low: f64,
+        close: f64,
+        scale: f64,
+        exposure: f64,
+        target: Option<f64>,
+    },
     // Coastline trader agent with parameters as defined in golang
     CL {
         direction: i64,
@@ -67,9 +105,93 @@ pub enum GAgent {
         scale: f64,
         target: f64,
     },
+    // Pair trade: long exposure_a of instrument_a against short exposure_b of instrument_b
+    // it does not build into a single GearHedger, see build_pair
+    PairSpread {
+        instrument_a: String,
+        exposure_a: f64,
+        instrument_b: String,
+        exposure_b: f64,
+        scale: f64,
+        target: f64,
+    },
+    // constant notional (units * price) between price0 and price1, long for a positive notional
+    FixedNotional {
+        notional: f64,
+        price0: f64,
+        price1: f64,
+        scale: f64,
+    },
+    // variant written by a newer version, kept as is to be written back unchanged
+    #[serde(skip)]
+    Unknown {
+        raw: serde_json::Value,
+    },
+}
+
+impl Serialize for GAgent {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            GAgent::Unknown { raw } => raw.serialize(serializer),
+            _ => GAgent::serialize(self, serializer),
+        }
+    }
+}
+
+// the tags of the GAgent variants this version reads, all but Unknown
+const GAGENT_VARIANTS: &[&str] = &[
+    "OHLC", "OHLCMomentum", "CL", "Symmetric", "Buy", "Sell", "JumpLong", "Coastline", "Segment", "PairSpread", "FixedNotional",
+];
+
+impl<'de> Deserialize<'de> for GAgent {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = serde_json::Value::deserialize(deserializer)?;
+        // only unknown variants are tolerated, a malformed known variant is still an error
+        let tag = raw.as_object().filter(|o| o.len() == 1).and_then(|o| o.keys().next());
+        if tag.is_some_and(|tag| !GAGENT_VARIANTS.contains(&tag.as_str())) {
+            return Ok(GAgent::Unknown { raw });
+        }
+        GAgent::deserialize(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "toml")]
+impl GAgent {
+    pub fn from_toml(s: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    pub fn to_toml(&self) -> String {
+        to_toml_string(self)
+    }
 }
 
 impl GAgent {
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, GAgent::Unknown { .. })
+    }
+
+    pub fn build_pair(&self) -> Option<PairAgent> {
+        match self {
+            GAgent::PairSpread {
+                instrument_a,
+                exposure_a,
+                instrument_b,
+                exposure_b,
+                scale,
+                target,
+            } => Some(PairAgent::new(
+                instrument_a.clone(),
+                *exposure_a,
+                instrument_b.clone(),
+                *exposure_b,
+                *scale,
+                *target,
+            )),
+            _ => None,
+        }
+    }
+
     pub fn build(&self) -> Option<GearHedger> {
         match self {
             GAgent::OHLC {
@@ -91,6 +213,25 @@ impl GAgent {
                         *low, exposure0, *high, exposuren, *scale, actualTarget,
             ))
             },
+            GAgent::OHLCMomentum {
+                open,
+                high,
+                low,
+                close,
+                scale,
+                exposure,
+                target,
+            } => {
+                // price to zero exposure, mirror of OHLC
+                let zerop = if open < close {open} else {close};
+                // same extremes as OHLC with flipped signs
+                let exposure0 = - exposure.min(exposure * (zerop - low) / (high - zerop));
+                let exposuren = exposure.min(exposure * (high - zerop) / (zerop - low));
+                let actual_target = target.unwrap_or(f64::MAX);
+                Some(GearHedger::segment(
+                        *low, exposure0, *high, exposuren, *scale, actual_target,
+            ))
+            },
             GAgent::CL {
                 direction: direction,
                 price: price,
@@ -169,13 +310,154 @@ impl GAgent {
             } => Some(GearHedger::segment(
                     *price0, *exposure0, *pricen, *exposuren, *scale, *target,
             )),
+            GAgent::FixedNotional {
+                notional,
+                price0,
+                price1,
+                scale,
+            } => Some(GearHedger::fixed_notional(
+                    *price0, *price1, *scale, *notional,
+            )),
             _ => None,
         }
     }
 }
+#[derive(Debug, PartialEq)]
+pub enum AgentBuildError {
+    // variant of a newer version, kept as raw json
+    Unknown(String),
+    // known variant building another agent type (e.g. PairSpread)
+    NotAGearHedger(String),
+}
+
+impl std::fmt::Display for AgentBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AgentBuildError::Unknown(raw) => write!(f, "unknown agent type: {}", raw),
+            AgentBuildError::NotAGearHedger(agent) => write!(f, "agent does not build a GearHedger: {}", agent),
+        }
+    }
+}
+
+impl Error for AgentBuildError {}
+
+impl TryFrom<GAgent> for GearHedger {
+    type Error = AgentBuildError;
+
+    fn try_from(agent: GAgent) -> Result<Self, Self::Error> {
+        agent.build().ok_or_else(|| {
+            let json = serde_json::to_string(&agent).unwrap_or_default();
+            if agent.is_unknown() {
+                AgentBuildError::Unknown(json)
+            } else {
+                AgentBuildError::NotAGearHedger(json)
+            }
+        })
+    }
+}
+
+impl From<GAgent> for Option<GearHedger> {
+    fn from(agent: GAgent) -> Self {
+        agent.build()
+    }
+}
+
+#[derive(Debug)]
+pub enum AgentLoadError {
+    // neither a GearHedger nor a GAgent
+    Json(serde_json::Error),
+    Build(AgentBuildError),
+}
+
+impl std::fmt::Display for AgentLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AgentLoadError::Json(e) => write!(f, "not an agent: {}", e),
+            AgentLoadError::Build(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for AgentLoadError {}
+
+// a GearHedger from its json, or from the json of a GAgent
+pub fn build_from_json_str(json: &str) -> Result<GearHedger, AgentLoadError> {
+    if let Ok(hedger) = serde_json::from_str::<GearHedger>(json) {
+        return Ok(hedger);
+    }
+    let agent = serde_json::from_str::<GAgent>(json).map_err(AgentLoadError::Json)?;
+    agent.try_into().map_err(AgentLoadError::Build)
+}
+
+// CL or Coastline configuration of the golang implementation, with the Go json field names:
+// {"Direction": 1, "Price": 1.058, "Scale": 0.001, "Size": 1000, "I0": 1, "Imax": 10, "Target": 1}
+// is a CL (I0 and Target are optional), Price0 instead of Price makes it a Coastline
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GolangAgent {
+    direction: i64,
+    price: Option<f64>,
+    price0: Option<f64>,
+    scale: f64,
+    size: f64,
+    i0: Option<f64>,
+    imax: f64,
+    target: Option<f64>,
+}
+
+impl GAgent {
+    pub fn from_golang_json(json: &str) -> Result<Self, serde_json::Error> {
+        let agent: GolangAgent = serde_json::from_str(json)?;
+        match (agent.price0, agent.price) {
+            (Some(price0), _) => Ok(GAgent::Coastline {
+                direction: agent.direction,
+                price0,
+                scale: agent.scale,
+                size: agent.size,
+                imax: agent.imax,
+            }),
+            (None, Some(price)) => Ok(GAgent::CL {
+                direction: agent.direction,
+                price,
+                scale: agent.scale,
+                size: agent.size,
+                i0: agent.i0,
+                imax: agent.imax,
+                target: agent.target,
+            }),
+            (None, None) => Err(serde::de::Error::missing_field("Price")),
+        }
+    }
+}
+
+// inventory of the agents of a golang strategy file (a json array of CL / Coastline configurations),
+// keyed by their type and position in the file, e.g. "CL-0", "Coastline-1"
+pub fn import_golang(json: &str) -> Result<AgentInventory<GearHedger>, AgentLoadError> {
+    let configs: Vec<serde_json::Value> = serde_json::from_str(json).map_err(AgentLoadError::Json)?;
+    let mut inventory = AgentInventory::new();
+    for (i, config) in configs.iter().enumerate() {
+        let agent = GAgent::from_golang_json(&config.to_string()).map_err(AgentLoadError::Json)?;
+        let kind = if matches!(agent, GAgent::Coastline { .. }) { "Coastline" } else { "CL" };
+        let hedger = agent.try_into().map_err(AgentLoadError::Build)?;
+        inventory.agents.insert(format!("{}-{}", kind, i), hedger);
+    }
+    Ok(inventory)
+}
+
+// why an agent is flattened, kept for the audit of the closing fill
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum CloseReason {
+    TargetReached,
+    StopLoss,
+    TrailingStop,
+    Manual,
+    DayEnd,
+}
+
 pub trait Agent {
 
-    fn close(&mut self, tick :&Tick) -> i64;
+    // prepare to flatten at the closing side of tick, returns the exposure delta to trade
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64;
     // active status
     fn is_active(&self) -> bool;
     fn deactivate(&mut self);
@@ -200,11 +482,25 @@ pub trait Agent {
     // compute the new state after trading occured with a target exposure and Order fill at a price
     fn update_on_fill(&mut self, order_fill: &OrderFill);
 
-    // set the next exposure and execute the order fill at the same time and price
+    // set the next exposure at the fill price, as on a tick at that price, then book the fill
     fn next_exposure_and_fill(&mut self, order_fill: &OrderFill);
 
     // current exposure of the agent
     fn exposure(&self) -> i64;
+
+    // serializable copy of the agent, None for agents that cannot be stored in a DynInventory file
+    fn tagged(&self) -> Option<TaggedAgent> {
+        None
+    }
+}
+
+// smallest gap between two trades of an agent, so a zero scale cannot trade again at the same price
+pub const MIN_PRICE_INCREMENT: f64 = 0.00001;
+
+// bad data: a tick with ask below bid could trigger both a buy and a sale, it is not traded
+// (bid == ask is kept, backtests build such ticks from single price bars)
+fn crossed_market(tick: &Tick) -> bool {
+    tick.ask < tick.bid
 }
 
 /**
@@ -223,6 +519,7 @@ pub struct GearHedger {
 
     // activation status and PL target
     pub active: bool,
+    /// Realized profit target, in the units of agentPL.cum_profit: absolute price difference times units
     pub target: f64,
 
     // next trades on the buy and sell sides
@@ -236,23 +533,233 @@ pub struct GearHedger {
     //these fields are used when next exposure is computed before requesting an actual trade on the market
     pub tentative_price: f64,
     pub tentative_exposure: i64,
+
+    // traded instrument, empty for agents of files written before tagging
+    #[serde(default)]
+    pub instrument: String,
+
+    // long only agents never sell and short only agents never buy, but to close (e.g. on the target):
+    // a buyer with long_only only accumulates on the way down
+    #[serde(default)]
+    pub long_only: bool,
+    #[serde(default)]
+    pub short_only: bool,
+
+    // if set, the first trades are capped to max_exposure / initial_ramp_ticks units each,
+    // and the agent keeps trading every tick until it holds the gear exposure (ramp_trades is then
+    // set to initial_ramp_ticks) or initial_ramp_ticks trades are done
+    #[serde(default)]
+    pub initial_ramp_ticks: Option<u32>,
+    #[serde(default)]
+    pub ramp_trades: u32,
+
+    // action when the PL target is reached, the closure is not serialized:
+    // target_action_kind is stored and used when there is no closure (e.g. after loading a file)
+    #[serde(skip)]
+    pub target_action_fn: Option<TargetActionFn>,
+    #[serde(default)]
+    pub target_action_kind: TargetActionKind,
+
+    // PL before the last tick was traded, for the PL attribution between two ticks
+    #[serde(skip)]
+    pub pl_checkpoint: Option<AgentPL>,
+
+    // circuit breaker: trades in a row realizing a loss, the agent is deactivated at the max
+    #[serde(default)]
+    pub consecutive_losses: u32,
+    #[serde(default)]
+    pub max_consecutive_losses: Option<u32>,
+
+    // whether a tick exactly at the next buy or sell price trades
+    #[serde(default)]
+    pub level_policy: LevelPolicy,
+
+    // half width of the price band around the zero exposure price where the target exposure is zero
+    #[serde(default)]
+    pub deadband: f64,
+
+    // if set, the PL target follows the volatility of the recent ticks instead of target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vol_target: Option<VolTarget>,
+
+    // no trade on a tick with a wider spread (news, rollover), the position is only marked to market
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_spread: Option<f64>,
+
+    // why the agent was last closed, until it trades again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_close_reason: Option<CloseReason>,
+
+    // crossed market ticks ignored since the agent was built or loaded, for the caller to report
+    #[serde(skip)]
+    pub crossed_ticks: u64,
+}
+
+// close target of k * volatility * max_exposure, with the volatility the standard deviation
+// of the returns between the last window + 1 tick prices
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VolTarget {
+    pub k: f64,
+    pub window: usize,
+    #[serde(default)]
+    pub prices: VecDeque<f64>,
+}
+
+impl VolTarget {
+    pub fn new(k: f64, window: usize) -> Self {
+        Self { k, window, prices: VecDeque::with_capacity(window + 1) }
+    }
+
+    pub fn push(&mut self, price: f64) {
+        self.prices.push_back(price);
+        while self.prices.len() > self.window + 1 {
+            self.prices.pop_front();
+        }
+    }
+
+    // None until two returns are known
+    pub fn volatility(&self) -> Option<f64> {
+        let returns = self.prices.iter().zip(self.prices.iter().skip(1))
+            .filter(|(p0, _)| **p0 != 0.0)
+            .map(|(p0, p1)| p1 / p0 - 1.0);
+        sample_std_dev(returns)
+    }
+}
+
+// standard deviation of a sample, None with less than two values
+fn sample_std_dev<I: Iterator<Item = f64> + Clone>(xs: I) -> Option<f64> {
+    let n = xs.clone().count();
+    if n < 2 {
+        return None;
+    }
+    let mean = xs.clone().sum::<f64>() / n as f64;
+    let var = xs.map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+    Some(var.sqrt())
+}
+
+// boundary of the next buy / sell prices, the same for both sides
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub enum LevelPolicy {
+    // trade when the price reaches the level: bid >= nextSellPrice, ask <= nextBuyPrice
+    #[default]
+    TradeOnTouch,
+    // trade when the price goes through the level: bid > nextSellPrice, ask < nextBuyPrice
+    TradeOnBreak,
+}
+
+// what merge_flat does when the combined position of the merged agents exceeds the merged max_exposure
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MergeOverflow {
+    // scale the merged gear up so its max_exposure covers the combined position
+    #[default]
+    Scale,
+    // refuse the merge with InventoryError::ExposureOverflow
+    Error,
+}
+
+// stable reporting view of a GearHedger
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct GearSummary {
+    // the agent has no name, set by the caller from the inventory key
+    pub name: Option<String>,
+    pub max_exposure: f64,
+    pub current_exposure: i64,
+    pub realized_pl: f64,
+    pub price_range: (f64, f64),
+    pub scale: f64,
+    pub active: bool,
+    pub target: f64,
+    pub target_reached_pct: f64,
+}
+
+// closure called by GearHedger::target_action, returning the tentative exposure
+#[derive(Clone)]
+pub struct TargetActionFn(pub Arc<dyn Fn(&mut GearHedger) -> i64 + Send + Sync>);
+
+impl std::fmt::Debug for TargetActionFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "TargetActionFn")
+    }
+}
+
+// serializable target actions
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub enum TargetActionKind {
+    // close the position and deactivate the agent
+    #[default]
+    Deactivate,
+    // close the position and keep trading with the target raised by step above the current PL
+    Rearm { step: f64 },
+}
+
+impl TargetActionKind {
+    pub fn apply(&self, agent: &mut GearHedger) -> i64 {
+        match self {
+            TargetActionKind::Deactivate => {
+                agent.tentative_exposure = 0;
+                agent.last_close_reason = Some(CloseReason::TargetReached);
+                agent.deactivate();
+                0
+            }
+            TargetActionKind::Rearm { step } => {
+                agent.tentative_exposure = 0;
+                agent.last_close_reason = Some(CloseReason::TargetReached);
+                agent.target = agent.agentPL.pl_at_price(agent.tentative_price) + step;
+                0
+            }
+        }
+    }
+
+    pub fn action(self) -> TargetActionFn {
+        TargetActionFn(Arc::new(move |agent: &mut GearHedger| self.apply(agent)))
+    }
 }
 
 impl GearHedger {
 
+    // tag the agent with its instrument
+    pub fn with_instrument(mut self, instrument: &str) -> Self {
+        self.instrument = instrument.to_string();
+        self
+    }
+
     /** method used to merge 2 GearHedger
+    agents on different instruments cannot be merged, an untagged agent takes the other one's instrument
+    a combined position beyond the merged max_exposure scales the merged gear up, see merge_flat_with
     */
-    pub fn merge_flat(& self, other: &GearHedger) -> Self {
+    pub fn merge_flat(& self, other: &GearHedger) -> Result<Self, InventoryError> {
+        self.merge_flat_with(other, MergeOverflow::Scale)
+    }
+
+    // merge_flat, with the handling of a combined position the merged gear cannot hold
+    pub fn merge_flat_with(&self, other: &GearHedger, on_overflow: MergeOverflow) -> Result<Self, InventoryError> {
+        if !self.instrument.is_empty() && !other.instrument.is_empty() && self.instrument != other.instrument {
+            return Err(InventoryError::InstrumentMismatch(self.instrument.clone(), other.instrument.clone()));
+        }
         // compute the price range
         let p_0 = self.gear_f.p_0.min(other.gear_f.p_0);
         let p_n = self.gear_f.p_n.max(other.gear_f.p_n);
 
         // compute the exposure range, then resulting gear and max_exposure
-        let low_gear = self.gear_f.g_0 * self.max_exposure + other.gear_f.g_0 * other.max_exposure;
-        let high_gear = self.gear_f.g_n * self.max_exposure + other.gear_f.g_n * other.max_exposure;
+        let mut low_gear = self.gear_f.g_0 * self.max_exposure + other.gear_f.g_0 * other.max_exposure;
+        let mut high_gear = self.gear_f.g_n * self.max_exposure + other.gear_f.g_n * other.max_exposure;
 
         // highest of the absolute gears
-        let max_exposure = low_gear.abs().max(high_gear.abs());
+        let mut max_exposure = low_gear.abs().max(high_gear.abs());
+
+        // gears cancelling out at the extremes can leave the combined position out of the merged range
+        let position = self.agentPL.exposure + other.agentPL.exposure;
+        if position.abs() as f64 > max_exposure {
+            match on_overflow {
+                MergeOverflow::Error => return Err(InventoryError::ExposureOverflow(position, max_exposure)),
+                MergeOverflow::Scale => {
+                    let factor = position.abs() as f64 / max_exposure;
+                    low_gear *= factor;
+                    high_gear *= factor;
+                    max_exposure = position.abs() as f64;
+                }
+            }
+        }
         let g_0 = low_gear / max_exposure;
         let g_n = high_gear / max_exposure;
 
@@ -268,133 +775,673 @@ impl GearHedger {
         // how much has been realized: buy-sell net * price difference...
         // if the exposures are different signs, then we are realizing some pl
         let mut agent: GearHedger = GAgent::Segment { price0: p_0, exposure0: low_gear, pricen: p_n, exposuren: high_gear, scale: scale, target: target }.build().unwrap();
-        agent.next_exposure_and_fill(&OrderFill { price: self.agentPL.price_average, units: self.agentPL.exposure });
-        agent.next_exposure_and_fill(&OrderFill { price: other.agentPL.price_average, units: other.agentPL.exposure });
+        agent.force_fill(&OrderFill { price: self.agentPL.price_average, units: self.agentPL.exposure });
+        agent.force_fill(&OrderFill { price: other.agentPL.price_average, units: other.agentPL.exposure });
 
         agent.active = true;
+        agent.instrument = if self.instrument.is_empty() { other.instrument.clone() } else { self.instrument.clone() };
 
-        return agent;
+        Ok(agent)
     }
 
-    pub fn buyer(
-        price0: f64,
-        price1: f64,
-        scaleUp: f64,
-        scaleDown: f64,
-        max_exposure: f64,
-    ) -> Self {
-        Self {
-            max_exposure: max_exposure,
-            gear_f: Gear::positive(price0, price1),
-            scaleUp: scaleUp,
-            scaleDown: scaleDown,
+    // scale the size of the agent: exposures, target and PL, the average price is unchanged
+    pub fn rescale(&mut self, factor: f64) {
+        self.max_exposure *= factor;
+        // an unlimited target stays unlimited, and serializable
+        if self.target != f64::MAX {
+            self.target = (self.target * factor).min(f64::MAX);
+        }
+        self.agentPL.exposure = (self.agentPL.exposure as f64 * factor).round() as i64;
+        self.agentPL.cum_profit *= factor;
+        self.agentPL.unrealized_pl *= factor;
+        self.tentative_exposure = (self.tentative_exposure as f64 * factor).round() as i64;
+    }
 
-            active: true,
-            target: f64::MAX,
+    // rescale the agent so a one pip move at max exposure is worth risk_per_pip in account currency,
+    // quote_rate being the account value of one unit of the quote currency
+    pub fn size_for_pip_risk(&mut self, instrument: &Instrument, risk_per_pip: f64, quote_rate: f64) {
+        let units = instrument.units_for_pip_risk(risk_per_pip, quote_rate);
+        if self.max_exposure > 0.0 && units > 0 {
+            self.rescale(units as f64 / self.max_exposure);
+        }
+    }
 
-            lastTradePrice: price1,
-            nextBuyPrice: price1,
-            nextSellPrice: price1,
+    // the most specific GAgent building the same gear, None for gears no variant can describe
+    pub fn as_gear_agent(&self) -> Option<GAgent> {
+        if self.scaleUp != self.scaleDown {
+            return None;
+        }
+        let gear = &self.gear_f;
+        let scale = self.scaleUp;
+        let exposure = self.max_exposure;
+        let unlimited = self.target == f64::MAX;
 
-            agentPL: AgentPL {
-                exposure: 0,
-                price_average: 0.0,
-                cum_profit: 0.0,
-                unrealized_pl: 0.0,
-            },
-            tentative_price: price1,
-            tentative_exposure: 0,
+        if gear.g_i.is_empty() {
+            if gear.p_0 == gear.p_n && gear.g_0 == 1.0 && gear.g_n == 0.0 && unlimited {
+                return Some(GAgent::JumpLong { price0: gear.p_0, scale, exposure });
+            }
+            return None;
+        }
+        if gear.g_i.len() != 1 {
+            return None;
+        }
+        let r = &gear.g_i[0];
+        if r.p_start != gear.p_0 || r.p_end != gear.p_n || r.g_start != gear.g_0 || r.g_end != gear.g_n {
+            return None;
+        }
+        let (price0, pricen) = (gear.p_0, gear.p_n);
+
+        // buyer, seller and coastline share the 1 -> 0 and 0 -> -1 profiles
+        let positive = gear.g_0 == 1.0 && gear.g_n == 0.0;
+        let negative = gear.g_0 == 0.0 && gear.g_n == -1.0;
+        if (positive || negative) && unlimited {
+            return Some(if positive {
+                GAgent::Buy { price0, price1: pricen, scale, exposure }
+            } else {
+                GAgent::Sell { price0, price1: pricen, scale, exposure }
+            });
+        }
+        if positive || negative {
+            let imax = (pricen - price0) / scale - 1.0;
+            let size = exposure / imax;
+            if imax > 0.0 && (self.target - scale * size).abs() <= 1e-9 * self.target.abs().max(1.0) {
+                let (direction, price0) = if positive { (1, pricen - scale) } else { (-1, price0 + scale) };
+                return Some(GAgent::Coastline { direction, price0, scale, size, imax });
+            }
         }
+        if gear.g_0 > 0.0 && gear.g_0 == -gear.g_n {
+            return Some(GAgent::Symmetric {
+                pmid: (price0 + pricen) / 2.0,
+                span: (pricen - price0) / 2.0,
+                scale,
+                exposure: exposure * gear.g_0,
+                target: self.target,
+            });
+        }
+        Some(GAgent::Segment {
+            price0,
+            exposure0: gear.g_0 * exposure,
+            pricen,
+            exposuren: gear.g_n * exposure,
+            scale,
+            target: self.target,
+        })
     }
 
-    pub fn seller(
-        price0: f64,
-        price1: f64,
-        scaleUp: f64,
-        scaleDown: f64,
-        max_exposure: f64,
-    ) -> Self {
-        Self {
-            max_exposure: max_exposure,
-            gear_f: Gear::negative(price0, price1),
-            scaleUp: scaleUp,
-            scaleDown: scaleDown,
+    // gear exposure the initial ramp trades towards, the current exposure on overflow
+    fn ramp_target(&self, price: f64) -> i64 {
+        self.checked_exposure_at_price(price).map_or(self.agentPL.exposure, |e| self.allowed_exposure(e))
+    }
 
-            active: true,
-            target: f64::MAX,
+    // during the initial ramp, trade towards the gear exposure on every tick by steps of max_exposure / ramp,
+    // the ramp ends when there is nothing left to trade and the grid takes over
+    fn ramp_exposure(&mut self, tick: &Tick, ramp: u32) -> i64 {
+        let step = (self.max_exposure / ramp as f64).ceil() as i64;
+        let exposure = self.agentPL.exposure;
+        let at_ask = self.ramp_target(tick.ask);
+        let at_bid = self.ramp_target(tick.bid);
+        if at_ask > exposure && !self.short_only {
+            self.tentative_price = tick.ask;
+            self.tentative_exposure = at_ask.min(exposure + step);
+        } else if at_bid < exposure && !self.long_only {
+            self.tentative_price = tick.bid;
+            self.tentative_exposure = at_bid.max(exposure - step);
+        } else {
+            self.ramp_trades = ramp;
+            return self.target_exposure(tick);
+        }
+        self.tentative_exposure
+    }
 
-            lastTradePrice: price0,
-            nextBuyPrice: price0,
-            nextSellPrice: price0,
+    // set a serializable target action, restored from target_action_kind after loading
+    pub fn with_target_action_fn_from_type(mut self, action_type: TargetActionKind) -> Self {
+        self.target_action_kind = action_type;
+        self.target_action_fn = Some(action_type.action());
+        self
+    }
 
-            agentPL: AgentPL {
-                exposure: 0,
-                price_average: 0.0,
-                cum_profit: 0.0,
-                unrealized_pl: 0.0,
-            },
-            tentative_price: price0,
-            tentative_exposure: 0,
+    // set a custom target action, lost when the agent is serialized
+    pub fn with_target_action_fn(mut self, f: impl Fn(&mut GearHedger) -> i64 + Send + Sync + 'static) -> Self {
+        self.target_action_fn = Some(TargetActionFn(Arc::new(f)));
+        self
+    }
+
+    pub fn summarize(&self) -> GearSummary {
+        GearSummary {
+            name: None,
+            max_exposure: self.max_exposure,
+            current_exposure: self.agentPL.exposure,
+            realized_pl: self.agentPL.cum_profit,
+            price_range: (self.gear_f.p_0, self.gear_f.p_n),
+            scale: self.scaleUp,
+            active: self.active,
+            target: self.effective_target(),
+            target_reached_pct: if self.effective_target() > 0.0 { self.agentPL.cum_profit / self.effective_target() * 100.0 } else { 0.0 },
         }
     }
 
-    pub fn constant(exposure: f64) -> Self {
-        Self {
-            max_exposure: exposure.abs(),
-            gear_f: Gear::constant(exposure as i64),
-            scaleUp: 1.0,
-            scaleDown: 1.0,
+    // Mermaid flowchart of the agent: the gear as price segments from low to high prices,
+    // the next trade triggers and the state, to be put in a ```mermaid block
+    pub fn to_mermaid_diagram(&self) -> String {
+        let g = &self.gear_f;
+        let exposure = |gear: f64| (gear * self.max_exposure) as i64;
+        let mut segments = vec![format!("below[\"price < {:.5}: {}\"]", g.p_0, exposure(g.g_0))];
+        segments.extend(g.g_i.iter().enumerate().map(|(i, r)| {
+            format!("range{}[\"{:.5} to {:.5}: {} to {}\"]", i, r.p_start, r.p_end, exposure(r.g_start), exposure(r.g_end))
+        }));
+        segments.push(format!("above[\"price >= {:.5}: {}\"]", g.p_n, exposure(g.g_n)));
 
-            active: true,
-            target: f64::MAX,
+        let target = if self.target == f64::MAX { "none".to_string() } else { format!("{:.2}", self.target) };
+        let status = if !self.active {
+            "inactive"
+        } else if self.agentPL.cum_profit > self.target {
+            "target reached"
+        } else {
+            "active"
+        };
+        [
+            "graph LR".to_string(),
+            format!("    {}", segments.join(" --> ")),
+            format!(
+                "    state[\"exposure {} at {:.5}<br/>PL {:.2} / target {}<br/>{}\"]",
+                self.agentPL.exposure, self.lastTradePrice, self.agentPL.cum_profit, target, status
+            ),
+            format!("    sell([\"sell at bid >= {:.5}\"]) -.-> state", self.nextSellPrice),
+            format!("    buy([\"buy at ask <= {:.5}\"]) -.-> state", self.nextBuyPrice),
+        ]
+        .join("\n")
+    }
 
-            lastTradePrice: 1.0,
-            nextBuyPrice: 1.0,
-            nextSellPrice: 1.0,
+    // move a symmetric gear to be centered on price, keeping its span
+    pub fn shift_mid_to_price(&mut self, price: f64) {
+        let span = (self.gear_f.p_n - self.gear_f.p_0)/2.0;
+        self.gear_f =  GAgent::Symmetric{
+            pmid: price,
+            span,
+            scale: self.scaleUp,
+            exposure: self.max_exposure,
+            target: self.target}.build().unwrap().gear_f;
+    }
 
-            agentPL: AgentPL {
-                exposure: 0,
-                price_average: 0.0,
-                cum_profit: 0.0,
-                unrealized_pl: 0.0,
-            },
-            tentative_price: 1.0,
-            tentative_exposure: 0,
-        }
+    // next levels re-centered on tick, e.g. after a restart on a market far from the last fill:
+    // the agent trades at once if its gear wants another exposure here, otherwise one scale away
+    pub fn warm_restart(&mut self, tick: &Tick) {
+        let buy_exposure = self.gear_exposure(tick.ask);
+        let sell_exposure = self.gear_exposure(tick.bid);
+        // on break, the level is set just inside the tick for it to trade
+        let inside = match self.level_policy {
+            LevelPolicy::TradeOnTouch => 0.0,
+            LevelPolicy::TradeOnBreak => MIN_PRICE_INCREMENT,
+        };
+        self.nextSellPrice = if sell_exposure < self.agentPL.exposure {
+            tick.bid - inside
+        } else {
+            tick.bid + self.scaleUp.max(MIN_PRICE_INCREMENT)
+        };
+        self.nextBuyPrice = if buy_exposure > self.agentPL.exposure {
+            tick.ask + inside
+        } else {
+            tick.ask - self.scaleDown.max(MIN_PRICE_INCREMENT)
+        };
     }
 
-    pub fn symmetric(
-        price0: f64,
-        price1: f64,
-        scaleUp: f64,
-        scaleDown: f64,
-        max_exposure: f64,
-        target: f64,
-    ) -> Self {
-        let zero_price = (price0 + price1) / 2.0;
-        Self {
-            max_exposure: max_exposure,
-            gear_f: Gear::symmetric(price0, price1),
-            scaleUp: scaleUp,
-            scaleDown: scaleDown,
+    // last trade at price, the next levels one scale away from it
+    fn anchor_levels(&mut self, price: f64) {
+        self.lastTradePrice = price;
+        self.nextSellPrice = price + self.scaleUp.max(MIN_PRICE_INCREMENT);
+        self.nextBuyPrice = price - self.scaleDown.max(MIN_PRICE_INCREMENT);
+        self.clamp_levels();
+    }
 
-            active: true,
-            target: target,
+    // out of the gear domain the exposure is flat, the levels stay one scale away from it
+    fn clamp_levels(&mut self) {
+        self.nextSellPrice = self.nextSellPrice.min(self.gear_f.p_n + self.scaleUp.max(MIN_PRICE_INCREMENT));
+        self.nextBuyPrice = self.nextBuyPrice.max(self.gear_f.p_0 - self.scaleDown.max(MIN_PRICE_INCREMENT));
+    }
 
-            lastTradePrice: zero_price,
-            nextBuyPrice: zero_price,
-            nextSellPrice: zero_price,
+    // a trade from the current exposure to exposure is in a direction the agent may trade
+    fn direction_allowed(&self, exposure: i64) -> bool {
+        !(self.long_only && exposure < self.agentPL.exposure || self.short_only && exposure > self.agentPL.exposure)
+    }
+
+    // exposure restricted to the long only / short only side of the agent
+    fn allowed_exposure(&self, exposure: i64) -> i64 {
+        if self.long_only {
+            exposure.max(0)
+        } else if self.short_only {
+            exposure.min(0)
+        } else {
+            exposure
+        }
+    }
+
+    // the neutral price, where the gear function crosses zero exposure
+    pub fn price_at_zero_exposure(&self) -> Option<f64> {
+        self.gear_f.zero_price()
+    }
+
+    // same gear and parameters, flat and active with the grid at the neutral price (the last trade price
+    // if the gear never crosses zero), e.g. a paper copy of a live agent trading forward from now
+    pub fn fork_flat(&self) -> GearHedger {
+        let neutral = self.price_at_zero_exposure().unwrap_or(self.lastTradePrice);
+        let mut fork = self.clone();
+        fork.active = true;
+        fork.lastTradePrice = neutral;
+        fork.nextBuyPrice = neutral;
+        fork.nextSellPrice = neutral;
+        fork.agentPL = AgentPL::flat();
+        fork.tentative_price = neutral;
+        fork.tentative_exposure = 0;
+        fork.ramp_trades = 0;
+        fork.pl_checkpoint = None;
+        fork.consecutive_losses = 0;
+        fork.last_close_reason = None;
+        fork.crossed_ticks = 0;
+        if let Some(vol_target) = fork.vol_target.as_mut() {
+            vol_target.prices.clear();
+        }
+        fork
+    }
+
+    // book a fill of fill.units at fill.price whatever the gear says, e.g. the position of a merged agent:
+    // the gear is bypassed, call next_exposure first for a fill sized by the gear
+    pub fn force_fill(&mut self, fill: &OrderFill) {
+        self.tentative_price = fill.price;
+        self.tentative_exposure += fill.units;
+        self.update_on_fill(fill);
+    }
+
+    // replay historical fills in order, e.g. imported trades, as if they had been traded live
+    pub fn apply_fills(&mut self, fills: &[OrderFill]) {
+        for fill in fills {
+            self.tentative_exposure = self.agentPL.exposure;
+            self.force_fill(fill);
+        }
+    }
+
+    // exposure held after each bar, trading at the mid close, on a copy of the agent
+    pub fn exposure_series(&self, bars: &[Bar]) -> Vec<i64> {
+        let mut agent = self.clone();
+        bars.iter()
+            .map(|bar| {
+                let price = bar.price();
+                let tick = Tick { time: bar.time() as i64, bid: price, ask: price };
+                if agent.is_active() {
+                    let target = agent.next_exposure(&tick);
+                    if target != agent.exposure() {
+                        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: target - agent.exposure() });
+                    }
+                }
+                agent.exposure()
+            })
+            .collect()
+    }
+
+    // close and deactivate, so the agent does not reopen a position on the next tick;
+    // the closing trade is booked by update_on_fill, set active back to resume trading
+    pub fn force_close(&mut self, tick: &Tick, reason: CloseReason) -> i64 {
+        let e = self.close(tick, reason);
+        self.deactivate();
+        e
+    }
+
+    pub fn with_level_policy(mut self, level_policy: LevelPolicy) -> Self {
+        self.level_policy = level_policy;
+        self
+    }
+
+    pub fn with_deadband(mut self, deadband: f64) -> Self {
+        self.deadband = deadband;
+        self
+    }
+
+    pub fn with_max_spread(mut self, max_spread: Option<f64>) -> Self {
+        self.max_spread = max_spread;
+        self
+    }
+
+    // close at k times the volatility of the last window tick returns times max_exposure
+    pub fn with_volatility_target(mut self, k: f64, window: usize) -> Self {
+        self.vol_target = Some(VolTarget::new(k, window));
+        self
+    }
+
+    // PL to reach before the target action, target until the volatility is known
+    pub fn effective_target(&self) -> f64 {
+        self.vol_target
+            .as_ref()
+            .and_then(|v| v.volatility().map(|vol| v.k * vol * self.max_exposure))
+            .unwrap_or(self.target)
+    }
+
+    // units of the gear at price, None when they do not fit in an i64 (a huge max_exposure):
+    // a plain cast would saturate at i64::MAX
+    pub fn checked_exposure_at_price(&self, price: f64) -> Option<i64> {
+        let exposure = self.gear_f.g(price) * self.max_exposure;
+        // i64::MAX as f64 rounds up to 2^63, which is out of range
+        if exposure.is_finite() && exposure >= i64::MIN as f64 && exposure < i64::MAX as f64 {
+            Some(exposure as i64)
+        } else {
+            None
+        }
+    }
+
+    // exposure of the gear at price, zero in the deadband, None on overflow
+    fn checked_gear_exposure(&self, price: f64) -> Option<i64> {
+        if self.deadband > 0.0 && self.gear_f.zero_price().is_some_and(|p| (price - p).abs() < self.deadband) {
+            return Some(0);
+        }
+        self.checked_exposure_at_price(price).map(|e| self.allowed_exposure(e))
+    }
+
+    // exposure of the gear at price, the current exposure on overflow
+    fn gear_exposure(&self, price: f64) -> i64 {
+        self.checked_gear_exposure(price).unwrap_or(self.agentPL.exposure)
+    }
+
+    fn sell_triggered(&self, bid: f64) -> bool {
+        match self.level_policy {
+            LevelPolicy::TradeOnTouch => bid >= self.nextSellPrice,
+            LevelPolicy::TradeOnBreak => bid > self.nextSellPrice,
+        }
+    }
+
+    fn buy_triggered(&self, ask: f64) -> bool {
+        match self.level_policy {
+            LevelPolicy::TradeOnTouch => ask <= self.nextBuyPrice,
+            LevelPolicy::TradeOnBreak => ask < self.nextBuyPrice,
+        }
+    }
+
+    pub fn with_max_consecutive_losses(mut self, n: u32) -> Self {
+        self.max_consecutive_losses = Some(n);
+        self
+    }
+
+    // units of exposure per unit of price at x, negative for a gear selling the rises
+    pub fn exposure_sensitivity(&self, x: f64) -> f64 {
+        self.gear_f.slope(x) * self.max_exposure
+    }
+
+    // gear value at the price of the last trade
+    pub fn gear_at_last_trade(&self) -> f64 {
+        self.gear_f.g(self.lastTradePrice)
+    }
+
+    // gear value at the price of the pending trade
+    pub fn target_gear(&self) -> f64 {
+        self.gear_f.g(self.tentative_price)
+    }
+
+    // best PL of the gear exposure on a move between an extreme of the gear domain and the price
+    // where the gear is zero (p_n if it never is), up through the long side or down through the short one
+    pub fn max_theoretical_gain(&self) -> f64 {
+        let (p_0, p_n) = (self.gear_f.p_0, self.gear_f.p_n);
+        let zero = self.gear_f.zero_price().map_or(p_n, |p| p.clamp(p_0, p_n));
+        let below = self.gear_f.integrate(p_0, zero).abs();
+        let above = self.gear_f.integrate(zero, p_n).abs();
+        below.max(above) * self.max_exposure
+    }
+
+    // the same move the other way round, as a negative PL
+    pub fn max_theoretical_loss(&self) -> f64 {
+        -self.max_theoretical_gain()
+    }
+
+    // PL if the price went from the last trade to x, trading continuously along the gear
+    pub fn pl_at_price(&self, x: f64) -> f64 {
+        let now = if self.agentPL.is_flat() {
+            self.agentPL.cum_profit
+        } else {
+            self.agentPL.pl_at_price(self.lastTradePrice)
+        };
+        now + self.gear_f.integrate(self.lastTradePrice, x) * self.max_exposure
+    }
+
+    // price of the gear domain with the best pl_at_price: the PL grows while the gear has the sign
+    // of the move, so the best price is a limit of the domain or a point where the gear crosses zero
+    pub fn best_price(&self) -> f64 {
+        let g = &self.gear_f;
+        let mut prices = vec![g.p_0, g.p_n];
+        for r in g.g_i.iter() {
+            prices.extend([r.p_start, r.p_end]);
+            if r.g_start.signum() != r.g_end.signum() && r.g_end != r.g_start {
+                prices.push(r.p_start - r.g_start * (r.p_end - r.p_start) / (r.g_end - r.g_start));
+            }
+        }
+        prices
+            .into_iter()
+            .map(|p| (p, self.pl_at_price(p)))
+            .fold((self.lastTradePrice, f64::MIN), |best, (p, pl)| if pl > best.1 { (p, pl) } else { best })
+            .0
+    }
+
+    // PL given up at price compared to the best price of the gear domain, 0 at the best price
+    pub fn drawdown_at_price(&self, price: f64) -> f64 {
+        (self.pl_at_price(self.best_price()) - self.pl_at_price(price)).max(0.0)
+    }
+
+    // expected net displacement in grid steps over a year, σ/h·√(2/π), of a random walk of annual
+    // volatility annual_vol in the price units of the scales. It is a lower bound of the trades:
+    // the crossings of the grid grow like (σ/h)² as the walk goes back and forth within the year
+    pub fn steady_state_turnover(&self, annual_vol: f64) -> f64 {
+        let scale = (self.scaleUp + self.scaleDown) / 2.0;
+        if scale <= 0.0 {
+            return 0.0;
+        }
+        annual_vol / scale * (2.0 / std::f64::consts::PI).sqrt()
+    }
+
+    pub fn buyer(
+        price0: f64,
+        price1: f64,
+        scaleUp: f64,
+        scaleDown: f64,
+        max_exposure: f64,
+    ) -> Self {
+        Self {
+            max_exposure: max_exposure,
+            gear_f: Gear::positive(price0, price1),
+            scaleUp: scaleUp,
+            scaleDown: scaleDown,
+
+            active: true,
+            target: f64::MAX,
+
+            lastTradePrice: price1,
+            nextBuyPrice: price1,
+            nextSellPrice: price1,
+
+            agentPL: AgentPL::flat(),
+            tentative_price: price1,
+            tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
+        }
+    }
+
+    pub fn seller(
+        price0: f64,
+        price1: f64,
+        scaleUp: f64,
+        scaleDown: f64,
+        max_exposure: f64,
+    ) -> Self {
+        Self {
+            max_exposure: max_exposure,
+            gear_f: Gear::negative(price0, price1),
+            scaleUp: scaleUp,
+            scaleDown: scaleDown,
+
+            active: true,
+            target: f64::MAX,
+
+            lastTradePrice: price0,
+            nextBuyPrice: price0,
+            nextSellPrice: price0,
+
+            agentPL: AgentPL::flat(),
+            tentative_price: price0,
+            tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
+        }
+    }
+
+    pub fn constant(exposure: f64) -> Self {
+        Self {
+            max_exposure: exposure.abs(),
+            gear_f: Gear::constant(exposure as i64),
+            scaleUp: 1.0,
+            scaleDown: 1.0,
 
+            active: true,
+            target: f64::MAX,
+
+            lastTradePrice: 1.0,
+            nextBuyPrice: 1.0,
+            nextSellPrice: 1.0,
+
+            agentPL: AgentPL::flat(),
+            tentative_price: 1.0,
+            tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
+        }
+    }
+
+    // agent taking over a position of the broker: max_exposure is set so the gear at the
+    // position price asks for the position units, and the next levels are scale away from it.
+    // None for a position without a (positive) price, e.g. a flat one: there is no average to book
+    pub fn from_position(position: &Position, gear: Gear, scale: f64, target: f64) -> Option<Self> {
+        let price = position.price.filter(|p| p.is_finite() && *p > 0.0)?;
+        let g = gear.g(price);
+        let max_exposure = if g != 0.0 && g.signum() == (position.units as f64).signum() {
+            position.units as f64 / g
+        } else {
+            position.units.abs() as f64
+        };
+        Some(Self {
+            max_exposure,
+            gear_f: gear,
+            scaleUp: scale,
+            scaleDown: scale,
+            target,
+            lastTradePrice: price,
+            nextBuyPrice: price - scale,
+            nextSellPrice: price + scale,
             agentPL: AgentPL {
-                exposure: 0,
-                price_average: 0.0,
+                exposure: position.units,
+                price_average: price,
                 cum_profit: 0.0,
-                unrealized_pl: 0.0,
+                unrealized_pl: position.unrealized_pl,
+                pl_mode: PlMode::Ratio,
             },
+            tentative_price: price,
+            tentative_exposure: position.units,
+            instrument: position.instrument.clone(),
+            ..Self::constant(0.0)
+        })
+    }
+
+    pub fn symmetric(
+        price0: f64,
+        price1: f64,
+        scaleUp: f64,
+        scaleDown: f64,
+        max_exposure: f64,
+        target: f64,
+    ) -> Self {
+        let zero_price = (price0 + price1) / 2.0;
+        Self {
+            max_exposure: max_exposure,
+            gear_f: Gear::symmetric(price0, price1),
+            scaleUp: scaleUp,
+            scaleDown: scaleDown,
+
+            active: true,
+            target: target,
+
+            lastTradePrice: zero_price,
+            nextBuyPrice: zero_price,
+            nextSellPrice: zero_price,
+
+            agentPL: AgentPL::flat(),
             tentative_price: zero_price,
             tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
         }
     }
+
+    // symmetric agent with a profit target given in pips, pip_value being the PL of one pip
+    // at exposure units, as given by Instrument::pip_value (e.g. 1.0 for 10000 EUR_USD)
+    pub fn symmetric_with_pip_target(
+        price0: f64,
+        price1: f64,
+        scale: f64,
+        exposure: f64,
+        target_pips: f64,
+        pip_value: f64,
+    ) -> Self {
+        Self::symmetric(price0, price1, scale, scale, exposure, target_pips * pip_value)
+    }
     pub fn jump(
         price0: f64,
         g_0: f64,
@@ -416,14 +1463,62 @@ impl GearHedger {
             nextBuyPrice: price0,
             nextSellPrice: price0,
 
-            agentPL: AgentPL {
-                exposure: 0,
-                price_average: 0.0,
-                cum_profit: 0.0,
-                unrealized_pl: 0.0,
-            },
+            agentPL: AgentPL::flat(),
             tentative_price: price0,
             tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
+        }
+    }
+
+    // notional / price units between price0 and price1, so units shrink as the price rises
+    pub fn fixed_notional(price0: f64, price1: f64, scale: f64, notional: f64) -> Self {
+        Self {
+            max_exposure: notional.abs() / price0,
+            gear_f: Gear::fixed_notional(notional.signum() as i64, price0, price1, scale),
+            scaleUp: scale,
+            scaleDown: scale,
+
+            active: true,
+            target: f64::MAX,
+
+            lastTradePrice: price1,
+            nextBuyPrice: price1,
+            nextSellPrice: price1,
+
+            agentPL: AgentPL::flat(),
+            tentative_price: price1,
+            tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
         }
     }
 
@@ -441,14 +1536,25 @@ impl GearHedger {
             nextBuyPrice: price0,
             nextSellPrice: price0,
 
-            agentPL: AgentPL {
-                exposure: 0,
-                price_average: 0.0,
-                cum_profit: 0.0,
-                unrealized_pl: 0.0,
-            },
+            agentPL: AgentPL::flat(),
             tentative_price: price0,
             tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
         }
     }
     pub fn segment(
@@ -479,29 +1585,57 @@ impl GearHedger {
             nextBuyPrice: price0,
             nextSellPrice: price0,
 
-            agentPL: AgentPL {
-                exposure: 0,
-                price_average: 0.0,
-                cum_profit: 0.0,
-                unrealized_pl: 0.0,
-            },
+            agentPL: AgentPL::flat(),
             tentative_price: price0,
             tentative_exposure: 0,
+            instrument: String::new(),
+            long_only: false,
+            short_only: false,
+            initial_ramp_ticks: None,
+            ramp_trades: 0,
+            target_action_fn: None,
+            target_action_kind: TargetActionKind::Deactivate,
+            pl_checkpoint: None,
+            consecutive_losses: 0,
+            max_consecutive_losses: None,
+            level_policy: LevelPolicy::TradeOnTouch,
+            deadband: 0.0,
+            vol_target: None,
+            max_spread: None,
+            last_close_reason: None,
+            crossed_ticks: 0,
         }
     }
 }
 
+// a + b is a.merge_flat(&b), it panics on agents of different instruments
+impl std::ops::Add<GearHedger> for GearHedger {
+    type Output = GearHedger;
+
+    fn add(self, other: GearHedger) -> GearHedger {
+        self.merge_flat(&other).unwrap_or_else(|e| panic!("cannot merge the agents: {}", e))
+    }
+}
+
+// in place merge_flat, it panics on agents of different instruments
+impl std::ops::AddAssign<&GearHedger> for GearHedger {
+    fn add_assign(&mut self, other: &GearHedger) {
+        *self = self.merge_flat(other).unwrap_or_else(|e| panic!("cannot merge the agents: {}", e));
+    }
+}
+
 impl Agent for GearHedger {
 
-    fn close(&mut self, tick :&Tick) -> i64 {
-        // otherwize,we check if we need to adjust exposure
+    // passive: only sets the closing trade, the agent stays active (see force_close)
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64 {
+        self.last_close_reason = Some(reason);
         if self.agentPL.exposure > 0 {
             self.tentative_price = tick.bid;
         } else {
             self.tentative_price = tick.ask;
         }
         self.tentative_exposure = 0;
-        0
+        -self.agentPL.exposure
     }
 
     // is active status
@@ -514,7 +1648,7 @@ impl Agent for GearHedger {
 
     // at the moment we never close, we need to add a way to add a delegate to decide closing of Agents
     fn to_be_closed(&self) -> bool {
-        self.agentPL.cum_profit > self.target
+        self.agentPL.cum_profit > self.effective_target()
         //false
     }
 
@@ -523,26 +1657,50 @@ impl Agent for GearHedger {
         self.agentPL.exposure
     }
 
+    fn tagged(&self) -> Option<TaggedAgent> {
+        Some(TaggedAgent::GearHedger(self.clone()))
+    }
+
     fn target_action(&mut self) -> i64 {
-        self.tentative_exposure = 0;
-        self.deactivate();
-        return 0;
+        match self.target_action_fn.clone() {
+            Some(f) => (f.0)(self),
+            None => {
+                let kind = self.target_action_kind;
+                kind.apply(self)
+            }
+        }
     }
 
     fn target_exposure(&mut self, tick: &Tick) -> i64 {
+        if crossed_market(tick) {
+            self.crossed_ticks += 1;
+            return self.agentPL.exposure;
+        }
+        if let Some(ramp) = self.initial_ramp_ticks.filter(|r| self.ramp_trades < *r) {
+            return self.ramp_exposure(tick, ramp);
+        }
         // otherwize,we check if we need to adjust exposure
-        if tick.bid >= self.nextSellPrice {
-            self.tentative_price = tick.bid;
-            self.tentative_exposure = (self.gear_f.g(tick.bid) * self.max_exposure) as i64;
-            //(size * (self.price0 - tick.bid)/self.scale).round() as i64;
-            self.tentative_exposure
-        } else if tick.ask <= self.nextBuyPrice {
-            self.tentative_price = tick.ask;
-            self.tentative_exposure = (self.gear_f.g(tick.ask) * self.max_exposure) as i64;
-            //(self.size as f64 * (self.price0 - tick.ask)/self.scale).round() as i64;
-            self.tentative_exposure
+        let price = if self.sell_triggered(tick.bid) {
+            tick.bid
+        } else if self.buy_triggered(tick.ask) {
+            tick.ask
         } else {
-            self.agentPL.exposure
+            return self.agentPL.exposure;
+        };
+        match self.checked_gear_exposure(price) {
+            Some(exposure) if !self.direction_allowed(exposure) => self.agentPL.exposure,
+            Some(exposure) => {
+                self.tentative_price = price;
+                self.tentative_exposure = exposure;
+                self.tentative_exposure
+            }
+            None => {
+                eprintln!(
+                    "Exposure overflow at price {} with max exposure {}, keeping exposure {}",
+                    price, self.max_exposure, self.agentPL.exposure
+                );
+                self.agentPL.exposure
+            }
         }
     }
 
@@ -562,12 +1720,28 @@ impl Agent for GearHedger {
         // we will trade to set exposure to zero and deactivate the agent.
         // TODO : call a closure defining the behaviour of the agent
         // default would be to deactivate the agent
+        self.pl_checkpoint = Some(self.agentPL.clone());
+        if crossed_market(tick) {
+            self.crossed_ticks += 1;
+            return self.agentPL.exposure;
+        }
+        if let Some(v) = self.vol_target.as_mut() {
+            v.push(tick.price());
+        }
+        if self.max_spread.is_some_and(|max| tick.spread() > max) {
+            let close_price = if self.exposure() > 0 { tick.bid } else { tick.ask };
+            if !self.agentPL.is_flat() {
+                self.agentPL.total_profit(close_price);
+            }
+            self.tentative_exposure = self.agentPL.exposure;
+            return self.agentPL.exposure;
+        }
         let close_price = if self.exposure() > 0 {
             tick.bid
         } else {
             tick.ask
         };
-        if self.agentPL.pl_at_price(close_price) > self.target {
+        if self.agentPL.pl_at_price(close_price) > self.effective_target() {
             self.tentative_price = close_price;
             self.tentative_exposure = 0;
             let e = self.target_action();
@@ -577,23 +1751,52 @@ impl Agent for GearHedger {
     }
 
     fn next_exposure_and_fill(&mut self, order_fill: &OrderFill) {
-        self.tentative_price = order_fill.price;
-        self.tentative_exposure = self.tentative_exposure + order_fill.units;
+        self.next_exposure(&Tick{bid: order_fill.price, ask: order_fill.price, time: 0});
         self.update_on_fill(order_fill);
     }
 
     fn update_on_fill(&mut self, order_fill: &OrderFill) {
         let traded = self.tentative_exposure - self.agentPL.exposure;
+        let ramping = self.initial_ramp_ticks.filter(|r| traded != 0 && self.ramp_trades < *r);
+        if ramping.is_some() {
+            self.ramp_trades += 1;
+        }
+        let realized = self.agentPL.cum_profit;
+        // only a trade reducing the position realizes PL
+        let closing = self.agentPL.exposure.signum() == -traded.signum() && traded != 0;
         if traded < 0 {
             self.agentPL.sell(order_fill.price, traded.abs());
-            self.lastTradePrice = order_fill.price;
-            self.nextSellPrice = order_fill.price + self.scaleUp;
-            self.nextBuyPrice = order_fill.price - self.scaleDown;
+            self.anchor_levels(order_fill.price);
         } else if traded > 0 {
             self.agentPL.buy(order_fill.price, traded.abs());
-            self.lastTradePrice = order_fill.price;
-            self.nextBuyPrice = order_fill.price - self.scaleDown;
-            self.nextSellPrice = order_fill.price + self.scaleUp;
+            self.anchor_levels(order_fill.price);
+        } else {
+            self.clamp_levels();
+        }
+        // the ramp is done once the gear exposure is reached
+        if let Some(ramp) = ramping {
+            if self.agentPL.exposure == self.ramp_target(order_fill.price) {
+                self.ramp_trades = ramp;
+            }
+        }
+        if traded != 0 {
+            match self.last_close_reason {
+                Some(reason) if self.agentPL.is_flat() => {
+                    eprintln!("Closed {} units at {} ({:?}), PL {:.2}", -traded, order_fill.price, reason, self.agentPL.cum_profit);
+                }
+                // trading again
+                Some(_) => self.last_close_reason = None,
+                None => {}
+            }
+            // opening and adding trades leave the count as it is
+            if closing && self.agentPL.cum_profit < realized {
+                self.consecutive_losses += 1;
+            } else if closing {
+                self.consecutive_losses = 0;
+            }
+            if self.consecutive_losses >= self.max_consecutive_losses.unwrap_or(u32::MAX) {
+                self.deactivate();
+            }
         }
         if self.to_be_closed() {
             self.deactivate()
@@ -611,18 +1814,76 @@ pub struct AgentPL {
     pub cum_profit: f64,
     // cumulated profit (Actual)
     pub unrealized_pl: f64,
+    // how a price move is turned into PL
+    #[serde(default)]
+    pub pl_mode: PlMode,
+}
+
+// Ratio: units * (x / price_average - 1), the historical PL of the agents
+// Absolute: units * (x - price_average), for instruments with prices near zero or negative
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub enum PlMode {
+    #[default]
+    Ratio,
+    Absolute,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InventoryError {
+    // no agent with this key in the inventory
+    UnknownAgent(String),
+    // agents on different instruments
+    InstrumentMismatch(String, String),
+    // combined position of merged agents beyond the merged max_exposure
+    ExposureOverflow(i64, f64),
+}
+
+impl std::fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InventoryError::UnknownAgent(key) => write!(f, "no agent named {} in the inventory", key),
+            InventoryError::InstrumentMismatch(a, b) => write!(f, "agents trade different instruments: {} and {}", a, b),
+            InventoryError::ExposureOverflow(position, max) => write!(f, "combined position {} exceeds the merged max exposure {}", position, max),
+        }
+    }
 }
 
+impl Error for InventoryError {}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AgentInventory<T: Agent> {
     pub agents: HashMap<String, T>,
     pub pl: f64,
+    // unix time (seconds) the inventory was last written, None for older files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saved_at: Option<i64>,
 }
 impl<T: Agent> AgentInventory<T> {
     pub fn new() -> Self {
         Self {
             agents: HashMap::new(),
             pl: 0.0,
+            saved_at: None,
+        }
+    }
+
+    // record the time the inventory is written at
+    pub fn stamp(&mut self, now: i64) {
+        self.saved_at = Some(now);
+    }
+
+    // the state is older than max_age seconds, or its age is unknown
+    pub fn is_stale(&self, now: i64, max_age: i64) -> bool {
+        self.saved_at.is_none_or(|saved_at| now - saved_at > max_age)
+    }
+
+    // next_exposure, None when the active agents already hold it and there is nothing to trade
+    pub fn next_exposure_if_changed(&mut self, tick: &Tick) -> Option<i64> {
+        let exposure = self.next_exposure(tick);
+        if exposure == self.exposure() {
+            None
+        } else {
+            Some(exposure)
         }
     }
     //
@@ -632,13 +1893,399 @@ impl<T: Agent> AgentInventory<T> {
     //    }
 }
 
-impl<T: Agent> Agent for AgentInventory<T> {
+// copy of the agents of an inventory at a point in time, to roll back to
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InventorySnapshot<T> {
+    // unix time (seconds) of the snapshot
+    pub taken_at: i64,
+    pub agents: HashMap<String, T>,
+    pub pl: f64,
+}
 
-    fn close(&mut self, tick :&Tick) -> i64 {
-        for (_, val) in self.agents.iter_mut() {
-            val.close(tick);
+// agent keys changed between two snapshots, sorted
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct InventoryDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl<T: Agent + Clone + Serialize> AgentInventory<T> {
+    pub fn snapshot(&self) -> InventorySnapshot<T> {
+        InventorySnapshot {
+            taken_at: chrono::Utc::now().timestamp(),
+            agents: self.agents.clone(),
+            pl: self.pl,
         }
-        0
+    }
+
+    // replace all the agents by the ones of the snapshot
+    pub fn restore(&mut self, snapshot: &InventorySnapshot<T>) {
+        self.agents = snapshot.agents.clone();
+        self.pl = snapshot.pl;
+    }
+
+    // agents are compared on their serialized state
+    pub fn diff(a: &InventorySnapshot<T>, b: &InventorySnapshot<T>) -> InventoryDiff {
+        let state = |agent: &T| serde_json::to_value(agent).ok();
+        let mut diff = InventoryDiff::default();
+        for (key, agent) in b.agents.iter() {
+            match a.agents.get(key) {
+                None => diff.added.push(key.clone()),
+                Some(before) if state(before) != state(agent) => diff.modified.push(key.clone()),
+                _ => {}
+            }
+        }
+        diff.removed = a.agents.keys().filter(|k| !b.agents.contains_key(*k)).cloned().collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.modified.sort();
+        diff
+    }
+}
+
+// PL of an agent between two ticks: realized by its trades, and the change of its unrealized PL
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PlDelta {
+    pub realized: f64,
+    pub unrealized: f64,
+}
+
+// unrealized PL if closing at tick, buying back at ask or selling at bid
+fn upl_at_tick(pl: &AgentPL, tick: &Tick) -> f64 {
+    match pl.exposure {
+        0 => 0.0,
+        e if e > 0 => pl.uPL(tick.bid),
+        _ => pl.uPL(tick.ask),
+    }
+}
+
+// flat, agent agnostic view of an inventory, for reporting and APIs
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct InventorySummary {
+    pub total_exposure: i64,
+    pub realized_pl: f64,
+    pub unrealized_pl: f64,
+    pub agent_count: usize,
+    pub active_count: usize,
+    pub instruments: Vec<String>,
+}
+
+impl AgentInventory<GearHedger> {
+    // book the units of a fill on the named agent only, on top of the position it holds
+    pub fn update_fill_for(&mut self, key: &str, fill: &OrderFill) -> Result<(), InventoryError> {
+        let agent = self
+            .agents
+            .get_mut(key)
+            .ok_or_else(|| InventoryError::UnknownAgent(key.to_string()))?;
+        agent.tentative_exposure = agent.agentPL.exposure;
+        agent.force_fill(fill);
+        Ok(())
+    }
+
+    // book a fill of fewer units than the active agents asked for with next_exposure, e.g. an order
+    // clamped to the account limits: each agent gets a share of the units in proportion of its
+    // own trade, the rest of the trades is dropped
+    pub fn update_on_partial_fill(&mut self, fill: &OrderFill) {
+        let mut names: Vec<String> = self
+            .agents
+            .iter()
+            .filter(|(_, a)| a.is_active() && a.tentative_exposure != a.exposure())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        let wanted: i64 = names.iter().map(|name| self.agents[name].tentative_exposure - self.agents[name].exposure()).sum();
+        let mut left = fill.units;
+        for (i, name) in names.iter().enumerate() {
+            let agent = self.agents.get_mut(name).unwrap();
+            let trade = agent.tentative_exposure - agent.exposure();
+            // the last agent takes the rounding
+            let units = match (i + 1 == names.len(), wanted) {
+                (true, _) => left,
+                (false, 0) => 0,
+                (false, wanted) => (trade as f64 * fill.units as f64 / wanted as f64).round() as i64,
+            };
+            left -= units;
+            agent.tentative_exposure = agent.agentPL.exposure;
+            if units != 0 {
+                agent.force_fill(&OrderFill { price: fill.price, units });
+            }
+        }
+    }
+
+    // PL of the active agents if their instrument gapped by gap_pct percent up and down from its tick
+    // (closing side), keyed "<agent>/up" and "<agent>/down", summed in "total/up" and "total/down",
+    // and "worst" the lowest of the two totals. Agents without a tick for their instrument are left out.
+    pub fn gap_scenario(&self, ticks: &HashMap<String, Tick>, gap_pct: f64) -> HashMap<String, f64> {
+        let mut scenario = HashMap::new();
+        let (mut total_up, mut total_down) = (0.0, 0.0);
+        for (name, agent) in self.agents.iter().filter(|a| a.1.active) {
+            let Some(tick) = ticks.get(&agent.instrument) else {
+                continue;
+            };
+            let price = if agent.exposure() > 0 { tick.bid } else { tick.ask };
+            let pl = |x: f64| if agent.exposure() == 0 { agent.agentPL.cum_profit } else { agent.agentPL.pl_at_price(x) };
+            let (up, down) = (pl(price * (1.0 + gap_pct / 100.0)), pl(price * (1.0 - gap_pct / 100.0)));
+            scenario.insert(format!("{}/up", name), up);
+            scenario.insert(format!("{}/down", name), down);
+            total_up += up;
+            total_down += down;
+        }
+        scenario.insert("total/up".to_string(), total_up);
+        scenario.insert("total/down".to_string(), total_down);
+        scenario.insert("worst".to_string(), f64::min(total_up, total_down));
+        scenario
+    }
+
+    // PL of each agent from prev to cur, cur being the last tick given to next_exposure
+    // (agents not ticked yet have no trade to attribute)
+    pub fn pl_delta(&self, prev: &Tick, cur: &Tick) -> HashMap<String, PlDelta> {
+        self.agents
+            .iter()
+            .map(|(name, agent)| {
+                let before = agent.pl_checkpoint.as_ref().unwrap_or(&agent.agentPL);
+                let delta = PlDelta {
+                    realized: agent.agentPL.cum_profit - before.cum_profit,
+                    unrealized: upl_at_tick(&agent.agentPL, cur) - upl_at_tick(before, prev),
+                };
+                (name.clone(), delta)
+            })
+            .collect()
+    }
+
+    // distinct instruments of the tagged agents, sorted
+    pub fn instruments(&self) -> Vec<String> {
+        let mut instruments: Vec<String> = self
+            .agents
+            .values()
+            .filter(|a| !a.instrument.is_empty())
+            .map(|a| a.instrument.clone())
+            .collect();
+        instruments.sort();
+        instruments.dedup();
+        instruments
+    }
+
+    // agents trading the instrument, to route its ticks
+    pub fn agents_for<'a>(&'a mut self, instrument: &'a str) -> impl Iterator<Item = (&'a String, &'a mut GearHedger)> + 'a {
+        self.agents.iter_mut().filter(move |a| a.1.instrument == instrument)
+    }
+
+    // migration of old files: tag the untagged agents with a default instrument
+    pub fn tag_untagged(&mut self, instrument: &str) {
+        for agent in self.agents.values_mut().filter(|a| a.instrument.is_empty()) {
+            agent.instrument = instrument.to_string();
+        }
+    }
+
+    // internal transfer between the active agents with opposing positions on instrument: each long
+    // agent sells to a short one at the mid of tick, so both positions shrink while the net exposure
+    // sent to the broker stays the same. Trading at the mid realizes the unrealized PL of the units
+    // moved, so the PL of each agent at the mid is unchanged, and the grid of both agents is anchored
+    // at the mid as after a fill. The gears are untouched: at its next level crossing each agent
+    // trades back to its gear exposure, the long and short legs of these trades net out in the
+    // broker order. Returns the units transferred.
+    pub fn rebalance(&mut self, instrument: &str, tick: &Tick) -> i64 {
+        let mid = tick.price();
+        let mut longs: Vec<String> = vec![];
+        let mut shorts: Vec<String> = vec![];
+        for (name, agent) in self.agents_for(instrument).filter(|a| a.1.active) {
+            match agent.exposure() {
+                e if e > 0 => longs.push(name.clone()),
+                e if e < 0 => shorts.push(name.clone()),
+                _ => {}
+            }
+        }
+        longs.sort();
+        shorts.sort();
+
+        let mut transferred = 0;
+        let (mut l, mut s) = (0, 0);
+        while l < longs.len() && s < shorts.len() {
+            let units = self.agents[&longs[l]].exposure().min(-self.agents[&shorts[s]].exposure());
+            for (name, units) in [(&longs[l], -units), (&shorts[s], units)] {
+                let agent = self.agents.get_mut(name).unwrap();
+                if units > 0 { agent.agentPL.buy(mid, units) } else { agent.agentPL.sell(mid, -units) }
+                agent.tentative_exposure = agent.agentPL.exposure;
+                agent.tentative_price = mid;
+                agent.anchor_levels(mid);
+            }
+            transferred += units;
+            if self.agents[&longs[l]].exposure() == 0 { l += 1; }
+            if self.agents[&shorts[s]].exposure() == 0 { s += 1; }
+        }
+        transferred
+    }
+
+    // warm restart the active agents trading instrument
+    pub fn warm_restart(&mut self, instrument: &str, tick: &Tick) {
+        for (_, agent) in self.agents_for(instrument).filter(|a| a.1.active) {
+            agent.warm_restart(tick);
+        }
+    }
+
+    // summarize the inventory trading instrument, unrealized PL is computed at the closing side of tick
+    pub fn summarize(&self, instrument: &str, tick: &Tick) -> InventorySummary {
+        self.summarize_with(instrument, tick, |_, pl| pl)
+    }
+
+    // summarize with the PL in account currency: the Ratio PL of an agent is in base currency,
+    // the Absolute PL in quote currency
+    pub fn summarize_in_account(&self, instrument: &Instrument, tick: &Tick, conversions: &HomeConversions) -> InventorySummary {
+        self.summarize_with(&instrument.name, tick, |agent, pl| {
+            let factor = match agent.agentPL.pl_mode {
+                PlMode::Ratio => conversions.base_home,
+                PlMode::Absolute => conversions.quote_home,
+            };
+            instrument.pl_in_account(pl, factor.rate(pl))
+        })
+    }
+
+    fn summarize_with(&self, instrument: &str, tick: &Tick, convert: impl Fn(&GearHedger, f64) -> f64) -> InventorySummary {
+        let mut summary = InventorySummary {
+            total_exposure: 0,
+            realized_pl: 0.0,
+            unrealized_pl: 0.0,
+            agent_count: self.agents.len(),
+            active_count: 0,
+            instruments: self.instruments(),
+        };
+        if summary.instruments.is_empty() {
+            summary.instruments.push(instrument.to_string());
+        }
+        for agent in self.agents.values() {
+            summary.realized_pl += convert(agent, agent.agentPL.cum_profit);
+            if !agent.is_active() {
+                continue;
+            }
+            summary.active_count += 1;
+            summary.total_exposure += agent.exposure();
+            if agent.exposure() != 0 {
+                let close_price = if agent.exposure() > 0 { tick.bid } else { tick.ask };
+                summary.unrealized_pl += convert(agent, agent.agentPL.uPL(close_price));
+            }
+        }
+        summary
+    }
+}
+
+// prices where the gear may change sign between lo and hi: range bounds and zero crossings
+fn sign_breakpoints(gear: &Gear, lo: f64, hi: f64) -> Vec<f64> {
+    let mut prices = vec![gear.p_0, gear.p_n];
+    for r in gear.g_i.iter() {
+        prices.extend([r.p_start, r.p_end]);
+        if r.g_start.signum() != r.g_end.signum() && r.g_end != r.g_start {
+            prices.push(r.p_start - r.g_start * (r.p_end - r.p_start) / (r.g_end - r.g_start));
+        }
+    }
+    prices.retain(|p| *p >= lo && *p <= hi);
+    prices
+}
+
+// pairs of agents on the same instrument whose gear domains [p_0, p_n] overlap with both gears
+// long or both short somewhere in the overlap, they pile up instead of hedging each other
+pub fn agents_conflicting(inventory: &AgentInventory<GearHedger>) -> Vec<(String, String)> {
+    let mut names: Vec<&String> = inventory.agents.keys().collect();
+    names.sort();
+    let mut conflicts = vec![];
+    for (i, name_a) in names.iter().enumerate() {
+        for name_b in names.iter().skip(i + 1) {
+            let (a, b) = (&inventory.agents[*name_a], &inventory.agents[*name_b]);
+            if !a.instrument.is_empty() && !b.instrument.is_empty() && a.instrument != b.instrument {
+                continue;
+            }
+            let lo = a.gear_f.p_0.max(b.gear_f.p_0);
+            let hi = a.gear_f.p_n.min(b.gear_f.p_n);
+            if lo > hi {
+                continue;
+            }
+            // both gears keep their sign between consecutive breakpoints, check them and the midpoints
+            let mut prices = [vec![lo, hi], sign_breakpoints(&a.gear_f, lo, hi), sign_breakpoints(&b.gear_f, lo, hi)].concat();
+            prices.sort_by(|x, y| x.total_cmp(y));
+            prices.dedup();
+            let midpoints: Vec<f64> = prices.windows(2).map(|w| (w[0] + w[1]) / 2.0).collect();
+            let same_sign = prices.iter().chain(midpoints.iter()).any(|p| a.gear_f.g(*p) * b.gear_f.g(*p) > 0.0);
+            if same_sign {
+                conflicts.push(((*name_a).clone(), (*name_b).clone()));
+            }
+        }
+    }
+    conflicts
+}
+
+// significant digits kept for the floats of the written inventory files
+pub const DEFAULT_JSON_PRECISION: usize = 8;
+
+// round x to digits significant digits, non finite values and the f64::MAX sentinels
+// (e.g. an unlimited target) are kept as is
+pub fn round_significant(x: f64, digits: usize) -> f64 {
+    if !x.is_finite() || x.abs() >= f64::MAX || x == 0.0 || digits == 0 {
+        return x;
+    }
+    format!("{:.*e}", digits - 1, x).parse().unwrap_or(x)
+}
+
+fn round_json_floats(value: &mut serde_json::Value, digits: usize) {
+    match value {
+        serde_json::Value::Number(n) if n.is_f64() => {
+            let rounded = n.as_f64().map(|x| round_significant(x, digits));
+            if let Some(rounded) = rounded.and_then(serde_json::Number::from_f64) {
+                *n = rounded;
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(|v| round_json_floats(v, digits)),
+        serde_json::Value::Object(map) => map.values_mut().for_each(|v| round_json_floats(v, digits)),
+        _ => {}
+    }
+}
+
+impl<T: Agent + Serialize> AgentInventory<T> {
+    // json of the inventory with the floats rounded, the in memory values are untouched
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        self.to_json_with_precision(DEFAULT_JSON_PRECISION)
+    }
+
+    pub fn to_json_with_precision(&self, digits: usize) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        round_json_floats(&mut value, digits);
+        serde_json::to_string(&value)
+    }
+}
+
+// TOML goes through the JSON value, so that both files have the same fields less the nulls
+#[cfg(feature = "toml")]
+fn to_toml_string<T: Serialize>(x: &T) -> String {
+    fn strip_nulls(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Array(values) => values.iter_mut().for_each(strip_nulls),
+            serde_json::Value::Object(map) => {
+                map.retain(|_, v| !v.is_null());
+                map.values_mut().for_each(strip_nulls);
+            }
+            _ => {}
+        }
+    }
+    let mut value = serde_json::to_value(x).expect("agents serialize to JSON");
+    strip_nulls(&mut value);
+    toml::to_string(&value).expect("a JSON object without nulls serializes to TOML")
+}
+
+#[cfg(feature = "toml")]
+impl AgentInventory<GearHedger> {
+    pub fn from_toml(s: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    pub fn to_toml(&self) -> String {
+        to_toml_string(self)
+    }
+}
+
+impl<T: Agent> Agent for AgentInventory<T> {
+
+    // one netted order flattens all the agents, active or not
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64 {
+        self.agents.values_mut().map(|a| a.close(tick, reason)).sum()
     }
 
     fn is_active(&self) -> bool {
@@ -666,9 +2313,13 @@ impl<T: Agent> Agent for AgentInventory<T> {
         0
     }
 
-    // we do nothing, it only happens on each individual Agent of the inventory
+    // sum of the targets of the active agents, as exposure() sums their positions
     fn target_exposure(&mut self, tick: &Tick) -> i64 {
-        0
+        self.agents
+            .values_mut()
+            .filter(|a| a.is_active())
+            .map(|a| a.target_exposure(tick))
+            .sum()
     }
 
 
@@ -680,6 +2331,9 @@ impl<T: Agent> Agent for AgentInventory<T> {
         exposure
     }
 
+    // DEPRECATED: broadcasts the fill to ALL active agents, each one booking its own
+    // tentative exposure at the fill price. This is only right just after next_exposure
+    // on the whole inventory, use update_fill_for to book a fill on a single agent.
     fn update_on_fill(&mut self, order_fill: &OrderFill) {
         for (_, val) in self.agents.iter_mut().filter(|a| a.1.is_active()) {
             val.update_on_fill(order_fill);
@@ -691,19 +2345,231 @@ impl<T: Agent> Agent for AgentInventory<T> {
     }
 }
 
+/*
+PairedHedger runs a long GearHedger and a short GearHedger on the same instrument as one agent.
+The order is the sum of both legs, and its fill is routed back to the legs in proportion
+of what each one asked for. The pair is done when both legs reached their target.
+*/
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PairedHedger {
+    pub long_leg: GearHedger,
+    pub short_leg: GearHedger,
+}
+
+impl PairedHedger {
+    pub fn new(long_leg: GearHedger, short_leg: GearHedger) -> Self {
+        Self { long_leg, short_leg }
+    }
+
+    // units of a fill for each leg: proportional to the leg request, the long leg is rounded
+    // and the short leg takes the rest. When both requests net out, no order reaches the
+    // market and each leg is filled its own request at the fill price.
+    fn split_fill(&self, units: i64) -> (i64, i64) {
+        let long_request = self.long_leg.tentative_exposure - self.long_leg.exposure();
+        let short_request = self.short_leg.tentative_exposure - self.short_leg.exposure();
+        let request = long_request + short_request;
+        if request == 0 {
+            return (long_request, short_request);
+        }
+        let long_units = (units as f64 * long_request as f64 / request as f64).round() as i64;
+        (long_units, units - long_units)
+    }
+}
+
+// book units on a leg, whatever its tentative exposure was
+fn fill_leg(leg: &mut GearHedger, price: f64, units: i64) {
+    leg.tentative_price = price;
+    leg.tentative_exposure = leg.exposure() + units;
+    leg.update_on_fill(&OrderFill { price, units });
+}
+
+// exposure asked by a leg, an inactive leg keeps its exposure
+fn leg_next_exposure(leg: &mut GearHedger, tick: &Tick) -> i64 {
+    if !leg.is_active() {
+        leg.tentative_exposure = leg.exposure();
+        return leg.exposure();
+    }
+    leg.next_exposure(tick)
+}
+
+impl Agent for PairedHedger {
+
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64 {
+        self.long_leg.close(tick, reason) + self.short_leg.close(tick, reason)
+    }
+
+    fn is_active(&self) -> bool {
+        self.long_leg.is_active() || self.short_leg.is_active()
+    }
+    fn deactivate(&mut self) {
+        self.long_leg.deactivate();
+        self.short_leg.deactivate();
+    }
+
+    fn to_be_closed(&self) -> bool {
+        self.long_leg.to_be_closed() && self.short_leg.to_be_closed()
+    }
+
+    fn target_action(&mut self) -> i64 {
+        self.long_leg.target_action() + self.short_leg.target_action()
+    }
+
+    fn target_exposure(&mut self, tick: &Tick) -> i64 {
+        self.long_leg.target_exposure(tick) + self.short_leg.target_exposure(tick)
+    }
+
+    fn next_exposure(&mut self, tick: &Tick) -> i64 {
+        leg_next_exposure(&mut self.long_leg, tick) + leg_next_exposure(&mut self.short_leg, tick)
+    }
+
+    fn update_on_fill(&mut self, order_fill: &OrderFill) {
+        let (long_units, short_units) = self.split_fill(order_fill.units);
+        fill_leg(&mut self.long_leg, order_fill.price, long_units);
+        fill_leg(&mut self.short_leg, order_fill.price, short_units);
+    }
+
+    fn next_exposure_and_fill(&mut self, order_fill: &OrderFill) {
+        self.next_exposure(&Tick{bid: order_fill.price, ask: order_fill.price, time: 0});
+        self.update_on_fill(order_fill);
+    }
+
+    fn exposure(&self) -> i64 {
+        self.long_leg.exposure() + self.short_leg.exposure()
+    }
+
+    fn tagged(&self) -> Option<TaggedAgent> {
+        Some(TaggedAgent::Paired(Box::new(self.clone())))
+    }
+}
+
+/*
+VolatilityScaledAgent sizes a GearHedger by the realized volatility of its fills:
+- each fill pushes the return from the previous fill price into a window of the last lookback returns
+- the inner max_exposure is then base_exposure * target_vol / realized_vol, with realized_vol the
+  standard deviation of the window, so positions are smaller in volatile markets
+The base exposure is kept until the window holds two returns, or if the prices did not move.
+*/
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VolatilityScaledAgent {
+    pub inner: GearHedger,
+    pub base_exposure: f64,
+    pub target_vol: f64,
+    pub vol_window: VecDeque<f64>,
+    pub lookback: usize,
+    pub last_price: Option<f64>,
+}
+
+impl VolatilityScaledAgent {
+    pub fn new(inner: GearHedger, target_vol: f64, lookback: usize) -> Self {
+        Self {
+            base_exposure: inner.max_exposure,
+            inner,
+            target_vol,
+            vol_window: VecDeque::with_capacity(lookback),
+            lookback,
+            last_price: None,
+        }
+    }
+
+    // standard deviation of the returns in the window, None with less than two returns
+    pub fn realized_vol(&self) -> Option<f64> {
+        sample_std_dev(self.vol_window.iter().copied())
+    }
+
+    fn push_price(&mut self, price: f64) {
+        if let Some(last) = self.last_price.filter(|p| *p != 0.0) {
+            self.vol_window.push_back(price / last - 1.0);
+            while self.vol_window.len() > self.lookback {
+                self.vol_window.pop_front();
+            }
+        }
+        self.last_price = Some(price);
+        if let Some(vol) = self.realized_vol().filter(|v| *v > 0.0) {
+            self.inner.max_exposure = self.base_exposure * self.target_vol / vol;
+        }
+    }
+}
+
+impl Agent for VolatilityScaledAgent {
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64 {
+        self.inner.close(tick, reason)
+    }
+
+    fn is_active(&self) -> bool {
+        self.inner.is_active()
+    }
+    fn deactivate(&mut self) {
+        self.inner.deactivate()
+    }
+
+    fn to_be_closed(&self) -> bool {
+        self.inner.to_be_closed()
+    }
+
+    fn target_action(&mut self) -> i64 {
+        self.inner.target_action()
+    }
+
+    fn target_exposure(&mut self, tick: &Tick) -> i64 {
+        self.inner.target_exposure(tick)
+    }
+
+    fn next_exposure(&mut self, tick: &Tick) -> i64 {
+        self.inner.next_exposure(tick)
+    }
+
+    // the fill is booked at the current size, the next trades use the new one
+    fn update_on_fill(&mut self, order_fill: &OrderFill) {
+        self.inner.update_on_fill(order_fill);
+        self.push_price(order_fill.price);
+    }
+
+    fn next_exposure_and_fill(&mut self, order_fill: &OrderFill) {
+        self.next_exposure(&Tick{bid: order_fill.price, ask: order_fill.price, time: 0});
+        self.update_on_fill(order_fill);
+    }
+
+    fn exposure(&self) -> i64 {
+        self.inner.exposure()
+    }
+}
+
 impl AgentPL {
+    // no exposure and no PL yet
+    pub fn flat() -> Self {
+        Self {
+            exposure: 0,
+            price_average: 0.0,
+            cum_profit: 0.0,
+            unrealized_pl: 0.0,
+            pl_mode: PlMode::Ratio,
+        }
+    }
+
+    pub fn is_flat(&self) -> bool {
+        self.exposure == 0
+    }
+
+    // PL of one unit of long exposure bought at price_average, at price x
+    fn pl_per_unit(&self, x: f64) -> f64 {
+        match self.pl_mode {
+            PlMode::Ratio => x / self.price_average - 1.0,
+            PlMode::Absolute => x - self.price_average,
+        }
+    }
+
     // total_profit compute the Process total profit for a given exit price
     pub fn total_profit(&mut self, x: f64) -> f64 {
-        self.unrealized_pl = (self.exposure as f64) * (x / self.price_average - 1.0);
+        self.unrealized_pl = (self.exposure as f64) * self.pl_per_unit(x);
         self.unrealized_pl + self.cum_profit
     }
 
     pub fn pl_at_price(&self, x: f64) -> f64 {
-        self.cum_profit + (self.exposure as f64) * (x / self.price_average - 1.0)
+        self.cum_profit + (self.exposure as f64) * self.pl_per_unit(x)
     }
 
     pub fn uPL(&self, x: f64) -> f64 {
-        (self.exposure as f64) * (x / self.price_average - 1.0)
+        (self.exposure as f64) * self.pl_per_unit(x)
     }
 
     // IncreaseBy a number of units (positive on Long exposure, negative on Short exposure)
@@ -714,18 +2580,36 @@ impl AgentPL {
             / e.abs() as f64;
         self.exposure = e;
         self.price_average = a;
-        self.unrealized_pl = self.exposure as f64 * (x / self.price_average - 1.0);
+        self.unrealized_pl = self.exposure as f64 * self.pl_per_unit(x);
+    }
+
+    // increase_by, failing without changes when the average price would be degenerate
+    pub fn checked_increase_by(&mut self, x: f64, units: i64) -> super::super::Result<()> {
+        finite(x, "price")?;
+        let e = self.exposure + units;
+        if e == 0 {
+            return Err(MathError::ZeroExposure);
+        }
+        let a = finite(
+            (self.price_average * self.exposure.abs() as f64 + x * units.abs() as f64) / e.abs() as f64,
+            "average price",
+        )?;
+        if self.pl_mode == PlMode::Ratio && a == 0.0 {
+            return Err(MathError::NonFinite("ratio PL"));
+        }
+        self.increase_by(x, units);
+        Ok(())
     }
 
     // DecreaseBy a number of Units (positive on Long exposure, negative on Short exposure)
     pub fn decrease_by(&mut self, x: f64, units: i64) {
         let de = units;
         let e = self.exposure - de;
-        let pi = de as f64 * (x / self.price_average - 1.0);
+        let pi = de as f64 * self.pl_per_unit(x);
 
         self.exposure = e;
         self.cum_profit += pi;
-        self.unrealized_pl = self.exposure as f64 * (x / self.price_average - 1.0);
+        self.unrealized_pl = self.exposure as f64 * self.pl_per_unit(x);
     }
 
     pub fn buy(&mut self, x: f64, units: i64) {
@@ -744,10 +2628,11 @@ impl AgentPL {
     }
     pub fn sell(&mut self, x: f64, units: i64) {
         if self.exposure <= 0 {
-            // increase long position
+            // increase short position: units is positive here, increase_by takes
+            // the signed change of exposure, so -units makes the short larger (intended)
             self.increase_by(x, -units);
         } else if self.exposure > 0 && units > self.exposure {
-            // decrease short position
+            // close the long position and open a short with the remainder
             // take the smallest between exposure and sale size
             let delta = units - self.exposure;
             self.decrease_by(x, self.exposure);
@@ -760,10 +2645,13 @@ impl AgentPL {
 
 #[cfg(test)]
 mod tests {
-    use super::super::account::OrderFill;
+    use super::super::account::{OrderFill, Position};
     use super::super::quote::Tick;
+    use super::super::super::Gear;
     use super::GAgent;
-    use super::{Agent, GearHedger};
+    use super::super::super::MathError;
+    use super::{agents_conflicting, build_from_json_str, CloseReason, import_golang, round_significant, Agent, AgentBuildError, AgentInventory, AgentLoadError, InventoryDiff, LevelPolicy, MergeOverflow, AgentPL, GearHedger, VolatilityScaledAgent, VolTarget, InventoryError, PairedHedger, PlMode, TargetActionKind};
+    use std::collections::HashMap;
 
     #[test]
     fn exploration() {
@@ -808,6 +2696,38 @@ mod tests {
         assert_eq!(gear.exposure(), -gear.max_exposure as i64);
     }
 
+    #[test]
+    fn zero_scale_needs_a_price_move() {
+        let mut gear = GearHedger::symmetric(0.80, 1.20, 0.0, 0.0, 100000.0, 100000.0);
+        let tick = Tick { time: 0, bid: 0.9990, ask: 0.9991 };
+        let e = gear.next_exposure(&tick);
+        gear.update_on_fill(&OrderFill { price: gear.tentative_price, units: e });
+        assert!(gear.exposure() > 0);
+        assert!(gear.nextSellPrice > gear.lastTradePrice);
+        assert!(gear.nextBuyPrice < gear.lastTradePrice);
+
+        // bid at the last trade price no longer triggers a trade
+        let tentative_price = gear.tentative_price;
+        let again = Tick { time: 1, bid: 0.9991, ask: 0.9992 };
+        assert_eq!(gear.next_exposure(&again), gear.exposure());
+        assert_eq!(gear.tentative_price, tentative_price);
+    }
+
+    #[test]
+    fn crossed_market_keeps_exposure() {
+        let mut gear = GearHedger::symmetric(0.80, 1.20, 0.0010, 0.0010, 100000.0, 100000.0);
+        let e = gear.next_exposure(&Tick { time: 0, bid: 0.9990, ask: 0.9991 });
+        gear.update_on_fill(&OrderFill { price: gear.tentative_price, units: e });
+        let exposure = gear.exposure();
+
+        // bid above nextSellPrice and ask below nextBuyPrice at once
+        let crossed = Tick { time: 1, bid: 1.0100, ask: 0.9800 };
+        assert_eq!(gear.next_exposure(&crossed), exposure);
+        assert_eq!(gear.target_exposure(&crossed), exposure);
+        assert_eq!(gear.exposure(), exposure);
+        assert_eq!(gear.crossed_ticks, 2);
+    }
+
     #[test]
     fn target() {
         let mut agent = GAgent::Segment {
@@ -848,4 +2768,1434 @@ mod tests {
        // assert_eq!(agent.agentPL.cum_profit, 0.0);
        // assert_eq!(agent.exposure(), 10000);
     }
+
+    #[test]
+    fn inventory_summary() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        let mut long = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        long.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let mut short = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        short.force_fill(&OrderFill { price: 1.00, units: -2000 });
+        short.deactivate();
+        inventory.agents.insert("long".to_string(), long);
+        inventory.agents.insert("short".to_string(), short);
+
+        let summary = inventory.summarize("EUR_USD", &Tick { time: 0, bid: 1.10, ask: 1.11 });
+        assert_eq!(summary.agent_count, 2);
+        assert_eq!(summary.active_count, 1);
+        assert_eq!(summary.total_exposure, 5000);
+        assert_eq!(summary.realized_pl, 0.0);
+        assert!((summary.unrealized_pl - 500.0).abs() < 1e-6);
+        assert_eq!(summary.instruments, vec!["EUR_USD".to_string()]);
+    }
+
+    #[test]
+    fn size_for_pip_risk() {
+        use crate::hff::instrument::Instrument;
+
+        // 10 USD per pip on EUR_USD is 100000 units
+        let mut agent = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 50.0);
+        agent.size_for_pip_risk(&Instrument::from_name("EUR_USD").unwrap(), 10.0, 1.0);
+        assert!((agent.max_exposure - 100000.0).abs() < 1e-6);
+        assert!((agent.target - 500.0).abs() < 1e-6);
+        // GBP quote at 1.25 USD: 80000 units of EUR_GBP
+        agent.size_for_pip_risk(&Instrument::from_name("EUR_GBP").unwrap(), 10.0, 1.25);
+        assert!((agent.max_exposure - 80000.0).abs() < 1e-6);
+        let pip_value = Instrument::from_name("EUR_GBP").unwrap().pip_value(agent.max_exposure as i64, 1.25);
+        assert!((pip_value - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn inventory_summary_in_account() {
+        use crate::hff::instrument::{HomeConversions, HomeFactor, Instrument};
+
+        // a USD account trading EUR_GBP
+        let eur_gbp = Instrument::from_name("EUR_GBP").unwrap();
+        let conversions = HomeConversions {
+            base_home: HomeFactor { gain: 1.08, loss: 1.09 },
+            quote_home: HomeFactor { gain: 1.25, loss: 1.26 },
+        };
+        let mut ratio = GearHedger::buyer(0.80, 1.00, 0.0010, 0.0010, 10000.0).with_instrument("EUR_GBP");
+        ratio.force_fill(&OrderFill { price: 0.80, units: 5000 });
+        ratio.agentPL.cum_profit = -10.0;
+        let mut absolute = ratio.clone();
+        absolute.agentPL.pl_mode = PlMode::Absolute;
+        let tick = Tick { time: 0, bid: 0.88, ask: 0.88 };
+
+        // Ratio PL in EUR: 5000 * (0.88 / 0.80 - 1) = 500
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("ratio".to_string(), ratio);
+        let summary = inventory.summarize_in_account(&eur_gbp, &tick, &conversions);
+        assert!((summary.unrealized_pl - 500.0 * 1.08).abs() < 1e-6);
+        assert!((summary.realized_pl + 10.0 * 1.09).abs() < 1e-9);
+
+        // Absolute PL in GBP: 5000 * (0.88 - 0.80) = 400
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("absolute".to_string(), absolute);
+        let summary = inventory.summarize_in_account(&eur_gbp, &tick, &conversions);
+        assert!((summary.unrealized_pl - 400.0 * 1.25).abs() < 1e-6);
+        assert!((summary.realized_pl + 10.0 * 1.26).abs() < 1e-9);
+    }
+
+    #[test]
+    fn coastline_reference_resets_on_fill() {
+        let scale = 0.0010;
+        let mut agent = GAgent::Coastline {
+            direction: 1,
+            price0: 1.0000,
+            scale,
+            size: 1000.0,
+            imax: 10.0,
+        }
+        .build()
+        .unwrap();
+
+        let trade = |agent: &mut GearHedger, price: f64| {
+            agent.next_exposure(&Tick { time: 0, bid: price, ask: price });
+            agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure - agent.exposure() });
+            // each fill is the new reference of the grid
+            assert_eq!(agent.lastTradePrice, price);
+            assert_eq!(agent.nextBuyPrice, price - scale);
+            assert_eq!(agent.nextSellPrice, price + scale);
+            agent.exposure()
+        };
+
+        let reversal = 1.0000;
+        let mut exposure = trade(&mut agent, reversal);
+        // 5 down moves, exposure increases with the distance from the reversal price
+        for _ in 0..5 {
+            let price = agent.nextBuyPrice - 0.00001;
+            let next = trade(&mut agent, price);
+            assert!(next > exposure);
+            exposure = next;
+        }
+        // 3 up moves from the new reversal, exposure decreases
+        for _ in 0..3 {
+            let price = agent.nextSellPrice + 0.00001;
+            let next = trade(&mut agent, price);
+            assert!(next < exposure);
+            exposure = next;
+        }
+        assert!(agent.active);
+    }
+
+    #[test]
+    fn ohlc_momentum() {
+        let ohlc = GAgent::OHLC {
+            open: 1.0000,
+            high: 1.0100,
+            low: 0.9900,
+            close: 1.0050,
+            scale: 0.0010,
+            exposure: 10000.0,
+            target: None,
+        }
+        .build()
+        .unwrap();
+        let momentum = GAgent::OHLCMomentum {
+            open: 1.0000,
+            high: 1.0100,
+            low: 0.9900,
+            close: 1.0050,
+            scale: 0.0010,
+            exposure: 10000.0,
+            target: None,
+        }
+        .build()
+        .unwrap();
+
+        // mean reversion is long below and short above, momentum the opposite
+        assert!(ohlc.gear_f.g(0.9800) > 0.0);
+        assert!(ohlc.gear_f.g(1.0200) < 0.0);
+        assert!(momentum.gear_f.g(0.9800) < 0.0);
+        assert!(momentum.gear_f.g(1.0200) > 0.0);
+        assert_eq!(momentum.max_exposure, 10000.0);
+    }
+
+    #[test]
+    fn unknown_variant_round_trip() {
+        let file = r#"{"known":{"JumpLong":{"price0":1.1,"scale":0.001,"exposure":1000.0}},"future":{"Spiral":{"turns":3,"price":1.05}}}"#;
+        let agents: HashMap<String, GAgent> = serde_json::from_str(file).unwrap();
+        assert!(!agents["known"].is_unknown());
+        assert!(agents["known"].build().is_some());
+        assert!(agents["future"].is_unknown());
+        assert!(agents["future"].build().is_none());
+
+        let back: serde_json::Value = serde_json::to_value(&agents).unwrap();
+        let original: serde_json::Value = serde_json::from_str(file).unwrap();
+        assert_eq!(back, original);
+
+        // a known variant with bad fields is still an error
+        assert!(serde_json::from_str::<GAgent>(r#"{"JumpLong":{"price0":1.1}}"#).is_err());
+        for variant in super::GAGENT_VARIANTS {
+            assert!(serde_json::from_str::<GAgent>(&format!(r#"{{"{}":{{"scale":"x"}}}}"#, variant)).is_err(), "{}", variant);
+        }
+        // the list holds every variant serde knows of (a check failing loudly if serde words it otherwise)
+        let expected = GAgent::deserialize(&serde_json::json!({"Newer": {}})).unwrap_err().to_string();
+        let expected: Vec<&str> = expected.split('`').skip(3).step_by(2).collect();
+        assert_eq!(expected, super::GAGENT_VARIANTS);
+    }
+
+    #[test]
+    fn update_fill_for_named_agent() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("a".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        inventory.agents.insert("b".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        let tick = Tick { time: 0, bid: 1.00, ask: 1.00 };
+        inventory.next_exposure(&tick);
+
+        // the units filled, not the 5000 the gear asked for
+        let fill = OrderFill { price: 1.00, units: 2000 };
+        assert_eq!(inventory.update_fill_for("a", &fill), Ok(()));
+        assert_eq!(inventory.agents["a"].exposure(), 2000);
+        assert_eq!(inventory.agents["b"].exposure(), 0);
+        assert_eq!(inventory.update_fill_for("a", &OrderFill { price: 1.01, units: -500 }), Ok(()));
+        assert_eq!(inventory.agents["a"].exposure(), 1500);
+        assert_eq!(inventory.agents["a"].lastTradePrice, 1.01);
+        assert_eq!(
+            inventory.update_fill_for("c", &fill),
+            Err(InventoryError::UnknownAgent("c".to_string()))
+        );
+    }
+
+    #[test]
+    fn price_at_zero_exposure() {
+        let agent = GearHedger::symmetric(0.80, 1.20, 0.0010, 0.0010, 100000.0, 100000.0);
+        assert!((agent.price_at_zero_exposure().unwrap() - 1.0).abs() < 1e-12);
+        let agent = GearHedger::segment(1.00, 30000.0, 1.04, -10000.0, 0.0010, 10.0);
+        assert!((agent.price_at_zero_exposure().unwrap() - 1.03).abs() < 1e-12);
+        assert_eq!(GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0).price_at_zero_exposure(), Some(1.10));
+        assert_eq!(GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0).price_at_zero_exposure(), Some(0.90));
+        assert_eq!(GearHedger::jump(1.05, 1.0, 0.0, 0.0010, 0.0010, 10000.0).price_at_zero_exposure(), Some(1.05));
+        assert_eq!(GearHedger::constant(10000.0).price_at_zero_exposure(), None);
+        assert_eq!(GearHedger::segment(1.00, 30000.0, 1.04, 10000.0, 0.0010, 10.0).price_at_zero_exposure(), None);
+    }
+
+    #[test]
+    fn instrument_tagging() {
+        let mut eur = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0).with_instrument("EUR_USD");
+        eur.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let gbp = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0).with_instrument("GBP_USD");
+        assert_eq!(
+            eur.merge_flat(&gbp).err(),
+            Some(InventoryError::InstrumentMismatch("EUR_USD".to_string(), "GBP_USD".to_string()))
+        );
+        let eur2 = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0).with_instrument("EUR_USD");
+        let merged = eur.merge_flat(&eur2).unwrap();
+        assert_eq!(merged.instrument, "EUR_USD");
+        assert_eq!(merged.exposure(), 5000);
+        let untagged = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        assert_eq!(untagged.merge_flat(&eur2).unwrap().instrument, "EUR_USD");
+
+        // old files have no instrument
+        let mut json = serde_json::to_value(&untagged).unwrap();
+        json.as_object_mut().unwrap().remove("instrument");
+        let old: GearHedger = serde_json::from_value(json).unwrap();
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("old".to_string(), old);
+        inventory.agents.insert("gbp".to_string(), gbp);
+        assert_eq!(inventory.instruments(), vec!["GBP_USD".to_string()]);
+        inventory.tag_untagged("EUR_USD");
+        assert_eq!(inventory.instruments(), vec!["EUR_USD".to_string(), "GBP_USD".to_string()]);
+        assert_eq!(inventory.agents_for("EUR_USD").count(), 1);
+    }
+
+    #[test]
+    fn long_only() {
+        let mut agent = GearHedger::segment(0.90, 10000.0, 1.10, -10000.0, 0.0100, f64::MAX);
+        agent.long_only = true;
+        // no sell before any buy
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 1.05, ask: 1.05 }), 0);
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: 0 });
+        assert_eq!(agent.exposure(), 0);
+
+        agent.next_exposure(&Tick { time: 0, bid: 0.95, ask: 0.95 });
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        assert_eq!(agent.exposure(), 5000);
+        // the rise does not sell, the next dip buys more
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 1.05, ask: 1.05 }), 5000);
+        assert_eq!(agent.tentative_exposure, 5000);
+        assert!((agent.next_exposure(&Tick { time: 0, bid: 0.92, ask: 0.92 }) - 8000).abs() <= 1);
+
+        let mut agent = GearHedger::segment(0.90, 10000.0, 1.10, -10000.0, 0.0100, f64::MAX);
+        agent.short_only = true;
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 0.95, ask: 0.95 }), 0);
+        // truncated towards zero
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 1.05, ask: 1.05 }), -4999);
+
+        // the built agents trade both ways, as the ones read from files without the flags
+        let buyer = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        let json = serde_json::to_value(&buyer).unwrap();
+        let mut stripped = json.clone();
+        stripped.as_object_mut().unwrap().retain(|k, _| k != "long_only" && k != "short_only");
+        let read: GearHedger = serde_json::from_value(stripped).unwrap();
+        assert_eq!((read.long_only, read.short_only), (buyer.long_only, buyer.short_only));
+        assert!(!GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0).short_only);
+    }
+
+    #[test]
+    fn rescale() {
+        let mut agent = GearHedger::symmetric(0.80, 1.20, 0.0100, 0.0100, 10000.0, 1000.0);
+        agent.next_exposure(&Tick { time: 0, bid: 0.90, ask: 0.90 });
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        agent.next_exposure(&Tick { time: 0, bid: 0.95, ask: 0.95 });
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        let price_average = agent.agentPL.price_average;
+        let pl = agent.agentPL.pl_at_price(1.05);
+
+        agent.rescale(2.0);
+        assert_eq!(agent.max_exposure, 20000.0);
+        assert_eq!(agent.target, 2000.0);
+        assert_eq!(agent.exposure(), 5000);
+        assert_eq!(agent.agentPL.price_average, price_average);
+        assert!((agent.agentPL.pl_at_price(1.05) - 2.0 * pl).abs() < 1e-9);
+
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        agent.rescale(2.0);
+        assert_eq!(agent.target, f64::MAX);
+        agent.rescale(0.5);
+        assert_eq!(agent.target, f64::MAX);
+    }
+
+    #[test]
+    fn as_gear_agent_round_trip() {
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-9 * a.abs().max(1.0);
+        let agents = vec![
+            GAgent::Symmetric { pmid: 1.0, span: 0.1, scale: 0.001, exposure: 10000.0, target: 50.0 },
+            GAgent::Segment { price0: 0.9, exposure0: 30000.0, pricen: 1.1, exposuren: -10000.0, scale: 0.001, target: 50.0 },
+            GAgent::Coastline { direction: 1, price0: 1.05, scale: 0.001, size: 1000.0, imax: 10.0 },
+            GAgent::Coastline { direction: -1, price0: 1.05, scale: 0.001, size: 1000.0, imax: 10.0 },
+            GAgent::Buy { price0: 0.9, price1: 1.1, scale: 0.001, exposure: 10000.0 },
+            GAgent::Sell { price0: 0.9, price1: 1.1, scale: 0.001, exposure: 10000.0 },
+            GAgent::JumpLong { price0: 1.05, scale: 0.001, exposure: 10000.0 },
+        ];
+        for gagent in agents {
+            let agent = gagent.build().unwrap();
+            let exported = agent.as_gear_agent().unwrap();
+            assert_eq!(std::mem::discriminant(&exported), std::mem::discriminant(&gagent), "{:?}", exported);
+            let rebuilt = exported.build().unwrap();
+            assert!(close(rebuilt.max_exposure, agent.max_exposure));
+            assert!(close(rebuilt.target, agent.target));
+            for x in [0.8, 0.95, 1.0, 1.04, 1.05, 1.2] {
+                assert!(close(rebuilt.gear_f.g(x), agent.gear_f.g(x)), "{:?} at {}", exported, x);
+            }
+        }
+
+        // multi segment gears have no variant
+        let mut agent = GearHedger::symmetric(0.9, 1.1, 0.001, 0.001, 10000.0, 50.0);
+        agent.gear_f.g_i.push(agent.gear_f.g_i[0].clone());
+        assert!(agent.as_gear_agent().is_none());
+    }
+
+    #[test]
+    fn initial_ramp() {
+        let tick = Tick { time: 0, bid: 0.80, ask: 0.80 };
+        let mut agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, f64::MAX);
+        agent.next_exposure(&tick);
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        assert_eq!(agent.exposure(), 10000);
+
+        let mut agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, f64::MAX);
+        agent.initial_ramp_ticks = Some(4);
+        let mut exposures = vec![];
+        for _ in 0..5 {
+            agent.next_exposure(&tick);
+            agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure - agent.exposure() });
+            exposures.push(agent.exposure());
+        }
+        assert_eq!(exposures, vec![2500, 5000, 7500, 10000, 10000]);
+        assert_eq!(agent.ramp_trades, 4);
+        // back to the grid after the ramp, one scale below the gear domain
+        assert_eq!(agent.nextBuyPrice, 0.90 - 0.0010);
+
+        // near the neutral price the gear exposure is reached at once and the grid takes over
+        let mut agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, f64::MAX);
+        agent.initial_ramp_ticks = Some(4);
+        let tick = Tick { time: 0, bid: 0.999, ask: 0.999 };
+        agent.next_exposure(&tick);
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        assert_eq!(agent.exposure(), 100);
+        assert_eq!(agent.ramp_trades, 4);
+        // small moves within the grid step do not trade
+        for price in [0.9992, 0.9995, 0.9988, 0.9991] {
+            assert_eq!(agent.next_exposure(&Tick { time: 1, bid: price, ask: price }), 100);
+        }
+
+        // no trade needed on the first tick: the ramp ends without a trade
+        let mut agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, f64::MAX);
+        agent.initial_ramp_ticks = Some(4);
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 1.0, ask: 1.0 }), 0);
+        assert_eq!(agent.ramp_trades, 4);
+        // then it trades as without a ramp
+        let mut plain = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, f64::MAX);
+        plain.next_exposure(&Tick { time: 0, bid: 1.0, ask: 1.0 });
+        let tick = Tick { time: 1, bid: 1.0002, ask: 1.0002 };
+        assert_eq!(agent.next_exposure(&tick), plain.next_exposure(&tick));
+    }
+
+    #[test]
+    fn target_action_fn() {
+        let agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, 10.0)
+            .with_target_action_fn_from_type(TargetActionKind::Rearm { step: 10.0 });
+        let json = serde_json::to_string(&agent).unwrap();
+        assert!(!json.contains("target_action_fn"));
+
+        let mut agent: GearHedger = serde_json::from_str(&json).unwrap();
+        assert!(agent.target_action_fn.is_none());
+        assert_eq!(agent.target_action_kind, TargetActionKind::Rearm { step: 10.0 });
+        agent.next_exposure(&Tick { time: 0, bid: 0.95, ask: 0.95 });
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        // target reached: closed, rearmed and still active
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 0.96, ask: 0.96 }), 0);
+        assert!(agent.active);
+        assert!(agent.target > 10.0);
+
+        let mut agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, 10.0).with_target_action_fn(|a| {
+            a.tentative_exposure = a.exposure() / 2;
+            a.tentative_exposure
+        });
+        agent.next_exposure(&Tick { time: 0, bid: 0.95, ask: 0.95 });
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 0.96, ask: 0.96 }), 2500);
+    }
+
+    #[test]
+    fn summarize() {
+        let mut agent = GearHedger::symmetric(0.90, 1.10, 0.0100, 0.0100, 10000.0, 100.0);
+        agent.next_exposure(&Tick { time: 0, bid: 0.95, ask: 0.95 });
+        agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: agent.tentative_exposure });
+        agent.agentPL.cum_profit = 25.0;
+
+        let summary = agent.summarize();
+        assert_eq!(summary.name, None);
+        assert_eq!(summary.current_exposure, 5000);
+        assert_eq!(summary.price_range, (0.90, 1.10));
+        assert_eq!(summary.scale, 0.0100);
+        assert!(summary.active);
+        assert_eq!(summary.target_reached_pct, 25.0);
+    }
+
+    #[test]
+    fn sell_increases_short() {
+        let mut pl = AgentPL { exposure: -50, price_average: 1.10, cum_profit: 0.0, unrealized_pl: 0.0, pl_mode: PlMode::Ratio };
+        pl.sell(1.20, 30);
+        assert_eq!(pl.exposure, -80);
+        assert_eq!(pl.cum_profit, 0.0);
+        assert!((pl.price_average - (1.10 * 50.0 + 1.20 * 30.0) / 80.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn paired_hedger() {
+        let long = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        let short = GearHedger::seller(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        let mut pair = PairedHedger::new(long, short);
+
+        // both legs nearly net out at mid range
+        let e = pair.next_exposure(&Tick { time: 0, bid: 1.00, ask: 1.00 });
+        pair.update_on_fill(&OrderFill { price: 1.00, units: e });
+        assert_eq!(e, 1);
+        assert_eq!(pair.long_leg.exposure(), 5000);
+        assert_eq!(pair.short_leg.exposure(), -4999);
+
+        // both legs buy on the way down, a partial fill is shared by their requests
+        let e = pair.next_exposure(&Tick { time: 1, bid: 0.98, ask: 0.98 });
+        assert_eq!(e - pair.exposure(), 2000);
+        pair.update_on_fill(&OrderFill { price: 0.98, units: 1000 });
+        assert_eq!(pair.long_leg.exposure(), 5500);
+        assert_eq!(pair.short_leg.exposure(), -4499);
+        assert_eq!(pair.exposure(), 1001);
+
+        // the pair stays active until both legs reached their target
+        pair.long_leg.deactivate();
+        assert!(pair.is_active());
+        pair.short_leg.deactivate();
+        assert!(!pair.is_active());
+    }
+
+    #[test]
+    fn stale_state() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        assert!(inventory.is_stale(1700000000, 3600));
+
+        inventory.stamp(1700000000);
+        assert!(!inventory.is_stale(1700000000 + 3600, 3600));
+        assert!(inventory.is_stale(1700000000 + 3601, 3600));
+
+        // a weekend down
+        let read: AgentInventory<GearHedger> = serde_json::from_str(&serde_json::to_string(&inventory).unwrap()).unwrap();
+        assert_eq!(read.saved_at, Some(1700000000));
+        assert!(read.is_stale(1700000000 + 2 * 86400, 3600));
+    }
+
+    #[test]
+    fn gear_at_agent_prices() {
+        let mut gear = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        gear.next_exposure(&Tick { time: 0, bid: 1.00, ask: 1.00 });
+        assert_eq!(gear.target_gear(), gear.gear_f.g(1.00));
+        gear.update_on_fill(&OrderFill { price: 1.00, units: gear.tentative_exposure });
+        gear.next_exposure(&Tick { time: 1, bid: 0.98, ask: 0.98 });
+        assert_eq!(gear.gear_at_last_trade(), gear.gear_f.g(1.00));
+        assert_eq!(gear.target_gear(), gear.gear_f.g(0.98));
+    }
+
+    #[test]
+    fn pl_delta() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        let mut long = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        long.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        inventory.agents.insert("long".to_string(), long);
+
+        // the price goes up, the agent sells 1000 of its 5000 units
+        let prev = Tick { time: 0, bid: 1.00, ask: 1.00 };
+        let cur = Tick { time: 1, bid: 1.02, ask: 1.02 };
+        for agent in inventory.agents.values_mut() {
+            let e = agent.next_exposure(&cur);
+            agent.update_on_fill(&OrderFill { price: agent.tentative_price, units: e - agent.exposure() });
+        }
+        assert_eq!(inventory.agents["long"].exposure(), 4000);
+
+        let delta = &inventory.pl_delta(&prev, &cur)["long"];
+        assert!((delta.realized - 1000.0 * 0.02).abs() < 1e-9);
+        assert!((delta.unrealized - 4000.0 * 0.02).abs() < 1e-9);
+        // the split adds up to the PL change of the agent
+        let agent = &inventory.agents["long"];
+        let before = agent.pl_checkpoint.as_ref().unwrap().pl_at_price(prev.bid);
+        assert!((delta.realized + delta.unrealized - (agent.agentPL.pl_at_price(cur.bid) - before)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn try_from_gagent() {
+        let agent = GAgent::Buy { price0: 0.90, price1: 1.10, scale: 0.0010, exposure: 10000.0 };
+        let hedger: GearHedger = agent.clone().try_into().unwrap();
+        assert_eq!(hedger.max_exposure, 10000.0);
+        assert!(Option::<GearHedger>::from(agent).is_some());
+
+        let pair = GAgent::PairSpread {
+            instrument_a: "EUR_USD".to_string(),
+            exposure_a: 10000.0,
+            instrument_b: "GBP_USD".to_string(),
+            exposure_b: -10000.0,
+            scale: 0.0010,
+            target: 10.0,
+        };
+        assert!(matches!(GearHedger::try_from(pair.clone()), Err(AgentBuildError::NotAGearHedger(_))));
+        assert!(Option::<GearHedger>::from(pair).is_none());
+
+        let unknown: GAgent = serde_json::from_str(r#"{"Future":{"price":1.0}}"#).unwrap();
+        assert_eq!(
+            GearHedger::try_from(unknown).unwrap_err(),
+            AgentBuildError::Unknown(r#"{"Future":{"price":1.0}}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn max_consecutive_losses() {
+        let mut gear = GearHedger::seller(0.90, 1.10, 0.0100, 0.0100, 10000.0).with_max_consecutive_losses(2);
+        gear.force_fill(&OrderFill { price: 1.00, units: -5000 });
+        assert_eq!(gear.consecutive_losses, 0);
+
+        // buying back above the average price of the short is a loss
+        gear.tentative_exposure = -4000;
+        gear.update_on_fill(&OrderFill { price: 1.01, units: 1000 });
+        assert_eq!(gear.consecutive_losses, 1);
+        // adding to the short realizes nothing, the count holds
+        gear.tentative_exposure = -5000;
+        gear.update_on_fill(&OrderFill { price: 1.02, units: -1000 });
+        assert_eq!(gear.consecutive_losses, 1);
+        // a profitable close resets it
+        gear.tentative_exposure = -4000;
+        gear.update_on_fill(&OrderFill { price: 0.99, units: 1000 });
+        assert_eq!(gear.consecutive_losses, 0);
+
+        // two losing trades in a row
+        gear.tentative_exposure = -3000;
+        gear.update_on_fill(&OrderFill { price: 1.03, units: 1000 });
+        assert!(gear.is_active());
+        gear.tentative_exposure = -2000;
+        gear.update_on_fill(&OrderFill { price: 1.04, units: 1000 });
+        assert_eq!(gear.consecutive_losses, 2);
+        assert!(!gear.is_active());
+
+        // a grid buying on the way down: the losing closes are separated by adds
+        let mut grid = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0).with_max_consecutive_losses(3);
+        grid.force_fill(&OrderFill { price: 1.00, units: 1000 });
+        for (i, price) in [0.99, 0.97, 0.95].iter().enumerate() {
+            grid.force_fill(&OrderFill { price: *price, units: -500 });
+            assert_eq!(grid.consecutive_losses, i as u32 + 1);
+            grid.force_fill(&OrderFill { price: price - 0.01, units: 1000 });
+        }
+        assert!(!grid.is_active());
+    }
+
+    #[test]
+    fn pl_modes() {
+        // a low price instrument, long 1000 units from 0.02 to 0.03
+        let mut ratio = AgentPL::flat();
+        let mut absolute = AgentPL { pl_mode: PlMode::Absolute, ..ratio.clone() };
+        for pl in [&mut ratio, &mut absolute] {
+            pl.buy(0.02, 1000);
+            pl.sell(0.03, 500);
+        }
+        // ratio: 500 * 50% on the sale, 500 * 100% at 0.04
+        assert!((ratio.cum_profit - 250.0).abs() < 1e-9);
+        assert!((ratio.pl_at_price(0.04) - 750.0).abs() < 1e-9);
+        // absolute: 500 * 0.01 on the sale, 500 * 0.02 at 0.04
+        assert!((absolute.cum_profit - 5.0).abs() < 1e-9);
+        assert!((absolute.pl_at_price(0.04) - 15.0).abs() < 1e-9);
+        assert!((absolute.uPL(0.01) + 5.0).abs() < 1e-9);
+
+        // files without pl_mode keep the ratio form
+        let read: AgentPL = serde_json::from_str(r#"{"exposure":0,"price_average":0.0,"cum_profit":0.0,"unrealized_pl":0.0}"#).unwrap();
+        assert_eq!(read.pl_mode, PlMode::Ratio);
+    }
+
+    #[test]
+    fn warm_restart() {
+        // bought 5000 at 1.00, the file is loaded with the market down at 0.95
+        let mut gear = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        gear.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let mut stale: GearHedger = serde_json::from_str(&serde_json::to_string(&gear).unwrap()).unwrap();
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("stale".to_string(), stale.clone());
+
+        // next buy level left below the market, the agent would wait for 0.94 to buy
+        stale.nextBuyPrice = 0.94;
+        let far = Tick { time: 0, bid: 0.95, ask: 0.9501 };
+        assert_eq!(stale.next_exposure(&far), 5000);
+
+        stale.warm_restart(&far);
+        assert_eq!(stale.nextBuyPrice, far.ask);
+        assert_eq!(stale.next_exposure(&far), 7495);
+
+        // already at the desired exposure: the next levels are one scale away from the tick
+        inventory.tag_untagged("EUR_USD");
+        let mid = Tick { time: 0, bid: 1.00, ask: 1.00 };
+        inventory.warm_restart("EUR_USD", &mid);
+        let agent = &inventory.agents["stale"];
+        assert_eq!((agent.nextBuyPrice, agent.nextSellPrice), (0.99, 1.01));
+    }
+
+    #[test]
+    fn mermaid_diagram() {
+        let mut gear = GearHedger::symmetric(0.90, 1.10, 0.0100, 0.0100, 10000.0, 50.0);
+        gear.force_fill(&OrderFill { price: 0.99, units: 1000 });
+        let diagram = gear.to_mermaid_diagram();
+        let lines: Vec<&str> = diagram.lines().collect();
+        assert_eq!(lines[0], "graph LR");
+        assert_eq!(
+            lines[1],
+            r#"    below["price < 0.90000: 10000"] --> range0["0.90000 to 1.10000: 10000 to -10000"] --> above["price >= 1.10000: -10000"]"#
+        );
+        assert_eq!(lines[2], r#"    state["exposure 1000 at 0.99000<br/>PL 0.00 / target 50.00<br/>active"]"#);
+        assert_eq!(lines[3], r#"    sell(["sell at bid >= 1.00000"]) -.-> state"#);
+        assert_eq!(lines[4], r#"    buy(["buy at ask <= 0.98000"]) -.-> state"#);
+
+        gear.deactivate();
+        assert!(gear.to_mermaid_diagram().contains("<br/>inactive\"]"));
+    }
+
+    #[test]
+    fn from_position() {
+        let position = Position { instrument: "EUR_USD".to_string(), units: 5000, price: Some(1.00), unrealized_pl: 12.5 };
+        let mut gear = GearHedger::from_position(&position, Gear::positive(0.90, 1.10), 0.0100, 1000.0).unwrap();
+        assert!((gear.max_exposure - 10000.0).abs() < 1e-6);
+        assert_eq!((gear.nextBuyPrice, gear.nextSellPrice), (0.99, 1.01));
+        assert_eq!(gear.agentPL.exposure, 5000);
+        assert_eq!(gear.agentPL.price_average, 1.00);
+        assert_eq!(gear.agentPL.unrealized_pl, 12.5);
+        assert_eq!(gear.instrument, "EUR_USD");
+
+        // carries on trading the gear from the position
+        assert_eq!(gear.next_exposure(&Tick { time: 0, bid: 1.005, ask: 1.005 }), 5000);
+        assert_eq!(gear.next_exposure(&Tick { time: 0, bid: 1.02, ask: 1.02 }), 4000);
+
+        // no price to book the position at
+        let flat = Position { instrument: "EUR_USD".to_string(), units: 0, price: None, unrealized_pl: 0.0 };
+        assert!(GearHedger::from_position(&flat, Gear::positive(0.90, 1.10), 0.0100, 1000.0).is_none());
+        let zero = Position { price: Some(0.0), ..position };
+        assert!(GearHedger::from_position(&zero, Gear::positive(0.90, 1.10), 0.0100, 1000.0).is_none());
+    }
+
+    #[test]
+    fn snapshot_restore_diff() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("long".to_string(), GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0));
+        inventory.agents.insert("short".to_string(), GearHedger::seller(0.90, 1.10, 0.0100, 0.0100, 10000.0));
+        let before = inventory.snapshot();
+
+        inventory.agents.get_mut("long").unwrap().force_fill(&OrderFill { price: 1.00, units: 5000 });
+        inventory.agents.remove("short");
+        inventory.agents.insert("flat".to_string(), GearHedger::constant(0.0));
+        let after = inventory.snapshot();
+        assert!(after.taken_at >= before.taken_at);
+
+        assert_eq!(
+            AgentInventory::diff(&before, &after),
+            InventoryDiff { added: vec!["flat".to_string()], removed: vec!["short".to_string()], modified: vec!["long".to_string()] }
+        );
+        assert_eq!(AgentInventory::diff(&after, &after), InventoryDiff::default());
+
+        // undo
+        inventory.restore(&before);
+        assert_eq!(inventory.agents["long"].exposure(), 0);
+        assert!(inventory.agents.contains_key("short") && !inventory.agents.contains_key("flat"));
+        assert_eq!(AgentInventory::diff(&before, &inventory.snapshot()), InventoryDiff::default());
+    }
+
+    #[test]
+    fn level_policy() {
+        for (policy, trades) in [(LevelPolicy::TradeOnTouch, true), (LevelPolicy::TradeOnBreak, false)] {
+            let mut gear = GearHedger::symmetric(0.90, 1.10, 0.0100, 0.0100, 10000.0, 1000.0).with_level_policy(policy);
+            gear.force_fill(&OrderFill { price: 1.00, units: 0 });
+            gear.nextSellPrice = 1.01;
+            gear.nextBuyPrice = 0.99;
+
+            // bid touching the sell level
+            let e = gear.next_exposure(&Tick { time: 0, bid: 1.01, ask: 1.0101 });
+            assert_eq!(e != 0, trades, "{:?} sell", policy);
+            // ask touching the buy level
+            let e = gear.next_exposure(&Tick { time: 1, bid: 0.9899, ask: 0.99 });
+            assert_eq!(e != 0, trades, "{:?} buy", policy);
+            // both policies trade through the levels
+            assert!(gear.next_exposure(&Tick { time: 2, bid: 1.0101, ask: 1.0102 }) < 0);
+            assert!(gear.next_exposure(&Tick { time: 3, bid: 0.9898, ask: 0.9899 }) > 0);
+        }
+    }
+
+    #[test]
+    fn force_close() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        let mut gear = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        gear.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        inventory.agents.insert("long".to_string(), gear);
+
+        let tick = Tick { time: 0, bid: 0.98, ask: 0.9801 };
+        let agent = inventory.agents.get_mut("long").unwrap();
+        // the passive close keeps the agent trading
+        assert_eq!(agent.close(&tick, CloseReason::Manual), -5000);
+        assert!(agent.is_active());
+
+        assert_eq!(agent.force_close(&tick, CloseReason::Manual), -5000);
+        assert_eq!(agent.tentative_price, 0.98);
+        assert!(!agent.is_active());
+        agent.update_on_fill(&OrderFill { price: 0.98, units: -5000 });
+        assert_eq!(agent.exposure(), 0);
+
+        // no position reopened on the next tick, below the buy level
+        assert_eq!(inventory.next_exposure(&Tick { time: 1, bid: 0.95, ask: 0.95 }), 0);
+        assert_eq!(inventory.agents["long"].exposure(), 0);
+    }
+
+    #[test]
+    fn apply_fills() {
+        let fills = [
+            OrderFill { price: 1.00, units: 5000 },
+            OrderFill { price: 0.99, units: 500 },
+            OrderFill { price: 1.01, units: -1500 },
+            OrderFill { price: 1.03, units: -5000 },
+            OrderFill { price: 1.02, units: 500 },
+        ];
+        let mut replayed = GearHedger::symmetric(0.90, 1.10, 0.0100, 0.0100, 10000.0, 1000.0);
+        replayed.apply_fills(&fills);
+
+        let mut live = GearHedger::symmetric(0.90, 1.10, 0.0100, 0.0100, 10000.0, 1000.0);
+        for fill in fills.iter() {
+            live.tentative_price = fill.price;
+            live.tentative_exposure = live.exposure() + fill.units;
+            live.update_on_fill(fill);
+        }
+        assert_eq!(replayed.exposure(), -500);
+        assert_eq!(serde_json::to_value(&replayed).unwrap(), serde_json::to_value(&live).unwrap());
+        assert_eq!((replayed.lastTradePrice, replayed.nextBuyPrice), (1.02, 1.01));
+    }
+
+    #[test]
+    fn build_from_json() {
+        let hedger = build_from_json_str(r#"{"Buy":{"price0":0.9,"price1":1.1,"scale":0.001,"exposure":10000.0}}"#).unwrap();
+        assert_eq!(hedger.max_exposure, 10000.0);
+
+        let mut saved = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        saved.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let loaded = build_from_json_str(&serde_json::to_string(&saved).unwrap()).unwrap();
+        assert_eq!(loaded.exposure(), 5000);
+
+        assert!(matches!(build_from_json_str(r#"{"Future":{"price":1.0}}"#), Err(AgentLoadError::Build(AgentBuildError::Unknown(_)))));
+        assert!(matches!(build_from_json_str("not json"), Err(AgentLoadError::Json(_))));
+    }
+
+    #[test]
+    fn deadband() {
+        // noise of +/- 3 pips around the neutral price, with a 1 pip scale
+        let noise: Vec<Tick> = (0..10)
+            .map(|i| {
+                let p = if i % 2 == 0 { 1.0003 } else { 0.9997 };
+                Tick { time: i, bid: p, ask: p }
+            })
+            .collect();
+        let trades = |gear: &mut GearHedger, ticks: &[Tick]| {
+            let mut count = 0;
+            for tick in ticks {
+                let e = gear.next_exposure(tick);
+                if e != gear.exposure() {
+                    gear.update_on_fill(&OrderFill { price: gear.tentative_price, units: e - gear.exposure() });
+                    count += 1;
+                }
+            }
+            count
+        };
+
+        let mut churning = GearHedger::symmetric(0.90, 1.10, 0.0001, 0.0001, 100000.0, 1000.0);
+        assert_eq!(trades(&mut churning, &noise), 10);
+
+        let mut banded = GearHedger::symmetric(0.90, 1.10, 0.0001, 0.0001, 100000.0, 1000.0).with_deadband(0.0005);
+        assert_eq!(trades(&mut banded, &noise), 0);
+        assert_eq!(banded.exposure(), 0);
+
+        // out of the band the gear applies
+        assert_eq!(trades(&mut banded, &[Tick { time: 10, bid: 0.99, ask: 0.99 }]), 1);
+        assert_eq!(banded.exposure(), 10000);
+    }
+
+    #[test]
+    fn levels_stay_near_gear_domain() {
+        let mut gear = GearHedger::seller(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        // fills far above the domain, the gear is at its -10000 floor
+        gear.apply_fills(&[OrderFill { price: 1.00, units: -5000 }, OrderFill { price: 1.50, units: -5000 }]);
+        assert_eq!(gear.nextSellPrice, 1.11);
+        assert_eq!(gear.nextBuyPrice, 1.49);
+
+        gear.apply_fills(&[OrderFill { price: 0.50, units: 5000 }]);
+        assert_eq!(gear.nextBuyPrice, 0.89);
+        assert_eq!(gear.nextSellPrice, 0.51);
+    }
+
+    #[test]
+    fn gap_scenario() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        let mut long = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0).with_instrument("EUR_USD");
+        long.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let mut short = GearHedger::seller(0.90, 1.10, 0.0100, 0.0100, 10000.0).with_instrument("EUR_USD");
+        short.force_fill(&OrderFill { price: 1.00, units: -2000 });
+        let other = GearHedger::buyer(1.20, 1.30, 0.0100, 0.0100, 10000.0).with_instrument("GBP_USD");
+        inventory.agents.insert("long".to_string(), long);
+        inventory.agents.insert("short".to_string(), short);
+        inventory.agents.insert("other".to_string(), other);
+
+        let ticks = HashMap::from([("EUR_USD".to_string(), Tick { time: 0, bid: 1.00, ask: 1.00 })]);
+        let scenario = inventory.gap_scenario(&ticks, 2.0);
+        let close = |key: &str, pl: f64| assert!((scenario[key] - pl).abs() < 1e-9, "{} {}", key, scenario[key]);
+        close("long/up", 100.0);
+        close("long/down", -100.0);
+        close("short/up", -40.0);
+        close("short/down", 40.0);
+        close("total/up", 60.0);
+        close("total/down", -60.0);
+        close("worst", -60.0);
+        assert!(!scenario.contains_key("other/up"));
+    }
+
+    #[test]
+    fn rounded_json() {
+        assert_eq!(round_significant(1.0649999999, 8), 1.065);
+        assert_eq!(round_significant(-0.000123456789, 3), -0.000123);
+        assert_eq!(round_significant(0.0, 8), 0.0);
+
+        let mut agent = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0);
+        agent.agentPL.cum_profit = 12.345678912345;
+        agent.nextBuyPrice = 1.0649999999;
+        let mut inventory = AgentInventory::new();
+        inventory.agents.insert("a".to_string(), agent.clone());
+        inventory.pl = 0.1 + 0.2;
+
+        let json = inventory.to_json().unwrap();
+        assert!(json.contains("1.065"));
+        assert!(json.contains("12.345679"));
+        assert!(!json.contains("0.30000000000000004"));
+        assert!(!json.contains("1.0649999999"));
+        assert!(inventory.to_json_with_precision(4).unwrap().contains("12.35"));
+        // in memory values are untouched
+        assert_eq!(inventory.pl, 0.1 + 0.2);
+
+        let back: AgentInventory<GearHedger> = serde_json::from_str(&json).unwrap();
+        let read = &back.agents["a"];
+        assert_eq!(back.pl, 0.3);
+        assert_eq!(read.nextBuyPrice, 1.065);
+        assert_eq!(read.agentPL.cum_profit, 12.345679);
+        assert_eq!(read.exposure(), agent.exposure());
+        assert_eq!(read.max_exposure, agent.max_exposure);
+    }
+
+    #[test]
+    fn rounded_json_unlimited_target() {
+        assert_eq!(round_significant(f64::MAX, 8), f64::MAX);
+        assert_eq!(round_significant(-f64::MAX, 8), -f64::MAX);
+
+        let agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        assert_eq!(agent.target, f64::MAX);
+        let mut inventory = AgentInventory::new();
+        inventory.agents.insert("a".to_string(), agent);
+        let back: AgentInventory<GearHedger> = serde_json::from_str(&inventory.to_json().unwrap()).unwrap();
+        let read = &back.agents["a"];
+        assert_eq!(read.target, f64::MAX);
+        assert!(read.to_mermaid_diagram().contains("none"));
+    }
+
+    #[test]
+    fn theoretical_gain_and_loss() {
+        // long from 1.0 at 0.90 to flat at 1.10
+        let buyer = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        assert!((buyer.max_theoretical_gain() - 1000.0).abs() < 1e-9);
+        assert!((buyer.max_theoretical_loss() + 1000.0).abs() < 1e-9);
+
+        let seller = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        assert!((seller.max_theoretical_gain() - 1000.0).abs() < 1e-9);
+
+        // long 1.0 at 0.90 to flat at 1.0, then short to 1.0 at 1.10: 0.05 on either side
+        let symmetric = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, 1000.0);
+        assert!((symmetric.max_theoretical_gain() - 500.0).abs() < 1e-9);
+        assert!((symmetric.max_theoretical_loss() + 500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn symmetric_with_pip_target() {
+        // 50 pips at 1.0 per pip for 10000 EUR_USD
+        let agent = GearHedger::symmetric_with_pip_target(0.99, 1.01, 0.0010, 10000.0, 50.0, 1.0);
+        assert!((agent.target - 50.0).abs() < 1e-12);
+        assert_eq!((agent.scaleUp, agent.scaleDown), (0.0010, 0.0010));
+        let plain = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 50.0);
+        assert_eq!(serde_json::to_value(&agent).unwrap(), serde_json::to_value(&plain).unwrap());
+    }
+
+    #[test]
+    fn checked_increase_by() {
+        let mut pl = GearHedger::constant(1.0).agentPL;
+        assert_eq!(pl.checked_increase_by(f64::NAN, 100), Err(MathError::NonFinite("price")));
+        assert_eq!(pl.checked_increase_by(1.0, 0), Err(MathError::ZeroExposure));
+        assert_eq!(pl.checked_increase_by(0.0, 100), Err(MathError::NonFinite("ratio PL")));
+        assert_eq!(pl.exposure, 0);
+
+        assert_eq!(pl.checked_increase_by(1.0, 100), Ok(()));
+        assert_eq!(pl.checked_increase_by(1.2, 100), Ok(()));
+        assert_eq!(pl.exposure, 200);
+        assert!((pl.price_average - 1.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn merge_over_exposed() {
+        // long at low prices, and a trend follower long at high prices: the merged gear is flat at the low end
+        let mut buyer = GearHedger::segment(0.90, 10000.0, 1.10, 0.0, 0.0010, 10.0);
+        buyer.force_fill(&OrderFill { price: 0.91, units: 9000 });
+        let mut follower = GearHedger::segment(0.90, -10000.0, 1.10, 10000.0, 0.0010, 10.0);
+        follower.force_fill(&OrderFill { price: 1.08, units: 8000 });
+        assert_eq!(buyer.exposure() + follower.exposure(), 17000);
+
+        assert_eq!(
+            buyer.merge_flat_with(&follower, MergeOverflow::Error).err(),
+            Some(InventoryError::ExposureOverflow(17000, 10000.0))
+        );
+
+        let merged = buyer.merge_flat(&follower).unwrap();
+        assert_eq!(merged.exposure(), 17000);
+        assert_eq!(merged.max_exposure, 17000.0);
+        assert_eq!(merged.gear_f.g_0, 0.0);
+        assert_eq!(merged.gear_f.g_n, 1.0);
+
+        // within the merged range, nothing is scaled
+        let merged = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0)
+            .merge_flat_with(&GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0), MergeOverflow::Error)
+            .unwrap();
+        assert_eq!(merged.max_exposure, 10000.0);
+    }
+
+    #[test]
+    fn conflicting_agents() {
+        let mut inventory = AgentInventory::new();
+        inventory.agents.insert("buyer".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        inventory.agents.insert("seller".to_string(), GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        // long below 1.00, overlapping the buyer
+        inventory.agents.insert("symmetric".to_string(), GearHedger::symmetric(0.98, 1.02, 0.0010, 0.0010, 10000.0, 1000.0).with_instrument("EUR_USD"));
+        // long above the buyer domain
+        inventory.agents.insert("high".to_string(), GearHedger::buyer(1.20, 1.30, 0.0010, 0.0010, 10000.0));
+        // same domain as the buyer, another instrument
+        inventory.agents.insert("gbp".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0).with_instrument("GBP_USD"));
+        // untagged agents may trade any instrument
+        inventory.agents.get_mut("buyer").unwrap().instrument = "EUR_USD".to_string();
+
+        assert_eq!(
+            agents_conflicting(&inventory),
+            vec![
+                ("buyer".to_string(), "symmetric".to_string()),
+                ("seller".to_string(), "symmetric".to_string()),
+            ]
+        );
+
+        // a seller only short above the buyer zero price does not conflict
+        let mut inventory = AgentInventory::new();
+        inventory.agents.insert("a".to_string(), GearHedger::segment(0.90, 10000.0, 1.00, 0.0, 0.0010, 10.0));
+        inventory.agents.insert("b".to_string(), GearHedger::segment(0.95, 0.0, 1.10, -10000.0, 0.0010, 10.0));
+        assert!(agents_conflicting(&inventory).is_empty());
+    }
+
+    #[test]
+    fn flat_pl() {
+        let mut pl = AgentPL::flat();
+        assert!(pl.is_flat());
+        assert_eq!((pl.exposure, pl.cum_profit, pl.unrealized_pl), (0, 0.0, 0.0));
+        assert_eq!(pl.pl_mode, PlMode::Ratio);
+
+        pl.buy(1.00, 1000);
+        assert!(!pl.is_flat());
+        pl.sell(1.10, 1000);
+        assert!(pl.is_flat());
+        assert!((pl.cum_profit - 100.0).abs() < 1e-9);
+        pl.sell(1.10, 500);
+        assert!(!pl.is_flat());
+    }
+
+    #[test]
+    fn exposure_series() {
+        let agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        let bars = crate::hff::backtest::tests::bars(&[1.00, 0.98, 0.98, 1.02, 1.00]);
+        assert_eq!(agent.exposure_series(&bars), vec![5000, 6000, 6000, 4000, 5000]);
+        // the agent itself did not trade
+        assert_eq!(agent.exposure(), 0);
+        assert!(agent.exposure_series(&[]).is_empty());
+    }
+
+    #[test]
+    fn inventory_close() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        let mut long = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        long.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let mut short = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        short.force_fill(&OrderFill { price: 1.02, units: -6000 });
+        inventory.agents.insert("long".to_string(), long);
+        inventory.agents.insert("short".to_string(), short);
+
+        let tick = Tick { time: 0, bid: 1.01, ask: 1.0102 };
+        // one order of 1000 flattens the net -1000
+        assert_eq!(inventory.close(&tick, CloseReason::DayEnd), 1000);
+        assert_eq!(inventory.agents["long"].tentative_price, 1.01);
+        assert_eq!(inventory.agents["short"].tentative_price, 1.0102);
+        assert_eq!(inventory.agents["long"].tentative_exposure, 0);
+        assert_eq!(inventory.agents["short"].tentative_exposure, 0);
+    }
+
+    #[test]
+    fn merge_operators() {
+        let mut buyer = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        buyer.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let mut seller = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        seller.force_fill(&OrderFill { price: 1.02, units: -6000 });
+        let merged = buyer.merge_flat(&seller).unwrap();
+
+        let sum = buyer.clone() + seller.clone();
+        assert_eq!(serde_json::to_value(&sum).unwrap(), serde_json::to_value(&merged).unwrap());
+
+        let mut portfolio = buyer.clone();
+        portfolio += &seller;
+        assert_eq!(serde_json::to_value(&portfolio).unwrap(), serde_json::to_value(&merged).unwrap());
+        assert_eq!(portfolio.exposure(), -1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot merge the agents")]
+    fn merge_operator_instruments() {
+        let mut eur = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0).with_instrument("EUR_USD");
+        eur += &GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0).with_instrument("GBP_USD");
+    }
+
+    #[test]
+    fn exposure_sensitivity() {
+        let agent = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0);
+        // from +10000 to -10000 over 0.02
+        assert!((agent.exposure_sensitivity(1.00) + 1_000_000.0).abs() < 1e-6);
+        let h = 1e-7;
+        let finite_difference = (agent.gear_f.g(1.005 + h) - agent.gear_f.g(1.005 - h)) / (2.0 * h) * agent.max_exposure;
+        assert!((agent.exposure_sensitivity(1.005) - finite_difference).abs() < 1e-3);
+        assert_eq!(agent.exposure_sensitivity(1.02), 0.0);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_round_trip() {
+        use crate::hff::agents::GAgent;
+
+        let toml = "[CL]\ndirection = 1\nprice = 1.08\nscale = 0.001\nsize = 1000.0\nimax = 10.0\n";
+        let agent = GAgent::from_toml(toml).unwrap();
+        assert!(matches!(agent, GAgent::CL { direction: 1, i0: None, target: None, .. }));
+        let written = agent.to_toml();
+        assert!(GAgent::from_toml(&written).is_ok());
+        // the same fields as the JSON, less the nulls
+        let from_toml: serde_json::Value = toml::from_str(&written).unwrap();
+        let mut from_json = serde_json::to_value(&agent).unwrap();
+        from_json["CL"].as_object_mut().unwrap().retain(|_, v| !v.is_null());
+        assert_eq!(from_toml, from_json);
+        assert!(GAgent::from_toml("[CL]\ndirection = 1\n").is_err());
+
+        let mut hedger = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0);
+        hedger.next_exposure(&Tick { time: 0, bid: 0.995, ask: 0.995 });
+        hedger.update_on_fill(&OrderFill { price: 0.995, units: hedger.tentative_exposure });
+        let mut inventory = AgentInventory::new();
+        inventory.agents.insert("a".to_string(), hedger.clone());
+        inventory.pl = 1.5;
+        let back = AgentInventory::<GearHedger>::from_toml(&inventory.to_toml()).unwrap();
+        assert_eq!(back.pl, 1.5);
+        assert_eq!(back.agents["a"].exposure(), hedger.exposure());
+        assert_eq!(serde_json::to_value(&back).unwrap(), serde_json::to_value(&inventory).unwrap());
+    }
+
+    #[test]
+    fn fixed_notional() {
+        let mut agent = GAgent::FixedNotional { notional: 10000.0, price0: 0.8, price1: 1.2, scale: 0.001 }.build().unwrap();
+        assert_eq!(agent.max_exposure, 12500.0);
+        for price in [0.8, 0.8137, 0.9, 1.0, 1.0503, 1.1999, 1.2] {
+            let units = agent.gear_exposure(price);
+            // truncated to a unit
+            assert!((units as f64 * price - 10000.0).abs() <= price, "{} {}", price, units);
+        }
+        // units shrink as the price rises, held outside the range
+        assert!(agent.gear_exposure(1.1) < agent.gear_exposure(0.9));
+        assert_eq!(agent.gear_exposure(0.5), 12500);
+        assert_eq!(agent.gear_exposure(1.5), 8333);
+
+        // trading down through the grid
+        let mut price = 1.2;
+        while price > 0.85 {
+            price -= 0.01;
+            let tick = Tick { time: 0, bid: price, ask: price };
+            let units = agent.next_exposure(&tick);
+            agent.update_on_fill(&OrderFill { price, units });
+            assert!((agent.exposure() as f64 * price - 10000.0).abs() <= price, "{}", price);
+        }
+
+        let short = GearHedger::fixed_notional(0.8, 1.2, 0.001, -10000.0);
+        assert_eq!(short.gear_exposure(1.0), -10000);
+    }
+
+    #[test]
+    fn inventory_target_exposure() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("buyer".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        inventory.agents.insert("seller".to_string(), GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 4000.0));
+        let mut inactive = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        inactive.deactivate();
+        inventory.agents.insert("inactive".to_string(), inactive);
+
+        let tick = Tick { time: 0, bid: 1.0, ask: 1.0 };
+        let expected = inventory.agents["buyer"].clone().target_exposure(&tick)
+            + inventory.agents["seller"].clone().target_exposure(&tick);
+        assert!((expected - (5000 - 2000)).abs() <= 1);
+        assert_eq!(inventory.target_exposure(&tick), expected);
+        // next_exposure of the inventory agrees on the same tick
+        assert_eq!(inventory.next_exposure(&tick), expected);
+    }
+
+    #[test]
+    fn next_exposure_if_changed() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("buyer".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        let tick = Tick { time: 0, bid: 1.0, ask: 1.0 };
+        let exposure = inventory.next_exposure_if_changed(&tick).unwrap();
+        assert!((exposure - 5000).abs() <= 1);
+        inventory.update_on_fill(&OrderFill { price: 1.0, units: exposure });
+        assert_eq!(inventory.exposure(), exposure);
+
+        // same tick, and a move within the grid step
+        assert_eq!(inventory.next_exposure_if_changed(&tick), None);
+        assert_eq!(inventory.next_exposure_if_changed(&Tick { time: 1, bid: 1.0005, ask: 1.0005 }), None);
+        assert!(inventory.next_exposure_if_changed(&Tick { time: 2, bid: 0.99, ask: 0.99 }).unwrap() > exposure);
+    }
+
+    #[test]
+    fn fork_flat() {
+        let mut live = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0).with_max_spread(Some(0.0005));
+        let tick = Tick { time: 0, bid: 0.995, ask: 0.995 };
+        live.next_exposure(&tick);
+        live.update_on_fill(&OrderFill { price: 0.995, units: live.tentative_exposure });
+        live.next_exposure(&Tick { time: 1, bid: 1.0, ask: 1.0 });
+        live.update_on_fill(&OrderFill { price: 1.0, units: live.tentative_exposure - live.exposure() });
+        assert!(live.agentPL.cum_profit > 0.0);
+
+        let fork = live.fork_flat();
+        assert_eq!(serde_json::to_value(&fork.gear_f).unwrap(), serde_json::to_value(&live.gear_f).unwrap());
+        assert_eq!((fork.max_exposure, fork.scaleUp, fork.target, fork.max_spread), (live.max_exposure, live.scaleUp, live.target, live.max_spread));
+        assert_eq!(fork.exposure(), 0);
+        assert_eq!(fork.agentPL.cum_profit, 0.0);
+        assert!(fork.agentPL.is_flat());
+        assert!((fork.lastTradePrice - 1.00).abs() < 1e-12);
+        assert_eq!(fork.nextBuyPrice, fork.lastTradePrice);
+
+        // the live agent is untouched
+        assert!(live.agentPL.cum_profit > 0.0);
+    }
+
+    #[test]
+    fn checked_exposure() {
+        let agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 1e18);
+        let half = agent.checked_exposure_at_price(1.0).unwrap();
+        assert!((half as f64 / 5e17 - 1.0).abs() < 1e-9);
+        assert_eq!(agent.checked_exposure_at_price(0.90), Some(1_000_000_000_000_000_000));
+
+        let mut huge = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 1e19);
+        assert!(huge.checked_exposure_at_price(1.0).is_some());
+        assert_eq!(huge.checked_exposure_at_price(0.90), None);
+        assert_eq!(GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 1e20).checked_exposure_at_price(1.10), None);
+
+        // the exposure is kept, nothing is pending
+        let tick = Tick { time: 0, bid: 0.90, ask: 0.90 };
+        assert_eq!(huge.target_exposure(&tick), 0);
+        assert_eq!(huge.tentative_exposure, 0);
+        assert!(huge.target_exposure(&Tick { time: 1, bid: 1.0, ask: 1.0 }) > 0);
+    }
+
+    #[test]
+    fn force_fill() {
+        let mut forced = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        forced.force_fill(&OrderFill { price: 1.00, units: 1234 });
+        // booked as is, whatever the gear says at the price
+        assert_eq!(forced.exposure(), 1234);
+        assert_eq!(forced.agentPL.price_average, 1.00);
+
+        // sized by the gear at the fill price
+        let mut sized = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        let mut expected = sized.clone();
+        let units = expected.next_exposure(&Tick { time: 0, bid: 1.00, ask: 1.00 });
+        sized.next_exposure_and_fill(&OrderFill { price: 1.00, units });
+        assert!((sized.exposure() - 5000).abs() <= 1);
+        assert_eq!(sized.exposure(), units);
+    }
+
+    #[test]
+    fn volatility_scaled() {
+        let fill_all = |agent: &mut VolatilityScaledAgent, prices: &[f64]| {
+            for price in prices {
+                agent.next_exposure_and_fill(&OrderFill { price: *price, units: 0 });
+            }
+        };
+        let inner = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, 1000.0);
+
+        let mut agent = VolatilityScaledAgent::new(inner.clone(), 0.001, 4);
+        fill_all(&mut agent, &[1.0, 1.001]);
+        // a single return, no volatility yet
+        assert_eq!(agent.realized_vol(), None);
+        assert_eq!(agent.inner.max_exposure, 10000.0);
+
+        let mut calm = VolatilityScaledAgent::new(inner.clone(), 0.001, 4);
+        fill_all(&mut calm, &[1.0, 1.001, 1.0, 1.001, 1.0]);
+        let mut wild = VolatilityScaledAgent::new(inner, 0.001, 4);
+        fill_all(&mut wild, &[1.0, 1.01, 1.0, 1.01, 1.0]);
+        assert_eq!(calm.vol_window.len(), 4);
+        let calm_vol = calm.realized_vol().unwrap();
+        let wild_vol = wild.realized_vol().unwrap();
+        assert!(wild_vol > 9.0 * calm_vol);
+        assert!((calm.inner.max_exposure - 10000.0 * 0.001 / calm_vol).abs() < 1e-6);
+        assert!((wild.inner.max_exposure - 10000.0 * 0.001 / wild_vol).abs() < 1e-6);
+        assert!(wild.inner.max_exposure < calm.inner.max_exposure / 9.0);
+
+        // the window only keeps the last lookback returns: the volatility comes back down
+        fill_all(&mut wild, &[1.001, 1.0, 1.001, 1.0, 1.001]);
+        assert!((wild.realized_vol().unwrap() - calm_vol).abs() < 1e-4);
+    }
+
+    #[test]
+    fn volatility_target() {
+        let mut target = VolTarget::new(2.0, 3);
+        target.push(1.0);
+        target.push(1.01);
+        assert_eq!(target.volatility(), None);
+        for p in [1.0, 1.01, 1.0, 1.01] {
+            target.push(p);
+        }
+        assert_eq!(target.prices.len(), 4);
+
+        // the target widens with the volatility of the ticks fed
+        let agent = GearHedger::symmetric(0.90, 1.10, 0.0010, 0.0010, 10000.0, 1000.0).with_volatility_target(2.0, 4);
+        assert_eq!(agent.effective_target(), 1000.0);
+        let feed = |amplitude: f64| {
+            let mut agent = agent.clone();
+            for i in 0..10 {
+                let price = if i % 2 == 0 { 1.0 } else { 1.0 + amplitude };
+                agent.next_exposure(&Tick { time: i, bid: price, ask: price });
+            }
+            agent
+        };
+        let calm = feed(0.0005);
+        let wild = feed(0.0050);
+        let vol = calm.vol_target.as_ref().unwrap().volatility().unwrap();
+        assert!((calm.effective_target() - 2.0 * vol * 10000.0).abs() < 1e-9);
+        assert!(wild.effective_target() > 9.0 * calm.effective_target());
+        assert_eq!(wild.summarize().target, wild.effective_target());
+
+        // the buffer is kept in the file
+        let json = serde_json::to_string(&wild).unwrap();
+        let back: GearHedger = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.effective_target(), wild.effective_target());
+        assert!(!serde_json::to_string(&GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0)).unwrap().contains("vol_target"));
+    }
+
+    #[test]
+    fn drawdown_at_price() {
+        // long below 1.00, short above
+        let agent = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0);
+        assert!((agent.best_price() - 1.00).abs() < 1e-12);
+        assert!(agent.drawdown_at_price(1.00).abs() < 1e-9);
+        // a move away from the zero price costs the integral of the gear: 10000 * 0.01 / 2 at the limits
+        assert!((agent.drawdown_at_price(1.01) - 50.0).abs() < 1e-6);
+        assert!((agent.drawdown_at_price(0.99) - 50.0).abs() < 1e-6);
+        assert!(agent.drawdown_at_price(1.005) < agent.drawdown_at_price(1.01));
+
+        // a buyer is at its best at the top of its range, with realized PL carried along
+        let mut buyer = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        buyer.agentPL.cum_profit = 12.0;
+        assert!((buyer.best_price() - 1.10).abs() < 1e-12);
+        assert!((buyer.pl_at_price(1.10) - 12.0).abs() < 1e-9);
+        let at_bottom = buyer.drawdown_at_price(0.90);
+        assert!((at_bottom - 10000.0 * 0.20 / 2.0).abs() < 1e-6);
+        assert_eq!(buyer.drawdown_at_price(1.10), 0.0);
+    }
+
+    #[test]
+    fn steady_state_turnover() {
+        let agent = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0);
+        // a volatility of 10 steps a year ends about 8 steps away from the start
+        let turnover = agent.steady_state_turnover(0.01);
+        assert!((turnover - 10.0 * (2.0 / std::f64::consts::PI).sqrt()).abs() < 1e-9);
+        // half the step, twice the steps
+        let fine = GearHedger::symmetric(0.99, 1.01, 0.0005, 0.0005, 10000.0, 1000.0);
+        assert!((fine.steady_state_turnover(0.01) - 2.0 * turnover).abs() < 1e-9);
+        assert_eq!(GearHedger::symmetric(0.99, 1.01, 0.0, 0.0, 10000.0, 1000.0).steady_state_turnover(0.01), 0.0);
+    }
+
+    #[test]
+    fn golang_import() {
+        let golang = r#"[
+            {"Direction": 1, "Price": 1.0580, "Scale": 0.0010, "Size": 1000, "Imax": 10},
+            {"Direction": -1, "Price": 1.0700, "Scale": 0.0010, "Size": 500, "I0": 2, "Imax": 8, "Target": 5},
+            {"Direction": 1, "Price0": 1.0500, "Scale": 0.0020, "Size": 1000, "Imax": 5}
+        ]"#;
+        let inventory = import_golang(golang).unwrap();
+        let mut names: Vec<&String> = inventory.agents.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["CL-0", "CL-1", "Coastline-2"]);
+
+        // the same agents as the Rust configurations
+        let cl = GAgent::CL { direction: -1, price: 1.07, scale: 0.001, size: 500.0, i0: Some(2.0), imax: 8.0, target: Some(5.0) }.build().unwrap();
+        assert_eq!(serde_json::to_value(&inventory.agents["CL-1"]).unwrap(), serde_json::to_value(&cl).unwrap());
+        let coastline = GAgent::Coastline { direction: 1, price0: 1.05, scale: 0.002, size: 1000.0, imax: 5.0 }.build().unwrap();
+        assert_eq!(inventory.agents["Coastline-2"].max_exposure, coastline.max_exposure);
+
+        // the output is a valid inventory file
+        let back: AgentInventory<GearHedger> = serde_json::from_str(&inventory.to_json().unwrap()).unwrap();
+        assert_eq!(back.agents.len(), 3);
+        assert_eq!(back.agents["CL-0"].max_exposure, 10000.0);
+
+        assert!(matches!(import_golang(r#"[{"Direction": 1, "Scale": 0.001, "Size": 1000, "Imax": 10}]"#), Err(AgentLoadError::Json(_))));
+        assert!(matches!(import_golang("{}"), Err(AgentLoadError::Json(_))));
+    }
+
+    #[test]
+    fn rebalance() {
+        let tag = |mut agent: GearHedger| { agent.instrument = "EUR_USD".to_string(); agent };
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        let mut long = tag(GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        long.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let mut short = tag(GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        short.force_fill(&OrderFill { price: 1.02, units: -3000 });
+        let mut other = GearHedger::seller(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        other.instrument = "GBP_USD".to_string();
+        other.force_fill(&OrderFill { price: 1.20, units: -4000 });
+        inventory.agents.insert("long".to_string(), long);
+        inventory.agents.insert("short".to_string(), short);
+        inventory.agents.insert("other".to_string(), other);
+
+        let tick = Tick { time: 0, bid: 1.0099, ask: 1.0101 };
+        let gross = |inventory: &AgentInventory<GearHedger>| inventory.agents.values().map(|a| a.exposure().abs()).sum::<i64>();
+        let pl = |inventory: &AgentInventory<GearHedger>, name: &str| inventory.agents[name].agentPL.pl_at_price(1.01);
+        let (pl_long, pl_short) = (pl(&inventory, "long"), pl(&inventory, "short"));
+        let (net, gross_before) = (inventory.exposure(), gross(&inventory));
+        let mut plain = inventory.agents.clone();
+
+        assert_eq!(inventory.rebalance("EUR_USD", &tick), 3000);
+        assert_eq!(inventory.agents["long"].exposure(), 2000);
+        assert_eq!(inventory.agents["short"].exposure(), 0);
+        assert_eq!(inventory.agents["short"].tentative_exposure, 0);
+        // the other instrument is left alone
+        assert_eq!(inventory.agents["other"].exposure(), -4000);
+        assert_eq!(inventory.exposure(), net);
+        assert_eq!(gross(&inventory), gross_before - 6000);
+        // PL at the mid is unchanged, the transferred units are realized
+        assert!((pl(&inventory, "long") - pl_long).abs() < 1e-9);
+        assert!((pl(&inventory, "short") - pl_short).abs() < 1e-9);
+        assert!((inventory.agents["short"].agentPL.cum_profit - 3000.0 * (1.0 - 1.01 / 1.02)).abs() < 1e-9);
+
+        // nothing left to offset
+        assert_eq!(inventory.rebalance("EUR_USD", &tick), 0);
+
+        // the grids are anchored at the mid: no trade within a scale of it
+        assert_eq!((inventory.agents["long"].nextBuyPrice, inventory.agents["long"].nextSellPrice), (1.01 - 0.0010, 1.01 + 0.0010));
+        let near = Tick { time: 1, bid: 1.0105, ask: 1.0105 };
+        for name in ["long", "short"] {
+            let mut agent = inventory.agents[name].clone();
+            assert_eq!(agent.next_exposure(&near), agent.exposure());
+        }
+
+        // next crossing: each agent is back to its gear exposure,
+        // the net exposure is the one without rebalance
+        let crossing = Tick { time: 2, bid: 1.03, ask: 1.03 };
+        let mut targets = (0, 0);
+        for name in ["long", "short"] {
+            let agent = inventory.agents.get_mut(name).unwrap();
+            targets.0 += agent.next_exposure(&crossing);
+            assert_eq!(agent.tentative_exposure, agent.gear_exposure(1.03));
+            targets.1 += plain.get_mut(name).unwrap().next_exposure(&crossing);
+        }
+        assert_eq!(targets.0, targets.1);
+    }
+
+    #[test]
+    fn max_spread() {
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0).with_max_spread(Some(0.0005));
+        agent.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        let mut reference = agent.clone();
+
+        // wide spread: no trade, the position is marked at the bid
+        let wide = Tick { time: 0, bid: 0.9500, ask: 0.9520 };
+        assert_eq!(agent.next_exposure(&wide), 5000);
+        assert_eq!(agent.tentative_exposure, 5000);
+        assert!((agent.agentPL.unrealized_pl - 5000.0 * (0.95 / 1.00 - 1.0)).abs() < 1e-9);
+
+        // normal spread: trades as without the filter
+        let normal = Tick { time: 1, bid: 0.9500, ask: 0.9502 };
+        let units = agent.next_exposure(&normal);
+        assert!(units > 5000);
+        assert_eq!(units, reference.next_exposure(&normal));
+        assert_eq!(Tick { time: 0, bid: 1.0, ask: 1.0002 }.spread(), 1.0002 - 1.0);
+    }
+
+    #[test]
+    fn close_reason() {
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        agent.force_fill(&OrderFill { price: 1.00, units: 5000 });
+        assert_eq!(agent.last_close_reason, None);
+
+        let tick = Tick { time: 0, bid: 1.01, ask: 1.0102 };
+        let units = agent.close(&tick, CloseReason::DayEnd);
+        agent.update_on_fill(&OrderFill { price: tick.bid, units });
+        assert!(agent.agentPL.is_flat());
+        // kept after the closing fill, and in the file
+        assert_eq!(agent.last_close_reason, Some(CloseReason::DayEnd));
+        let json = serde_json::to_string(&agent).unwrap();
+        assert_eq!(serde_json::from_str::<GearHedger>(&json).unwrap().last_close_reason, Some(CloseReason::DayEnd));
+
+        // cleared when the agent trades again
+        agent.force_fill(&OrderFill { price: 1.00, units: 1000 });
+        assert_eq!(agent.last_close_reason, None);
+
+        // the target action is a close on target
+        let mut agent = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1.0);
+        agent.force_fill(&OrderFill { price: 0.995, units: 5000 });
+        assert_eq!(agent.next_exposure(&Tick { time: 0, bid: 1.0, ask: 1.0 }), 0);
+        assert_eq!(agent.last_close_reason, Some(CloseReason::TargetReached));
+    }
 }
@@ -0,0 +1,72 @@
+use serde::{Serialize,Deserialize};
+
+use super::agents::GearHedger;
+use super::backtest::backtest;
+use super::quote::Bar;
+
+// performance of one agent of a parameter sweep, with the parameters of the agent
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SweepResult {
+    pub scale: f64,
+    pub exposure: f64,
+    pub price_range: (f64, f64),
+    pub total_pl: f64,
+    pub trade_count: u64,
+    pub max_drawdown: f64,
+}
+
+// runs backtests of agents built over a range of parameters
+pub struct GearAnalyzer;
+
+impl GearAnalyzer {
+
+    fn run(bars: &[Bar], mut agent: GearHedger) -> SweepResult {
+        let result = backtest(&mut agent, bars);
+        SweepResult {
+            scale: agent.scaleUp,
+            exposure: agent.max_exposure,
+            price_range: (agent.gear_f.p_0, agent.gear_f.p_n),
+            total_pl: result.total_pl,
+            trade_count: result.trade_count,
+            max_drawdown: result.max_drawdown,
+        }
+    }
+
+    pub fn sweep_scale(bars: &[Bar], agent_factory: impl Fn(f64) -> GearHedger, scales: &[f64]) -> Vec<SweepResult> {
+        scales.iter().map(|s| Self::run(bars, agent_factory(*s))).collect()
+    }
+
+    pub fn sweep_exposure(bars: &[Bar], agent_factory: impl Fn(f64) -> GearHedger, exposures: &[f64]) -> Vec<SweepResult> {
+        exposures.iter().map(|e| Self::run(bars, agent_factory(*e))).collect()
+    }
+
+    pub fn sweep_price_range(bars: &[Bar], agent_factory: impl Fn(f64, f64) -> GearHedger, ranges: &[(f64, f64)]) -> Vec<SweepResult> {
+        ranges.iter().map(|r| Self::run(bars, agent_factory(r.0, r.1))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::agents::GearHedger;
+    use super::super::backtest::tests::bars;
+    use super::GearAnalyzer;
+
+    #[test]
+    fn sweeps() {
+        let bars = bars(&[1.00, 0.99, 0.98, 0.99, 1.00, 1.01, 1.00]);
+
+        let results = GearAnalyzer::sweep_scale(&bars, |s| GearHedger::symmetric(0.95, 1.05, s, s, 10000.0, f64::MAX), &[0.005, 0.01, 0.05]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].scale, 0.005);
+        // a scale larger than the moves trades only once
+        assert_eq!(results[2].trade_count, 1);
+        assert!(results[1].trade_count > results[2].trade_count);
+
+        let results = GearAnalyzer::sweep_exposure(&bars, |e| GearHedger::symmetric(0.95, 1.05, 0.01, 0.01, e, f64::MAX), &[1000.0, 2000.0]);
+        assert_eq!(results[1].exposure, 2000.0);
+        assert!((results[1].total_pl - 2.0 * results[0].total_pl).abs() < 0.01);
+
+        let results = GearAnalyzer::sweep_price_range(&bars, |p0, p1| GearHedger::symmetric(p0, p1, 0.01, 0.01, 10000.0, f64::MAX), &[(0.95, 1.05), (0.90, 1.10)]);
+        assert_eq!(results[1].price_range, (0.90, 1.10));
+    }
+}
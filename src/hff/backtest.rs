@@ -0,0 +1,833 @@
+use serde::{Serialize,Deserialize};
+use serde_json::{json, Value};
+
+use super::agents::{GearHedger, Agent, AgentPL, CloseReason};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use super::account::OrderFill;
+use super::quote::{Bar, Tick};
+
+// outcome of a GearHedger run over a series of bars
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BacktestResult {
+    // realized + unrealized PL at the last bar
+    pub total_pl: f64,
+    pub realized_pl: f64,
+    pub trade_count: u64,
+    // largest drop of the equity from its previous peak
+    pub max_drawdown: f64,
+    pub final_exposure: i64,
+    // equity (realized + unrealized PL) after each bar
+    pub equity: Vec<(u64, f64)>,
+    // (time, price, units) of every fill
+    #[serde(default)]
+    pub trades: Vec<(u64, f64, i64)>,
+    // time of the bar the account was stopped out at, the agent is flattened there
+    #[serde(default)]
+    pub stopped_out: Option<u64>,
+    // state of the agent after each bar
+    #[serde(default)]
+    pub bars: Vec<BarResult>,
+    // absolute exposure times the seconds it was held, from one bar to the next
+    #[serde(default)]
+    pub exposure_seconds: f64,
+}
+
+// a bar (mid prices) and the agent after trading it
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct BarResult {
+    pub time: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub exposure: i64,
+    pub realized_pl: f64,
+    // realized + unrealized
+    pub cumulative_pl: f64,
+    pub unrealized_pl: f64,
+}
+
+// an entry fill matched with an exit fill, units are signed by the side of the entry
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RoundTrip {
+    pub entry_time: u64,
+    pub entry_price: f64,
+    pub exit_time: u64,
+    pub exit_price: f64,
+    pub units: i64,
+    pub holding_time: u64,
+    // same PL as the agents: units * (exit / entry - 1)
+    pub pl: f64,
+}
+
+// broker margin rules: the position is liquidated when equity falls below
+// maintenance_fraction of the margin used, |exposure| * price / leverage
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MarginConfig {
+    // account balance at the start of the backtest
+    pub balance: f64,
+    pub leverage: f64,
+    pub maintenance_fraction: f64,
+}
+
+impl MarginConfig {
+    pub fn is_margin_call(&self, equity: f64, exposure: i64, price: f64) -> bool {
+        let used_margin = exposure.abs() as f64 * price / self.leverage;
+        exposure != 0 && self.balance + equity < used_margin * self.maintenance_fraction
+    }
+}
+
+impl BacktestResult {
+    // plotly figure (data + layout) of the equity curve, with the trades as markers on a second axis
+    pub fn to_plotly_json(&self) -> Value {
+        let (buys, sells): (Vec<_>, Vec<_>) = self.trades.iter().partition(|t| t.2 > 0);
+        let markers = |trades: Vec<&(u64, f64, i64)>, name: &str, symbol: &str, color: &str| {
+            json!({
+                "type": "scatter",
+                "mode": "markers",
+                "name": name,
+                "yaxis": "y2",
+                "x": trades.iter().map(|t| t.0).collect::<Vec<_>>(),
+                "y": trades.iter().map(|t| t.1).collect::<Vec<_>>(),
+                "text": trades.iter().map(|t| t.2.to_string()).collect::<Vec<_>>(),
+                "marker": { "symbol": symbol, "color": color, "size": 8 },
+            })
+        };
+        json!({
+            "data": [
+                {
+                    "type": "scatter",
+                    "mode": "lines",
+                    "name": "equity",
+                    "x": self.equity.iter().map(|e| e.0).collect::<Vec<_>>(),
+                    "y": self.equity.iter().map(|e| e.1).collect::<Vec<_>>(),
+                },
+                markers(buys, "buy", "triangle-up", "green"),
+                markers(sells, "sell", "triangle-down", "red"),
+            ],
+            "layout": {
+                "title": format!("total PL {:.2}, max drawdown {:.2}, {} trades", self.total_pl, self.max_drawdown, self.trade_count),
+                "xaxis": { "title": "time" },
+                "yaxis": { "title": "equity" },
+                "yaxis2": { "title": "price", "overlaying": "y", "side": "right" },
+            },
+        })
+    }
+
+    // standalone page rendering to_plotly_json, plotly.js is loaded by the browser
+    pub fn to_html(&self) -> String {
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><script src="https://cdn.plot.ly/plotly-2.27.0.min.js"></script></head>
+<body>
+<div id="equity" style="width:100%;height:90vh;"></div>
+<script>
+const figure = {};
+Plotly.newPlot("equity", figure.data, figure.layout);
+</script>
+</body>
+</html>
+"#,
+            self.to_plotly_json()
+        )
+    }
+}
+
+const SECONDS_PER_DAY: u64 = 86400;
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+impl BacktestResult {
+    // equity change of each day with bars, from 0 before the first bar
+    pub fn daily_pl(&self) -> Vec<f64> {
+        let mut closes: Vec<(u64, f64)> = vec![];
+        for (time, e) in self.equity.iter() {
+            match closes.last_mut() {
+                Some(last) if last.0 == time / SECONDS_PER_DAY => last.1 = *e,
+                _ => closes.push((time / SECONDS_PER_DAY, *e)),
+            }
+        }
+        let mut previous = 0.0;
+        closes
+            .iter()
+            .map(|(_, e)| {
+                let pl = e - previous;
+                previous = *e;
+                pl
+            })
+            .collect()
+    }
+
+    // PL over a year at the pace of the backtest
+    pub fn annualized_pl(&self) -> Option<f64> {
+        let (first, last) = (self.equity.first()?, self.equity.last()?);
+        let seconds = last.0.checked_sub(first.0).filter(|s| *s > 0)?;
+        Some(self.total_pl * TRADING_DAYS_PER_YEAR * SECONDS_PER_DAY as f64 / seconds as f64)
+    }
+
+    // time weighted average of the absolute exposure, None for a backtest without duration
+    pub fn average_exposure(&self) -> Option<f64> {
+        let (first, last) = (self.equity.first()?, self.equity.last()?);
+        let seconds = last.0.checked_sub(first.0).filter(|s| *s > 0)?;
+        Some(self.exposure_seconds / seconds as f64)
+    }
+
+    // annualized ratio of the mean daily PL to its standard deviation
+    pub fn sharpe_ratio(&self) -> Option<f64> {
+        let daily = self.daily_pl();
+        if daily.len() < 2 {
+            return None;
+        }
+        let n = daily.len() as f64;
+        let mean = daily.iter().sum::<f64>() / n;
+        let std = (daily.iter().map(|pl| (pl - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+        (std > 0.0).then(|| mean / std * TRADING_DAYS_PER_YEAR.sqrt())
+    }
+
+    // realized PL of each trade closing some exposure, replayed from the trades
+    pub fn closing_trade_pls(&self) -> Vec<f64> {
+        let mut pl = AgentPL::flat();
+        let mut pls = vec![];
+        for (_, price, units) in self.trades.iter() {
+            let closing = pl.exposure != 0 && pl.exposure.signum() != units.signum();
+            let before = pl.cum_profit;
+            if *units > 0 {
+                pl.buy(*price, *units);
+            } else {
+                pl.sell(*price, -units);
+            }
+            if closing {
+                pls.push(pl.cum_profit - before);
+            }
+        }
+        pls
+    }
+
+    // fills matched FIFO: each exit closes the oldest open entries first,
+    // the entries still open at the end are not in the round trips
+    pub fn round_trips(&self) -> Vec<RoundTrip> {
+        let mut open: VecDeque<(u64, f64, i64)> = VecDeque::new();
+        let mut trips = vec![];
+        for &(time, price, units) in self.trades.iter() {
+            let mut remaining = units;
+            while remaining != 0 {
+                match open.front_mut() {
+                    Some(entry) if entry.2.signum() != remaining.signum() => {
+                        let matched = entry.2.abs().min(remaining.abs()) * entry.2.signum();
+                        trips.push(RoundTrip {
+                            entry_time: entry.0,
+                            entry_price: entry.1,
+                            exit_time: time,
+                            exit_price: price,
+                            units: matched,
+                            holding_time: time - entry.0,
+                            pl: matched as f64 * (price / entry.1 - 1.0),
+                        });
+                        entry.2 -= matched;
+                        remaining += matched;
+                        if entry.2 == 0 {
+                            open.pop_front();
+                        }
+                    }
+                    _ => {
+                        open.push_back((time, price, remaining));
+                        remaining = 0;
+                    }
+                }
+            }
+        }
+        trips
+    }
+
+    // one csv row per bar, with headers
+    pub fn to_csv_writer(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let mut csv = csv::Writer::from_writer(writer);
+        for bar in self.bars.iter() {
+            csv.serialize(bar)?;
+        }
+        csv.flush()
+    }
+
+    pub fn to_csv_file(&self, path: &Path) -> io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        self.to_csv_writer(&mut file)
+    }
+
+    // gross profit over gross loss of the round trips, infinite without losses
+    pub fn profit_factor(&self) -> Option<f64> {
+        let trips = self.round_trips();
+        if trips.is_empty() {
+            return None;
+        }
+        let profit: f64 = trips.iter().map(|t| t.pl).filter(|pl| *pl > 0.0).sum();
+        let loss: f64 = trips.iter().map(|t| t.pl).filter(|pl| *pl < 0.0).sum();
+        Some(if loss == 0.0 { f64::INFINITY } else { profit / -loss })
+    }
+
+    // mean PL of the round trips
+    pub fn expectancy(&self) -> Option<f64> {
+        let trips = self.round_trips();
+        (!trips.is_empty()).then(|| trips.iter().map(|t| t.pl).sum::<f64>() / trips.len() as f64)
+    }
+
+    // share of the closing trades with a gain
+    pub fn win_rate(&self) -> Option<f64> {
+        let pls = self.closing_trade_pls();
+        (!pls.is_empty()).then(|| pls.iter().filter(|pl| **pl > 0.0).count() as f64 / pls.len() as f64)
+    }
+}
+
+impl fmt::Display for BacktestResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let row = |f: &mut fmt::Formatter, name: &str, value: Option<f64>, unit: &str| match value {
+            Some(v) => writeln!(f, "{:<20}{:>14.2} {}", name, v, unit),
+            None => writeln!(f, "{:<20}{:>14} {}", name, "n/a", unit),
+        };
+        let daily = self.daily_pl();
+        let average = (self.trade_count > 0).then(|| self.realized_pl / self.trade_count as f64);
+        row(f, "Total PL", Some(self.total_pl), "")?;
+        row(f, "Annualized Return", self.annualized_pl(), "/ year")?;
+        row(f, "Sharpe Ratio", self.sharpe_ratio(), "")?;
+        row(f, "Max Drawdown", Some(self.max_drawdown), "")?;
+        writeln!(f, "{:<20}{:>14} trades", "Number of Trades", self.trade_count)?;
+        row(f, "Win Rate", self.win_rate().map(|w| w * 100.0), "%")?;
+        row(f, "Average Trade PL", average, "/ trade")?;
+        row(f, "Best Day", daily.iter().cloned().reduce(f64::max), "")?;
+        row(f, "Worst Day", daily.iter().cloned().reduce(f64::min), "")?;
+        if let Some(time) = self.stopped_out {
+            writeln!(f, "{:<20}{:>14} s", "Stopped Out", time)?;
+        }
+        Ok(())
+    }
+}
+
+// PL if we closed the agent at tick, buying back at ask or selling at bid
+pub fn equity_at_tick(agent: &GearHedger, tick: &Tick) -> f64 {
+    let exposure = agent.exposure();
+    if exposure == 0 {
+        return agent.agentPL.cum_profit;
+    }
+    let close_price = if exposure > 0 { tick.bid } else { tick.ask };
+    agent.agentPL.pl_at_price(close_price)
+}
+
+// run the agent over the bars (close bid/ask), every target exposure is filled at the tentative price
+pub fn backtest(agent: &mut GearHedger, bars: &[Bar]) -> BacktestResult {
+    run_backtest(agent, bars, None)
+}
+
+// backtest, and with a margin config the agent is flattened and deactivated on a margin call
+pub fn run_backtest(agent: &mut GearHedger, bars: &[Bar], margin: Option<&MarginConfig>) -> BacktestResult {
+    let mut trade_count = 0;
+    let mut peak = 0.0f64;
+    let mut max_drawdown = 0.0f64;
+    let mut equity = Vec::with_capacity(bars.len());
+    let mut bar_results: Vec<BarResult> = Vec::with_capacity(bars.len());
+    let mut trades = Vec::new();
+    let mut stopped_out = None;
+    let mut exposure_seconds = 0.0;
+
+    for bar in bars {
+        // the exposure after the previous bar was held until this one
+        if let Some(previous) = bar_results.last() {
+            exposure_seconds += previous.exposure.abs() as f64 * bar.time().saturating_sub(previous.time) as f64;
+        }
+        let tick = Tick::new(bar);
+        if agent.is_active() {
+            let target = agent.next_exposure(&tick);
+            if target != agent.exposure() {
+                let units = target - agent.exposure();
+                trades.push((bar.time(), agent.tentative_price, units));
+                agent.update_on_fill(&OrderFill { price: agent.tentative_price, units });
+                trade_count += 1;
+            }
+        }
+        let mut e = equity_at_tick(agent, &tick);
+        if margin.is_some_and(|m| m.is_margin_call(e, agent.exposure(), tick.price())) {
+            let units = agent.close(&tick, CloseReason::StopLoss);
+            trades.push((bar.time(), agent.tentative_price, units));
+            agent.update_on_fill(&OrderFill { price: agent.tentative_price, units });
+            agent.deactivate();
+            trade_count += 1;
+            stopped_out = Some(bar.time());
+            e = equity_at_tick(agent, &tick);
+        }
+        peak = peak.max(e);
+        max_drawdown = max_drawdown.max(peak - e);
+        equity.push((bar.time(), e));
+        let (open, high, low, close) = bar.mid_ohlc();
+        bar_results.push(BarResult {
+            time: bar.time(),
+            open,
+            high,
+            low,
+            close,
+            exposure: agent.exposure(),
+            realized_pl: agent.agentPL.cum_profit,
+            cumulative_pl: e,
+            unrealized_pl: e - agent.agentPL.cum_profit,
+        });
+        if stopped_out.is_some() {
+            break;
+        }
+    }
+
+    BacktestResult {
+        total_pl: equity.last().map_or(agent.agentPL.cum_profit, |e| e.1),
+        realized_pl: agent.agentPL.cum_profit,
+        trade_count,
+        max_drawdown,
+        final_exposure: agent.exposure(),
+        equity,
+        trades,
+        stopped_out,
+        bars: bar_results,
+        exposure_seconds,
+    }
+}
+
+// run any agent over bid/ask ticks (time in seconds): next_exposure once per tick, and a change of
+// exposure is filled right away at the side crossed, ask to buy and bid to sell.
+// The PL is booked on a ledger of the fills, the agent may not expose its own.
+pub fn run_tick_backtest<A: Agent>(agent: &mut A, ticks: &[Tick]) -> BacktestResult {
+    let mut ledger = AgentPL::flat();
+    let mut trade_count = 0;
+    let mut peak = 0.0f64;
+    let mut max_drawdown = 0.0f64;
+    let mut equity = Vec::with_capacity(ticks.len());
+    let mut trades = Vec::new();
+    let mut exposure_seconds = 0.0;
+    let mut previous: Option<(u64, i64)> = None;
+
+    for tick in ticks {
+        let time = tick.time.max(0) as u64;
+        if let Some((t, exposure)) = previous {
+            exposure_seconds += exposure.abs() as f64 * time.saturating_sub(t) as f64;
+        }
+        if agent.is_active() {
+            let units = agent.next_exposure(tick) - agent.exposure();
+            if units != 0 {
+                let price = if units > 0 { tick.ask } else { tick.bid };
+                trades.push((time, price, units));
+                agent.update_on_fill(&OrderFill { price, units });
+                if units > 0 {
+                    ledger.buy(price, units);
+                } else {
+                    ledger.sell(price, -units);
+                }
+                trade_count += 1;
+            }
+        }
+        let e = match ledger.exposure {
+            0 => ledger.cum_profit,
+            x if x > 0 => ledger.pl_at_price(tick.bid),
+            _ => ledger.pl_at_price(tick.ask),
+        };
+        peak = peak.max(e);
+        max_drawdown = max_drawdown.max(peak - e);
+        equity.push((time, e));
+        previous = Some((time, agent.exposure()));
+    }
+
+    BacktestResult {
+        total_pl: equity.last().map_or(ledger.cum_profit, |e| e.1),
+        realized_pl: ledger.cum_profit,
+        trade_count,
+        max_drawdown,
+        final_exposure: agent.exposure(),
+        equity,
+        trades,
+        stopped_out: None,
+        bars: vec![],
+        exposure_seconds,
+    }
+}
+
+// two agents over the same bars, and the portfolio holding half of each
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ComparisonReport {
+    pub a: BacktestResult,
+    pub b: BacktestResult,
+    // correlation of the bar to bar changes of the two equity curves, None if one is flat
+    pub correlation: Option<f64>,
+    // the 50/50 portfolio: every number is the average of both results (integers rounded), the equity
+    // bar by bar and the drawdown is the one of that average curve, without trades, bars or stop out
+    pub combined: BacktestResult,
+}
+
+// both agents run with run_backtest, the margin config applying to each of them
+pub fn compare(a: &mut GearHedger, b: &mut GearHedger, bars: &[Bar], margin: Option<&MarginConfig>) -> ComparisonReport {
+    let a = run_backtest(a, bars, margin);
+    let b = run_backtest(b, bars, margin);
+    combine(a, b)
+}
+
+// any two agents, e.g. two inventories, run with run_tick_backtest on the close bid/ask of each bar,
+// without margin
+pub fn compare_agents<A: Agent, B: Agent>(a: &mut A, b: &mut B, bars: &[Bar]) -> ComparisonReport {
+    let ticks: Vec<Tick> = bars.iter().map(Tick::new).map(|tick| Tick { time: tick.time(), ..tick }).collect();
+    let a = run_tick_backtest(a, &ticks);
+    let b = run_tick_backtest(b, &ticks);
+    combine(a, b)
+}
+
+fn combine(a: BacktestResult, b: BacktestResult) -> ComparisonReport {
+
+    // the curves have the same bars, a stopped out curve stays at its last equity
+    let n = a.equity.len().max(b.equity.len());
+    let at = |r: &BacktestResult, i: usize| r.equity.get(i).or(r.equity.last()).map_or((0, 0.0), |e| *e);
+    let equity: Vec<(u64, f64)> = (0..n)
+        .map(|i| {
+            let ((ta, ea), (tb, eb)) = (at(&a, i), at(&b, i));
+            (ta.max(tb), (ea + eb) / 2.0)
+        })
+        .collect();
+    let mut peak = 0.0f64;
+    let mut max_drawdown = 0.0f64;
+    for (_, e) in equity.iter() {
+        peak = peak.max(*e);
+        max_drawdown = max_drawdown.max(peak - e);
+    }
+    let changes = |r: &BacktestResult| -> Vec<f64> {
+        let mut previous = 0.0;
+        (0..n).map(|i| {
+            let e = at(r, i).1;
+            let change = e - previous;
+            previous = e;
+            change
+        }).collect()
+    };
+    let correlation = correlation(&changes(&a), &changes(&b));
+
+    let combined = BacktestResult {
+        total_pl: (a.total_pl + b.total_pl) / 2.0,
+        realized_pl: (a.realized_pl + b.realized_pl) / 2.0,
+        trade_count: ((a.trade_count + b.trade_count) as f64 / 2.0).round() as u64,
+        max_drawdown,
+        final_exposure: ((a.final_exposure + b.final_exposure) as f64 / 2.0).round() as i64,
+        equity,
+        trades: vec![],
+        stopped_out: None,
+        bars: vec![],
+        exposure_seconds: (a.exposure_seconds + b.exposure_seconds) / 2.0,
+    };
+    ComparisonReport { a, b, correlation, combined }
+}
+
+// Pearson correlation of two samples of the same length, None without variance
+fn correlation(x: &[f64], y: &[f64]) -> Option<f64> {
+    let n = x.len().min(y.len());
+    if n < 2 {
+        return None;
+    }
+    let (mx, my) = (x[..n].iter().sum::<f64>() / n as f64, y[..n].iter().sum::<f64>() / n as f64);
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (xi, yi) in x[..n].iter().zip(y[..n].iter()) {
+        sxy += (xi - mx) * (yi - my);
+        sxx += (xi - mx).powi(2);
+        syy += (yi - my).powi(2);
+    }
+    (sxx > 0.0 && syy > 0.0).then(|| sxy / (sxx * syy).sqrt())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::super::agents::{Agent, GearHedger};
+    use super::super::quote::Bar;
+    use super::super::simulation::simulate_mean_reverting;
+    use super::super::agents::AgentInventory;
+    use super::super::quote::Tick;
+    use super::{backtest, compare, compare_agents, run_backtest, run_tick_backtest, BacktestResult, MarginConfig, RoundTrip};
+
+    // bars with all prices at the given closes, one minute apart
+    pub(crate) fn bars(prices: &[f64]) -> Vec<Bar> {
+        let csv: String = prices
+            .iter()
+            .enumerate()
+            .map(|(i, p)| format!("{},{p},{p},{p},{p},{p},{p},{p},{p}\n", i * 60000))
+            .collect();
+        bars_from_csv(&csv)
+    }
+
+    // bars of Lean csv lines: time,obid,hbid,lbid,cbid,oask,hask,lask,cask
+    pub(crate) fn bars_from_csv(csv: &str) -> Vec<Bar> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .trim(csv::Trim::All)
+            .from_reader(csv.as_bytes())
+            .deserialize()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        let result = backtest(&mut agent, &bars(&[1.00, 0.98, 1.00, 1.02]));
+        // buy at 1.00 and 0.98, sell at 1.00 and 1.02
+        assert_eq!(result.trade_count, 4);
+        assert_eq!(result.equity.len(), 4);
+        assert!(result.realized_pl > 0.0);
+        assert!(result.max_drawdown > 0.0);
+        assert_eq!(result.final_exposure, agent.exposure());
+        assert_eq!(result.total_pl, result.equity[3].1);
+        assert_eq!(result.trades.len(), 4);
+    }
+
+    #[test]
+    fn plotly_json() {
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        let result = backtest(&mut agent, &bars(&[1.00, 0.98, 0.99, 1.00, 1.02]));
+        let figure = result.to_plotly_json();
+        let data = figure["data"].as_array().unwrap();
+        assert_eq!(data[0]["x"].as_array().unwrap().len(), 5);
+        assert_eq!(data[0]["y"].as_array().unwrap().len(), 5);
+        let markers = data[1]["x"].as_array().unwrap().len() + data[2]["x"].as_array().unwrap().len();
+        assert_eq!(markers as u64, result.trade_count);
+        assert!(result.to_html().contains("\"equity\""));
+    }
+
+    #[test]
+    fn stop_out() {
+        let margin = MarginConfig { balance: 1000.0, leverage: 50.0, maintenance_fraction: 0.5 };
+        let prices = [1.00, 0.98, 0.96, 0.94];
+
+        // 50000 units at 1.00 use all the balance as margin, 2% down wipes the equity
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 100000.0);
+        let result = run_backtest(&mut agent, &bars(&prices), Some(&margin));
+        assert_eq!(result.stopped_out, Some(60));
+        assert_eq!(result.final_exposure, 0);
+        assert_eq!(result.equity.len(), 2);
+        assert!(result.total_pl < -margin.balance * 0.5);
+        assert!(!agent.is_active());
+
+        // without margin rules the agent keeps buying all the way down
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 100000.0);
+        let result = backtest(&mut agent, &bars(&prices));
+        assert_eq!(result.stopped_out, None);
+        assert_eq!(result.equity.len(), 4);
+        assert!(result.final_exposure > 0);
+    }
+
+    #[test]
+    fn display() {
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        let result = backtest(&mut agent, &bars(&[1.00, 0.98, 1.00, 1.02]));
+        // two sales closing the buys at a gain
+        assert_eq!(result.closing_trade_pls().len(), 2);
+        assert_eq!(result.win_rate(), Some(1.0));
+        // all bars in a single day
+        assert_eq!(result.daily_pl().len(), 1);
+        assert_eq!(result.sharpe_ratio(), None);
+
+        let report = result.to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], format!("{:<20}{:>14.2} ", "Total PL", result.total_pl));
+        assert!(lines[2].starts_with("Sharpe Ratio") && lines[2].contains("n/a"));
+        assert!(lines[4].ends_with(" 4 trades"));
+        assert!(lines[5].contains("100.00 %"));
+        assert!(lines.iter().all(|l| l.len() >= 34));
+    }
+
+    #[test]
+    fn mean_reverting_market() {
+        // a symmetric agent around the mean of a mean reverting market makes money
+        let prices = simulate_mean_reverting(1.10, 20.0, 1.10, 0.05, 1.0 / 1440.0, 1440, 1);
+        let mut agent = GearHedger::symmetric(1.05, 1.15, 0.0010, 0.0010, 10000.0, f64::MAX);
+        let result = backtest(&mut agent, &bars(&prices));
+        assert!(result.trade_count > 10);
+        assert!(result.realized_pl > 0.0);
+
+        // same seed, same backtest
+        let mut again = GearHedger::symmetric(1.05, 1.15, 0.0010, 0.0010, 10000.0, f64::MAX);
+        assert_eq!(backtest(&mut again, &bars(&prices)).equity, result.equity);
+    }
+
+    #[test]
+    fn round_trips() {
+        let result = BacktestResult {
+            total_pl: 0.0,
+            realized_pl: 0.0,
+            trade_count: 4,
+            max_drawdown: 0.0,
+            final_exposure: -50,
+            equity: vec![],
+            trades: vec![(0, 1.00, 100), (60, 0.98, 100), (120, 1.01, -150), (180, 1.02, -100)],
+            stopped_out: None,
+            bars: vec![],
+            exposure_seconds: 0.0,
+        };
+        let trip = |entry_time, entry_price, exit_time, exit_price, units: i64| RoundTrip {
+            entry_time,
+            entry_price,
+            exit_time,
+            exit_price,
+            units,
+            holding_time: exit_time - entry_time,
+            pl: units as f64 * (exit_price / entry_price - 1.0),
+        };
+        // the sale at 1.02 closes the last 50 long and opens a 50 short, left open
+        assert_eq!(
+            result.round_trips(),
+            vec![
+                trip(0, 1.00, 120, 1.01, 100),
+                trip(60, 0.98, 120, 1.01, 50),
+                trip(60, 0.98, 180, 1.02, 50),
+            ]
+        );
+        assert!((result.round_trips()[0].pl - 1.0).abs() < 1e-9);
+        assert_eq!(result.round_trips()[2].holding_time, 120);
+
+        // buying back the short lower is a gain
+        let closed = BacktestResult { trades: [result.trades.clone(), vec![(240, 1.00, 50)]].concat(), ..result };
+        let short = closed.round_trips().pop().unwrap();
+        assert_eq!(short, trip(180, 1.02, 240, 1.00, -50));
+        assert!(short.pl > 0.0);
+    }
+
+    #[test]
+    fn profit_factor_and_expectancy() {
+        let with_trades = |trades: Vec<(u64, f64, i64)>| BacktestResult {
+            total_pl: 0.0,
+            realized_pl: 0.0,
+            trade_count: trades.len() as u64,
+            max_drawdown: 0.0,
+            final_exposure: 0,
+            equity: vec![],
+            trades,
+            stopped_out: None,
+            bars: vec![],
+            exposure_seconds: 0.0,
+        };
+        // round trips of 1000 units: +20, -10, +10
+        let result = with_trades(vec![(0, 1.00, 1000), (1, 1.02, -1000), (2, 1.00, 1000), (3, 0.99, -1000), (4, 1.00, -1000), (5, 0.99, 1000)]);
+        assert!((result.profit_factor().unwrap() - 3.0).abs() < 1e-9);
+        assert!((result.expectancy().unwrap() - 20.0 / 3.0).abs() < 1e-9);
+
+        let no_loss = with_trades(vec![(0, 1.00, 1000), (1, 1.02, -1000)]);
+        assert_eq!(no_loss.profit_factor(), Some(f64::INFINITY));
+        assert!((no_loss.expectancy().unwrap() - 20.0).abs() < 1e-9);
+
+        let open_only = with_trades(vec![(0, 1.00, 1000)]);
+        assert_eq!(open_only.profit_factor(), None);
+        assert_eq!(open_only.expectancy(), None);
+    }
+
+    #[test]
+    fn csv_export() {
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0100, 0.0100, 10000.0);
+        let result = backtest(&mut agent, &bars(&[1.00, 0.98, 1.00]));
+        let mut out = vec![];
+        result.to_csv_writer(&mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "time,open,high,low,close,exposure,realized_pl,cumulative_pl,unrealized_pl");
+        assert!(lines[1].starts_with("0,1.0,1.0,1.0,1.0,"));
+        assert!(lines[2].starts_with("60,0.98,0.98,0.98,0.98,"));
+
+        let last = &result.bars[2];
+        assert_eq!(last.exposure, result.final_exposure);
+        assert_eq!(last.cumulative_pl, result.total_pl);
+        assert!((last.realized_pl + last.unrealized_pl - last.cumulative_pl).abs() < 1e-9);
+
+        let path = std::env::temp_dir().join(format!("backtest_{}.csv", std::process::id()));
+        result.to_csv_file(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), csv);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn average_exposure() {
+        // 5000 held for 3 minutes, then 6000 for 1 minute
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        let result = backtest(&mut agent, &bars(&[1.00, 1.00, 1.00, 0.98, 0.98]));
+        assert_eq!(result.bars.iter().map(|b| b.exposure).collect::<Vec<_>>(), vec![5000, 5000, 5000, 6000, 6000]);
+        assert_eq!(result.exposure_seconds, 5000.0 * 180.0 + 6000.0 * 60.0);
+        assert_eq!(result.average_exposure(), Some(5250.0));
+
+        let mut agent = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        assert_eq!(backtest(&mut agent, &bars(&[1.00])).average_exposure(), None);
+    }
+
+    #[test]
+    fn tick_backtest() {
+        let ticks: Vec<Tick> = [(1.0000, 1.0002), (0.9979, 0.9981), (0.9969, 0.9971), (1.0001, 1.0003), (1.0011, 1.0013)]
+            .iter()
+            .enumerate()
+            .map(|(i, (bid, ask))| Tick { time: i as i64 * 10, bid: *bid, ask: *ask })
+            .collect();
+
+        let mut agent = GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0);
+        let result = run_tick_backtest(&mut agent, &ticks);
+        assert_eq!(result.trade_count, result.trades.len() as u64);
+        assert!(result.trade_count >= 3);
+        assert_eq!(result.final_exposure, agent.exposure());
+        assert_eq!(result.equity.len(), ticks.len());
+        // buys at the ask and sales at the bid
+        for (time, price, units) in result.trades.iter() {
+            let tick = &ticks[*time as usize / 10];
+            assert_eq!(*price, if *units > 0 { tick.ask } else { tick.bid });
+        }
+        // the ledger books the same PL as the agent
+        assert!((result.realized_pl - agent.agentPL.cum_profit).abs() < 1e-9);
+        assert!(result.realized_pl > 0.0);
+
+        // any agent, e.g. an inventory
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("a".to_string(), GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0));
+        let from_inventory = run_tick_backtest(&mut inventory, &ticks);
+        assert_eq!(from_inventory.trades, result.trades);
+    }
+
+    #[test]
+    fn comparison_report() {
+        let bars = bars(&[1.00, 0.99, 0.98, 1.00, 1.02, 1.01, 0.99]);
+        let mut buyer = GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        let mut symmetric = GearHedger::symmetric(0.95, 1.05, 0.0010, 0.0010, 10000.0, 1000.0);
+        let report = compare(&mut buyer, &mut symmetric, &bars, None);
+
+        // the same results as the separate runs
+        let alone = backtest(&mut GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0), &bars);
+        assert_eq!(report.a.trades, alone.trades);
+        assert_eq!(alone.trade_count, report.a.trade_count);
+        assert_eq!(report.a.equity, alone.equity);
+        assert_eq!(report.combined.equity.len(), bars.len());
+        for ((a, b), c) in report.a.equity.iter().zip(report.b.equity.iter()).zip(report.combined.equity.iter()) {
+            assert_eq!(c.0, a.0);
+            assert!((c.1 - (a.1 + b.1) / 2.0).abs() < 1e-12);
+        }
+        assert!((report.combined.total_pl - (report.a.total_pl + report.b.total_pl) / 2.0).abs() < 1e-12);
+        assert_eq!(report.combined.trade_count, ((report.a.trade_count + report.b.trade_count) as f64 / 2.0).round() as u64);
+        assert_eq!(report.combined.final_exposure, ((report.a.final_exposure + report.b.final_exposure) as f64 / 2.0).round() as i64);
+        assert!(report.combined.max_drawdown <= report.a.max_drawdown.max(report.b.max_drawdown) + 1e-12);
+        // both are long on the way down: the curves move together
+        let correlation = report.correlation.unwrap();
+        assert!(correlation > 0.0 && correlation <= 1.0 + 1e-12);
+
+        // an agent compared to itself
+        let fresh = || GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0);
+        let report = compare(&mut fresh(), &mut fresh(), &bars, None);
+        assert!((report.correlation.unwrap() - 1.0).abs() < 1e-12);
+
+        // a margin call stops each side as in run_backtest
+        let margin = MarginConfig { balance: 100.0, leverage: 50.0, maintenance_fraction: 0.5 };
+        let report = compare(&mut fresh(), &mut GearHedger::symmetric(0.95, 1.05, 0.0010, 0.0010, 10000.0, 1000.0), &bars, Some(&margin));
+        assert_eq!(report.a.stopped_out, run_backtest(&mut fresh(), &bars, Some(&margin)).stopped_out);
+        assert!(report.a.stopped_out.is_some());
+
+        // an inventory against one of its agents
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("buyer".to_string(), fresh());
+        inventory.agents.insert("symmetric".to_string(), GearHedger::symmetric(0.95, 1.05, 0.0010, 0.0010, 10000.0, 1000.0));
+        let report = compare_agents(&mut inventory, &mut fresh(), &bars);
+        assert_eq!(report.b.equity, alone.equity);
+        assert!(report.a.trade_count > 0);
+        assert_eq!(report.a.final_exposure, inventory.exposure());
+        assert_eq!(report.combined.equity.len(), bars.len());
+    }
+}
@@ -1,8 +1,9 @@
 use serde::{Serialize,Deserialize};
 
-use super::agents::{GearHedger,Agent, GAgent};
+use super::agents::{GearHedger,Agent, CloseReason, GAgent};
 use super::account::OrderFill;
 use super::quote::Tick;
+use super::dyninventory::TaggedAgent;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct GBiAgent {
@@ -52,13 +53,7 @@ impl BiCoastAgent {
     }
 
     fn shift_mid_to_price(&mut self, price: f64) {
-        let span = (self.gear_hedger.gear_f.p_n - self.gear_hedger.gear_f.p_0)/2.0;
-        self.gear_hedger.gear_f =  GAgent::Symmetric{
-            pmid: price,
-            span: span,
-            scale: self.gear_hedger.scaleUp,
-            exposure: self.gear_hedger.max_exposure,
-            target: self.gear_hedger.target}.build().unwrap().gear_f;
+        self.gear_hedger.shift_mid_to_price(price);
     }
 
     pub fn pl(&self) -> f64 {
@@ -69,7 +64,7 @@ impl BiCoastAgent {
 impl Agent for BiCoastAgent {
 
     // NOT IMPLEMENTED!
-    fn close(&mut self, tick :&Tick) -> i64 {
+    fn close(&mut self, tick :&Tick, _reason: CloseReason) -> i64 {
         // otherwize,we check if we need to adjust exposure
 
         0
@@ -127,6 +122,10 @@ impl Agent for BiCoastAgent {
     fn exposure(&self) -> i64 {
         self.gear_hedger.exposure()
     }
+
+    fn tagged(&self) -> Option<TaggedAgent> {
+        Some(TaggedAgent::BiCoast(self.clone()))
+    }
 }
 
 
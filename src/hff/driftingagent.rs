@@ -0,0 +1,122 @@
+use serde::{Serialize,Deserialize};
+
+use super::agents::{GearHedger, Agent, CloseReason, GAgent};
+use super::account::OrderFill;
+use super::quote::Tick;
+use super::dyninventory::TaggedAgent;
+
+/*
+DriftingSymmetric is a symmetric GearHedger whose mid price follows an EMA of the tick prices:
+- the EMA is updated on each tick with weight alpha for the new price
+- the gear is then shifted to be centered on the EMA before computing the exposure
+This is a continuous version of the BiCoastAgent recalibration.
+*/
+#[derive(Debug,Deserialize,Serialize, Clone)]
+pub struct DriftingSymmetric {
+    pub alpha: f64,
+    pub ema: Option<f64>,
+    pub gear_hedger: GearHedger,
+}
+
+impl DriftingSymmetric {
+
+    // constructor
+    pub fn new(price: f64, span: f64, scale: f64, exposure: f64, target: f64, alpha: f64) -> Self {
+        Self {
+            alpha,
+            ema: None,
+            gear_hedger: GAgent::Symmetric{pmid: price, span, scale, exposure, target}.build().unwrap(),
+        }
+    }
+
+    pub fn mid_price(&self) -> f64 {
+        (self.gear_hedger.gear_f.p_0 + self.gear_hedger.gear_f.p_n)/2.0
+    }
+
+    // update the EMA with a new price and move the gear onto it
+    fn drift(&mut self, price: f64) {
+        let ema = match self.ema {
+            Some(ema) => self.alpha * price + (1.0 - self.alpha) * ema,
+            None => price,
+        };
+        self.ema = Some(ema);
+        self.gear_hedger.shift_mid_to_price(ema);
+    }
+}
+
+impl Agent for DriftingSymmetric {
+
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64 {
+        self.gear_hedger.close(tick, reason)
+    }
+
+    fn is_active(&self) -> bool {
+        self.gear_hedger.is_active()
+    }
+    fn deactivate(&mut self) {
+        self.gear_hedger.deactivate()
+    }
+
+    fn to_be_closed(&self) -> bool {
+        self.gear_hedger.to_be_closed()
+    }
+
+    fn target_action(&mut self) -> i64 {
+        self.gear_hedger.target_action()
+    }
+
+    fn target_exposure(&mut self, tick: &Tick) -> i64 {
+        self.gear_hedger.target_exposure(tick)
+    }
+
+    // the gear drifts with the EMA before the exposure is computed
+    fn next_exposure(&mut self, tick: &Tick) -> i64 {
+        self.drift(tick.price());
+        self.gear_hedger.next_exposure(tick)
+    }
+
+    fn update_on_fill(&mut self, order_fill: &OrderFill) {
+        self.gear_hedger.update_on_fill(order_fill)
+    }
+
+    fn next_exposure_and_fill(&mut self, order_fill: &OrderFill) {
+        self.next_exposure(&Tick{bid: order_fill.price, ask: order_fill.price, time: 0});
+        self.update_on_fill(order_fill);
+    }
+
+    fn exposure(&self) -> i64 {
+        self.gear_hedger.exposure()
+    }
+
+    fn tagged(&self) -> Option<TaggedAgent> {
+        Some(TaggedAgent::Drifting(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::account::OrderFill;
+    use super::super::quote::Tick;
+    use super::{Agent, DriftingSymmetric};
+
+    #[test]
+    fn follows_ema() {
+        let mut agent = DriftingSymmetric::new(1.0000, 0.0100, 0.0010, 100000.0, 1000.0, 0.2);
+        let mut ema = 1.0000;
+        let mut mid = agent.mid_price();
+        for i in 1..=20 {
+            let price = 1.0000 + 0.0005 * i as f64;
+            ema = if i == 1 { price } else { 0.2 * price + 0.8 * ema };
+            agent.next_exposure(&Tick { time: i, bid: price, ask: price });
+            agent.update_on_fill(&OrderFill { price: agent.gear_hedger.tentative_price, units: 0 });
+            assert!((agent.mid_price() - ema).abs() < 1e-9);
+            assert!((agent.ema.unwrap() - ema).abs() < 1e-12);
+            // trending up, the neutral price follows below the price
+            assert!(agent.mid_price() > mid);
+            assert!(agent.mid_price() <= price);
+            mid = agent.mid_price();
+        }
+        // the price is above the neutral price: short
+        assert!(agent.exposure() < 0);
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Serialize,Deserialize};
+use serde::ser::Error as _;
+
+use super::agents::{Agent, CloseReason, GearHedger, PairedHedger};
+use super::bicoastagent::BiCoastAgent;
+use super::driftingagent::DriftingSymmetric;
+use super::pairagent::PairAgent;
+use super::account::OrderFill;
+use super::quote::Tick;
+use std::collections::HashMap;
+
+// the agent types a DynInventory can store in a file, tagged with their type
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "kind")]
+pub enum TaggedAgent {
+    GearHedger(GearHedger),
+    BiCoast(BiCoastAgent),
+    Drifting(DriftingSymmetric),
+    Pair(Box<PairAgent>),
+    Paired(Box<PairedHedger>),
+}
+
+impl TaggedAgent {
+    pub fn into_agent(self) -> Box<dyn Agent> {
+        match self {
+            TaggedAgent::GearHedger(a) => Box::new(a),
+            TaggedAgent::BiCoast(a) => Box::new(a),
+            TaggedAgent::Drifting(a) => Box::new(a),
+            TaggedAgent::Pair(a) => a,
+            TaggedAgent::Paired(a) => a,
+        }
+    }
+}
+
+/*
+DynInventory is an inventory of agents of different types, driven through the Agent trait.
+It is serialized as an AgentInventory of TaggedAgent.
+*/
+#[derive(Default)]
+pub struct DynInventory {
+    pub agents: HashMap<String, Box<dyn Agent>>,
+    pub pl: f64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct DynInventoryFile {
+    agents: HashMap<String, TaggedAgent>,
+    pl: f64,
+}
+
+impl DynInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<A: Agent + 'static>(&mut self, key: String, agent: A) {
+        self.agents.insert(key, Box::new(agent));
+    }
+}
+
+impl Serialize for DynInventory {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut agents = HashMap::new();
+        for (key, agent) in self.agents.iter() {
+            let tagged = agent
+                .tagged()
+                .ok_or_else(|| S::Error::custom(format!("agent {} cannot be serialized", key)))?;
+            agents.insert(key.clone(), tagged);
+        }
+        DynInventoryFile { agents, pl: self.pl }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DynInventory {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let file = DynInventoryFile::deserialize(deserializer)?;
+        Ok(Self {
+            agents: file.agents.into_iter().map(|(k, a)| (k, a.into_agent())).collect(),
+            pl: file.pl,
+        })
+    }
+}
+
+impl Agent for DynInventory {
+
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64 {
+        self.agents.values_mut().map(|a| a.close(tick, reason)).sum()
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+    fn deactivate(&mut self) {
+        for (_, val) in self.agents.iter_mut() {
+            val.deactivate();
+        }
+    }
+
+    fn to_be_closed(&self) -> bool {
+        false
+    }
+
+    fn exposure(&self) -> i64 {
+        self.agents
+            .iter()
+            .filter(|a| a.1.is_active())
+            .fold(0, |a, b| a + b.1.exposure())
+    }
+
+    // we do nothing, it only happens on each individual Agent of the inventory
+    fn target_action(&mut self) -> i64 {
+        0
+    }
+
+    // we do nothing, it only happens on each individual Agent of the inventory
+    fn target_exposure(&mut self, _tick: &Tick) -> i64 {
+        0
+    }
+
+    fn next_exposure(&mut self, tick: &Tick) -> i64 {
+        let mut exposure = 0;
+        for (_, val) in self.agents.iter_mut().filter(|a| a.1.is_active()) {
+            exposure += val.next_exposure(tick);
+        }
+        exposure
+    }
+
+    fn update_on_fill(&mut self, order_fill: &OrderFill) {
+        for (_, val) in self.agents.iter_mut().filter(|a| a.1.is_active()) {
+            val.update_on_fill(order_fill);
+        }
+    }
+
+    fn next_exposure_and_fill(&mut self, order_fill: &OrderFill) {
+        self.next_exposure(&Tick{bid: order_fill.price, ask: order_fill.price, time: 0});
+        self.update_on_fill(order_fill);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::account::OrderFill;
+    use super::super::agents::{Agent, AgentInventory, GearHedger};
+    use super::super::bicoastagent::BiCoastAgent;
+    use super::super::quote::Tick;
+    use super::DynInventory;
+
+    #[test]
+    fn heterogeneous_agents() {
+        let mut inventory = DynInventory::new();
+        inventory.insert("hedger".to_string(), GearHedger::symmetric(0.99, 1.01, 0.0010, 0.0010, 10000.0, 1000.0));
+        inventory.insert("bicoast".to_string(), BiCoastAgent::new(1.0000, 0.0100, 0.0010, 10000.0, 1000.0));
+
+        for price in [1.0000, 0.9990, 0.9980, 0.9990] {
+            let tick = Tick { time: 0, bid: price, ask: price };
+            let target = inventory.next_exposure(&tick);
+            inventory.update_on_fill(&OrderFill { price, units: target - inventory.exposure() });
+            assert_eq!(inventory.exposure(), target);
+        }
+        assert_eq!(inventory.exposure(), inventory.agents["hedger"].exposure() + inventory.agents["bicoast"].exposure());
+        assert!(inventory.exposure() > 0);
+
+        let json = serde_json::to_string(&inventory).unwrap();
+        let back: DynInventory = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.agents.len(), 2);
+        assert_eq!(back.exposure(), inventory.exposure());
+
+        // agents without a tagged form cannot be written
+        inventory.insert("nested".to_string(), AgentInventory::<GearHedger>::new());
+        assert!(serde_json::to_string(&inventory).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Serialize,Deserialize};
+
+// descriptor of a currency pair as named by Oanda, e.g. EUR_USD: base EUR, quote USD
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Instrument {
+    pub name: String,
+    pub base: String,
+    pub quote: String,
+    // power of ten of a pip, -4 for most pairs, -2 for JPY quotes
+    pub pip_location: i32,
+}
+
+impl Instrument {
+    pub fn new(name: &str, pip_location: i32) -> Option<Self> {
+        let (base, quote) = name.split_once('_')?;
+        Some(Self {
+            name: name.to_string(),
+            base: base.to_string(),
+            quote: quote.to_string(),
+            pip_location,
+        })
+    }
+
+    // pip location guessed from the quote currency
+    pub fn from_name(name: &str) -> Option<Self> {
+        let quote = name.split_once('_')?.1;
+        let pip_location = if quote == "JPY" { -2 } else { -4 };
+        Self::new(name, pip_location)
+    }
+
+    // decimals of the quoted prices, one more than the pip: 5 for EUR_USD, 3 for USD_JPY
+    pub fn display_precision(&self) -> usize {
+        (1 - self.pip_location).max(0) as usize
+    }
+
+    pub fn pip_size(&self) -> f64 {
+        10f64.powi(self.pip_location)
+    }
+
+    // value of a one pip move on units, in account currency
+    // conversion_rate: price of one quote currency unit in account currency (1.0 when quote is the account currency)
+    pub fn pip_value(&self, units: i64, conversion_rate: f64) -> f64 {
+        units as f64 * self.pip_size() * conversion_rate
+    }
+
+    // units to trade so a one pip move is worth risk_per_pip in account currency
+    pub fn units_for_pip_risk(&self, risk_per_pip: f64, conversion_rate: f64) -> i64 {
+        (risk_per_pip / self.pip_value(1, conversion_rate)) as i64
+    }
+
+    // a PL in quote currency, in account currency
+    // (or in base currency with the base conversion_rate, e.g. the Ratio PL of the agents)
+    pub fn pl_in_account(&self, quote_pl: f64, conversion_rate: f64) -> f64 {
+        quote_pl * conversion_rate
+    }
+}
+
+// Oanda conversion of an amount of a currency to the account currency:
+// multiply by gain when it is a profit, by loss when it is a loss
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HomeFactor {
+    pub gain: f64,
+    pub loss: f64,
+}
+
+impl HomeFactor {
+    pub fn rate(&self, amount: f64) -> f64 {
+        if amount >= 0.0 { self.gain } else { self.loss }
+    }
+
+    pub fn convert(&self, amount: f64) -> f64 {
+        amount * self.rate(amount)
+    }
+}
+
+// conversions to the account currency of both currencies of an instrument
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HomeConversions {
+    pub base_home: HomeFactor,
+    pub quote_home: HomeFactor,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instrument;
+
+    #[test]
+    fn direct_quote() {
+        // USD account, USD quote
+        let eur_usd = Instrument::from_name("EUR_USD").unwrap();
+        assert_eq!(eur_usd.base, "EUR");
+        assert_eq!(eur_usd.quote, "USD");
+        assert!((eur_usd.pip_value(10000, 1.0) - 1.0).abs() < 1e-9);
+        assert_eq!(eur_usd.units_for_pip_risk(100.0, 1.0), 1000000);
+    }
+
+    #[test]
+    fn cross_quote() {
+        // USD account, GBP quote at GBP_USD 1.25
+        let eur_gbp = Instrument::from_name("EUR_GBP").unwrap();
+        assert!((eur_gbp.pip_value(10000, 1.25) - 1.25).abs() < 1e-9);
+        assert_eq!(eur_gbp.units_for_pip_risk(100.0, 1.25), 800000);
+        assert!((eur_gbp.pl_in_account(40.0, 1.25) - 50.0).abs() < 1e-9);
+
+        // USD account, JPY quote at USD_JPY 150
+        let usd_jpy = Instrument::from_name("USD_JPY").unwrap();
+        assert_eq!(usd_jpy.pip_location, -2);
+        assert!((usd_jpy.pip_value(15000, 1.0 / 150.0) - 1.0).abs() < 1e-9);
+
+        assert!(Instrument::from_name("EURUSD").is_none());
+    }
+}
@@ -1,5 +1,12 @@
 pub mod agents;
+pub mod analytics;
+pub mod backtest;
 pub mod bicoastagent;
+pub mod driftingagent;
+pub mod dyninventory;
+pub mod instrument;
+pub mod pairagent;
 pub mod overshoot;
 pub mod quote;
+pub mod simulation;
 pub mod account;
\ No newline at end of file
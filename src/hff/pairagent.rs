@@ -0,0 +1,237 @@
+use serde::{Serialize,Deserialize};
+
+use super::agents::{GearHedger, Agent, CloseReason};
+use super::account::OrderFill;
+use super::quote::Tick;
+use super::dyninventory::TaggedAgent;
+
+/*
+PairAgent holds a long exposure_a on instrument_a against a short exposure_b on instrument_b.
+- each leg is a constant GearHedger, the short leg is booked long and its PL is subtracted: pl = pl_a - pl_b
+- the pair only trades when the spread (price_a - price_b) moved more than scale from the last trade
+- as an Agent, ticks and fills are expressed on the spread (see spread_tick), the legs are
+booked with the actual leg fills through update_on_pair_fill
+*/
+#[derive(Debug,Deserialize,Serialize, Clone)]
+pub struct PairAgent {
+    pub instrument_a: String,
+    pub instrument_b: String,
+    pub leg_a: GearHedger,
+    pub leg_b: GearHedger,
+    pub scale: f64,
+    pub target: f64,
+    pub active: bool,
+    // spread at the last trade of the pair, None until the pair is entered
+    pub last_spread: Option<f64>,
+}
+
+impl PairAgent {
+
+    // constructor
+    pub fn new(instrument_a: String, exposure_a: f64, instrument_b: String, exposure_b: f64, scale: f64, target: f64) -> Self {
+        Self {
+            leg_a: GearHedger::constant(exposure_a).with_instrument(&instrument_a),
+            leg_b: GearHedger::constant(exposure_b).with_instrument(&instrument_b),
+            instrument_a,
+            instrument_b,
+            scale,
+            target,
+            active: true,
+            last_spread: None,
+        }
+    }
+
+    // the quote of the spread: we buy the spread at ask_a - bid_b and sell it at bid_a - ask_b
+    pub fn spread_tick(tick_a: &Tick, tick_b: &Tick) -> Tick {
+        Tick {
+            time: tick_a.time.max(tick_b.time),
+            bid: tick_a.bid - tick_b.ask,
+            ask: tick_a.ask - tick_b.bid,
+        }
+    }
+
+    // spread PL at given leg prices
+    pub fn pl_at_prices(&self, price_a: f64, price_b: f64) -> f64 {
+        self.leg_a.agentPL.pl_at_price(price_a) - self.leg_b.agentPL.pl_at_price(price_b)
+    }
+
+    // exposures of the two legs as traded on the market (leg b is short)
+    pub fn leg_exposures(&self) -> (i64, i64) {
+        (self.leg_a.exposure(), -self.leg_b.exposure())
+    }
+
+    // did the spread move enough from the last trade to fire
+    fn spread_moved(&self, tick: &Tick) -> bool {
+        match self.last_spread {
+            None => true,
+            Some(last) => (tick.price() - last).abs() > self.scale,
+        }
+    }
+
+    // target exposures of the two legs for the given leg ticks
+    pub fn next_pair_exposure(&mut self, tick_a: &Tick, tick_b: &Tick) -> (i64, i64) {
+        if !self.active || !self.spread_moved(&Self::spread_tick(tick_a, tick_b)) {
+            return self.leg_exposures();
+        }
+        let exposure_a = self.leg_a.next_exposure(tick_a);
+        // leg b is booked long on its market quote, its constant gear wants the same exposure on either side,
+        // the actual sale at the bid is booked by update_on_pair_fill
+        let exposure_b = self.leg_b.next_exposure(tick_b);
+        (exposure_a, -exposure_b)
+    }
+
+    // book the actual fills of the two legs, fill_b units are the (negative) market units of the short leg
+    pub fn update_on_pair_fill(&mut self, fill_a: &OrderFill, fill_b: &OrderFill) {
+        self.leg_a.update_on_fill(fill_a);
+        self.leg_b.update_on_fill(&OrderFill { price: fill_b.price, units: -fill_b.units });
+        self.last_spread = Some(fill_a.price - fill_b.price);
+        if self.to_be_closed() {
+            self.deactivate();
+        }
+    }
+
+    fn cum_profit(&self) -> f64 {
+        self.leg_a.agentPL.cum_profit - self.leg_b.agentPL.cum_profit
+    }
+}
+
+impl Agent for PairAgent {
+
+    fn close(&mut self, tick :&Tick, reason: CloseReason) -> i64 {
+        let (exposure_a, exposure_b) = self.leg_exposures();
+        self.leg_a.close(tick, reason);
+        self.leg_b.close(tick, reason);
+        -(exposure_a + exposure_b)
+    }
+
+    fn is_active(&self) -> bool {
+        self.active
+    }
+    fn deactivate(&mut self) {
+        self.active = false;
+        self.leg_a.deactivate();
+        self.leg_b.deactivate();
+    }
+
+    fn to_be_closed(&self) -> bool {
+        self.cum_profit() > self.target
+    }
+
+    fn target_action(&mut self) -> i64 {
+        self.leg_a.tentative_exposure = 0;
+        self.leg_b.tentative_exposure = 0;
+        self.deactivate();
+        0
+    }
+
+    // the pair holds constant legs, the net target exposure is the net of the legs
+    fn target_exposure(&mut self, _tick: &Tick) -> i64 {
+        (self.leg_a.max_exposure - self.leg_b.max_exposure) as i64
+    }
+
+    // tick is the spread tick, see spread_tick
+    fn next_exposure(&mut self, tick: &Tick) -> i64 {
+        if !self.active || !self.spread_moved(tick) {
+            return self.exposure();
+        }
+        self.target_exposure(tick)
+    }
+
+    // order_fill is a fill on the spread: only the traded spread is recorded,
+    // the legs are booked with update_on_pair_fill
+    fn update_on_fill(&mut self, order_fill: &OrderFill) {
+        self.last_spread = Some(order_fill.price);
+    }
+
+    fn next_exposure_and_fill(&mut self, order_fill: &OrderFill) {
+        self.next_exposure(&Tick{bid: order_fill.price, ask: order_fill.price, time: 0});
+        self.update_on_fill(order_fill);
+    }
+
+    // net exposure of the two legs
+    fn exposure(&self) -> i64 {
+        let (exposure_a, exposure_b) = self.leg_exposures();
+        exposure_a + exposure_b
+    }
+
+    fn tagged(&self) -> Option<TaggedAgent> {
+        Some(TaggedAgent::Pair(Box::new(self.clone())))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::account::OrderFill;
+    use super::super::agents::GAgent;
+    use super::super::quote::Tick;
+    use super::{Agent, PairAgent};
+
+    fn pair() -> PairAgent {
+        GAgent::PairSpread {
+            instrument_a: "EUR_USD".to_string(),
+            exposure_a: 10000.0,
+            instrument_b: "GBP_USD".to_string(),
+            exposure_b: 8000.0,
+            scale: 0.0010,
+            target: 100.0,
+        }
+        .build_pair()
+        .unwrap()
+    }
+
+    #[test]
+    fn pair_build() {
+        let agent = pair();
+        assert_eq!(agent.instrument_a, "EUR_USD");
+        assert_eq!(agent.instrument_b, "GBP_USD");
+        assert!(GAgent::PairSpread {
+            instrument_a: "EUR_USD".to_string(),
+            exposure_a: 1.0,
+            instrument_b: "GBP_USD".to_string(),
+            exposure_b: 1.0,
+            scale: 0.0010,
+            target: 1.0,
+        }
+        .build()
+        .is_none());
+    }
+
+    #[test]
+    fn pair_trade() {
+        let mut agent = pair();
+        let tick_a = Tick { time: 0, bid: 1.1000, ask: 1.1000 };
+        let tick_b = Tick { time: 0, bid: 1.2500, ask: 1.2500 };
+        assert_eq!(agent.next_pair_exposure(&tick_a, &tick_b), (10000, -8000));
+        agent.update_on_pair_fill(
+            &OrderFill { price: 1.1000, units: 10000 },
+            &OrderFill { price: 1.2500, units: -8000 },
+        );
+        assert_eq!(agent.leg_exposures(), (10000, -8000));
+        assert_eq!(agent.exposure(), 2000);
+
+        // spread did not move more than scale: no trade
+        let tick_a = Tick { time: 1, bid: 1.1005, ask: 1.1005 };
+        assert_eq!(agent.next_exposure(&PairAgent::spread_tick(&tick_a, &tick_b)), 2000);
+        assert_eq!(agent.next_pair_exposure(&tick_a, &tick_b), (10000, -8000));
+
+        // long leg up, short leg down: the spread PL is positive
+        assert!(agent.pl_at_prices(1.1100, 1.2400) > 0.0);
+        assert!(agent.pl_at_prices(1.0900, 1.2600) < 0.0);
+    }
+
+    #[test]
+    fn pair_trade_with_spread() {
+        let mut agent = pair();
+        let tick_a = Tick { time: 0, bid: 1.0999, ask: 1.1001 };
+        let tick_b = Tick { time: 0, bid: 1.2498, ask: 1.2502 };
+        assert_eq!(agent.next_pair_exposure(&tick_a, &tick_b), (10000, -8000));
+        agent.update_on_pair_fill(
+            &OrderFill { price: 1.1001, units: 10000 },
+            &OrderFill { price: 1.2498, units: -8000 },
+        );
+        assert_eq!(agent.leg_exposures(), (10000, -8000));
+        // the short leg is booked at its sale price
+        assert_eq!(agent.leg_b.agentPL.price_average, 1.2498);
+    }
+}
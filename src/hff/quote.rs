@@ -1,5 +1,6 @@
 /* quotes, ticks and other pricing stuff */
 use serde::Deserialize;
+use std::collections::VecDeque;
 
 #[derive(Debug,Deserialize)]
 pub struct Bar {
@@ -28,12 +29,39 @@ impl Bar {
         self.cbid
     }
 
+    // ask above bid, positive on a normal market
     pub fn spread(&self) -> f64 {
-        self.cbid - self.cask
+        self.cask - self.cbid
+    }
+
+    // open, high, low and close of the mid price
+    pub fn mid_ohlc(&self) -> (f64, f64, f64, f64) {
+        (
+            (self.obid + self.oask) / 2.0,
+            (self.hbid + self.hask) / 2.0,
+            (self.lbid + self.lask) / 2.0,
+            (self.cbid + self.cask) / 2.0,
+        )
+    }
+
+    // one bar at time over consecutive bars: first open, highest high, lowest low, last close
+    pub fn aggregate(time: u64, bars: &[Bar]) -> Option<Bar> {
+        let (first, last) = (bars.first()?, bars.last()?);
+        Some(Bar {
+            time,
+            obid: first.obid,
+            hbid: bars.iter().map(|b| b.hbid).fold(f64::NEG_INFINITY, f64::max),
+            lbid: bars.iter().map(|b| b.lbid).fold(f64::INFINITY, f64::min),
+            cbid: last.cbid,
+            oask: first.oask,
+            hask: bars.iter().map(|b| b.hask).fold(f64::NEG_INFINITY, f64::max),
+            lask: bars.iter().map(|b| b.lask).fold(f64::INFINITY, f64::min),
+            cask: last.cask,
+        })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Tick {
     pub time: i64,
     pub bid: f64,
@@ -64,7 +92,98 @@ impl Tick {
         self.bid
     }
 
+    // ask above bid, positive on a normal market
     pub fn spread(&self) -> f64 {
-        self.bid - self.ask
+        self.ask - self.bid
+    }
+}
+
+/* sliding window of the last max_size ticks, sorted by time */
+#[derive(Debug, Clone)]
+pub struct TickStream {
+    pub window: VecDeque<Tick>,
+    pub max_size: usize,
+}
+
+impl TickStream {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            window: VecDeque::with_capacity(max_size),
+            max_size,
+        }
+    }
+
+    // add a tick, late ticks are inserted at their time, the oldest tick leaves a full window
+    pub fn push(&mut self, tick: Tick) {
+        let i = self.window.partition_point(|t| t.time <= tick.time);
+        self.window.insert(i, tick);
+        while self.window.len() > self.max_size {
+            self.window.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    pub fn mid_prices(&self) -> Vec<f64> {
+        self.window.iter().map(|t| t.price()).collect()
     }
-}
\ No newline at end of file
+
+    // average of ask - bid, 0 on an empty window
+    pub fn spread_avg(&self) -> f64 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+        self.window.iter().map(|t| t.ask - t.bid).sum::<f64>() / self.window.len() as f64
+    }
+
+    // fraction of the bid and ask moves between consecutive ticks that are bid moves,
+    // 0.5 when nothing moved
+    pub fn bid_ask_imbalance(&self) -> f64 {
+        let (mut bid_moves, mut ask_moves) = (0, 0);
+        for (prev, next) in self.window.iter().zip(self.window.iter().skip(1)) {
+            if next.bid != prev.bid {
+                bid_moves += 1;
+            }
+            if next.ask != prev.ask {
+                ask_moves += 1;
+            }
+        }
+        if bid_moves + ask_moves == 0 {
+            return 0.5;
+        }
+        bid_moves as f64 / (bid_moves + ask_moves) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tick, TickStream};
+
+    #[test]
+    fn tick_stream() {
+        let mut stream = TickStream::new(3);
+        assert_eq!(stream.spread_avg(), 0.0);
+        assert_eq!(stream.bid_ask_imbalance(), 0.5);
+
+        stream.push(Tick { time: 1, bid: 1.0000, ask: 1.0002 });
+        stream.push(Tick { time: 3, bid: 1.0001, ask: 1.0002 });
+        // late tick is sorted in
+        stream.push(Tick { time: 2, bid: 1.0000, ask: 1.0004 });
+        assert_eq!(stream.window.iter().map(|t| t.time).collect::<Vec<i64>>(), vec![1, 2, 3]);
+        // bid moved once, ask twice
+        assert!((stream.bid_ask_imbalance() - 1.0 / 3.0).abs() < 1e-12);
+
+        stream.push(Tick { time: 4, bid: 1.0002, ask: 1.0004 });
+        assert_eq!(stream.len(), 3);
+        assert_eq!(stream.window.front().unwrap().time, 2);
+        let mids = stream.mid_prices();
+        assert!((mids[0] - 1.0002).abs() < 1e-12);
+        assert!((stream.spread_avg() - (0.0004 + 0.0001 + 0.0002) / 3.0).abs() < 1e-9);
+    }
+}
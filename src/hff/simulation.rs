@@ -0,0 +1,70 @@
+/* seeded price paths, to test and backtest agents on reproducible markets */
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+// standard normal draw (Box-Muller)
+fn normal(rng: &mut SmallRng) -> f64 {
+    // 1 - [0, 1) keeps ln away from 0
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+// s0 then n_steps prices of dS = mu S dt + sigma S dW, sampled exactly in log space
+pub fn simulate_geometric_brownian_motion(s0: f64, mu: f64, sigma: f64, dt: f64, n_steps: usize, seed: u64) -> Vec<f64> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let drift = (mu - sigma * sigma / 2.0) * dt;
+    let vol = sigma * dt.sqrt();
+    let mut prices = Vec::with_capacity(n_steps + 1);
+    let mut s = s0;
+    prices.push(s);
+    for _ in 0..n_steps {
+        s *= (drift + vol * normal(&mut rng)).exp();
+        prices.push(s);
+    }
+    prices
+}
+
+// s0 then n_steps prices of the Ornstein-Uhlenbeck dX = theta (mu - X) dt + sigma dW (Euler steps)
+pub fn simulate_mean_reverting(s0: f64, theta: f64, mu: f64, sigma: f64, dt: f64, n_steps: usize, seed: u64) -> Vec<f64> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let vol = sigma * dt.sqrt();
+    let mut prices = Vec::with_capacity(n_steps + 1);
+    let mut x = s0;
+    prices.push(x);
+    for _ in 0..n_steps {
+        x += theta * (mu - x) * dt + vol * normal(&mut rng);
+        prices.push(x);
+    }
+    prices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{simulate_geometric_brownian_motion, simulate_mean_reverting};
+
+    #[test]
+    fn geometric_brownian_motion() {
+        let prices = simulate_geometric_brownian_motion(1.10, 0.0, 0.10, 1.0 / 252.0, 1000, 42);
+        assert_eq!(prices.len(), 1001);
+        assert_eq!(prices[0], 1.10);
+        assert!(prices.iter().all(|p| *p > 0.0));
+        assert_eq!(prices, simulate_geometric_brownian_motion(1.10, 0.0, 0.10, 1.0 / 252.0, 1000, 42));
+        assert_ne!(prices, simulate_geometric_brownian_motion(1.10, 0.0, 0.10, 1.0 / 252.0, 1000, 43));
+
+        // without volatility the path is the drift
+        let drift = simulate_geometric_brownian_motion(1.0, 0.05, 0.0, 1.0, 2, 0);
+        assert!((drift[2] - 0.1f64.exp()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mean_reverting() {
+        let prices = simulate_mean_reverting(1.20, 5.0, 1.10, 0.02, 1.0 / 252.0, 5000, 7);
+        assert_eq!(prices.len(), 5001);
+        assert_eq!(prices, simulate_mean_reverting(1.20, 5.0, 1.10, 0.02, 1.0 / 252.0, 5000, 7));
+        // pulled from 1.20 to around 1.10
+        let tail = &prices[1000..];
+        let mean = tail.iter().sum::<f64>() / tail.len() as f64;
+        assert!((mean - 1.10).abs() < 0.01, "mean {}", mean);
+    }
+}
@@ -12,9 +12,13 @@ use std::io::Read;
 use chrono::Utc;
 use chrono::DateTime;
 use chrono::LocalResult;
+use chrono::NaiveDate;
 use chrono::TimeZone;
 use core::slice::Iter;
 
+#[cfg(feature = "s3")]
+pub mod s3;
+
 pub struct DayBars {
     daypaths: Vec<PathBuf>,
     iter: usize,
@@ -34,6 +38,17 @@ impl DayBars {
             iter: 0,
         }
     }
+    // the next path next_day will look at, without reading it
+    pub fn peek(&self) -> Option<&PathBuf> {
+        self.daypaths.get(self.iter)
+    }
+
+    // date of the next path from its YYYYMMDD stem, without reading the file
+    pub fn peek_date(&self) -> Option<NaiveDate> {
+        let stem = self.peek()?.file_stem().and_then(OsStr::to_str)?;
+        NaiveDate::parse_from_str(stem.get(0..8)?, "%Y%m%d").ok()
+    }
+
     pub fn next_day(&mut self) -> Option<(LocalResult<DateTime<Utc>>, Vec<Bar>)> {
         let entry = self.daypaths.get(self.iter);
         self.iter = self.iter + 1;
@@ -41,10 +56,10 @@ impl DayBars {
             let path_buf = entry.unwrap();
             if let Some("zip") = path_buf.as_path().extension().and_then(OsStr::to_str) {
                 //entry.map(|e| e.as_path().extension().and_then(OsStr::to_str)) {
-                let year = path_buf.file_stem().unwrap().to_str().unwrap()[0..4].parse::<i32>().unwrap();
-                let month = path_buf.file_stem().unwrap().to_str().unwrap()[4..6].parse::<u32>().unwrap();
-                let day = path_buf.file_stem().unwrap().to_str().unwrap()[6..8].parse::<u32>().unwrap();
-                let date = Utc.with_ymd_and_hms(year, month, day, 0, 0, 0);
+                // a zip not named after a day is skipped like any other file
+                let Ok(date) = Lean::day_of(path_buf) else {
+                    return self.next_day();
+                };
                 if let Ok(data) = Lean::readZipStuff(&entry.unwrap()) {
                     return Some((date,data));
                 }
@@ -57,6 +72,22 @@ impl DayBars {
 }
 
 
+// period of resampled bars
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BarPeriod {
+    Minutes(u32),
+    Hours(u32),
+}
+
+impl BarPeriod {
+    pub fn millis(&self) -> u64 {
+        match self {
+            BarPeriod::Minutes(m) => *m as u64 * 60_000,
+            BarPeriod::Hours(h) => *h as u64 * 3_600_000,
+        }
+    }
+}
+
 pub struct Lean {
     pub dir: String,
 }
@@ -88,14 +119,42 @@ impl Lean {
         */
     }
 
+    // aggregate time sorted bars by period, each bar time is the start of its bucket
+    // (Lean forex bars are quotes without volume, only bid and ask OHLC are aggregated),
+    // no bars for an empty period
+    pub fn resample_bars(bars: &[Bar], period: BarPeriod) -> Vec<Bar> {
+        let millis = period.millis();
+        if millis == 0 {
+            return vec![];
+        }
+        bars.chunk_by(|a, b| a.time / millis == b.time / millis)
+            .filter_map(|bucket| Bar::aggregate(bucket[0].time / millis * millis, bucket))
+            .collect()
+    }
+
+    // date of a YYYYMMDD.zip file
+    fn day_of(path: &Path) -> Result<LocalResult<DateTime<Utc>>, Box<dyn Error>> {
+        let stem = path.file_stem().and_then(OsStr::to_str).ok_or("no file name")?;
+        let year = stem.get(0..4).ok_or("no year in file name")?.parse::<i32>()?;
+        let month = stem.get(4..6).ok_or("no month in file name")?.parse::<u32>()?;
+        let day = stem.get(6..8).ok_or("no day in file name")?.parse::<u32>()?;
+        Ok(Utc.with_ymd_and_hms(year, month, day, 0, 0, 0))
+    }
+
+    // the bars of a single YYYYMMDD.zip file, outside of a Lean directory
+    pub fn read_day(path: &Path) -> Result<(DateTime<Utc>, Vec<Bar>), Box<dyn Error>> {
+        let date = Lean::day_of(path)?.single().ok_or("invalid date in file name")?;
+        Ok((date, Lean::readZipStuff(path)?))
+    }
+
     pub fn readZipStuff(path: &Path)  -> Result<Vec<Bar>,Box<dyn Error>>{
-        let zipfile = std::fs::File::open(path).unwrap();
-        let mut archive = zip::ZipArchive::new(zipfile).unwrap();
+        let zipfile = std::fs::File::open(path)?;
+        let mut archive = zip::ZipArchive::new(zipfile)?;
         let mut file = archive.by_index(0)?;
         //eprintln!("Filename: {}", file.name());
 
         let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap();
+        file.read_to_string(&mut contents)?;
         let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(contents.as_bytes());
         let mut vec: Vec<Bar> = Vec::new();
 
@@ -106,4 +165,97 @@ impl Lean {
         }
         Ok(vec)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::hff::backtest::tests::bars_from_csv;
+    use super::{BarPeriod, DayBars, Lean};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use std::path::PathBuf;
+    use std::io::Write;
+
+    // a day of Lean quote bars zipped as in the YYYYMMDD.zip files
+    pub(crate) fn day_zip(csv: &[u8]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        zip.start_file("20230102_eurusd_minute_quote.csv", zip::write::FileOptions::default()).unwrap();
+        zip.write_all(csv).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn resample_bars() {
+        // time,obid,hbid,lbid,cbid,oask,hask,lask,cask, one minute bars
+        let csv = "0,1.0,1.2,0.9,1.1,1.1,1.3,1.0,1.2\n\
+                   60000,1.1,1.5,1.0,1.4,1.2,1.6,1.1,1.5\n\
+                   120000,1.4,1.4,0.8,0.9,1.5,1.5,0.9,1.0\n\
+                   300000,0.9,1.0,0.9,1.0,1.0,1.1,1.0,1.1\n";
+        let bars = bars_from_csv(csv);
+
+        let five = Lean::resample_bars(&bars, BarPeriod::Minutes(5));
+        assert_eq!(five.len(), 2);
+        assert_eq!(five[0].time, 0);
+        assert_eq!((five[0].cbid, five[0].cask), (0.9, 1.0));
+        assert_eq!(five[1].time, 300000);
+        assert_eq!(five[1].price(), 1.05);
+        assert!(format!("{:?}", five[0]).contains("obid: 1.0, hbid: 1.5, lbid: 0.8"));
+
+        let hour = Lean::resample_bars(&bars, BarPeriod::Hours(1));
+        assert_eq!(hour.len(), 1);
+        assert_eq!(hour[0].buy_price(), 1.1);
+
+        assert!(Lean::resample_bars(&bars, BarPeriod::Minutes(0)).is_empty());
+        assert!(Lean::resample_bars(&bars, BarPeriod::Hours(0)).is_empty());
+    }
+
+    #[test]
+    fn read_day() {
+        let dir = std::env::temp_dir().join(format!("lean_read_day_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("20230102.zip");
+        std::fs::write(&path, day_zip(b"0,1.0650,1.0660,1.0640,1.0655,1.0652,1.0662,1.0642,1.0657\n60000,1.0655,1.0670,1.0650,1.0665,1.0657,1.0672,1.0652,1.0667\n")).unwrap();
+
+        let (date, bars) = Lean::read_day(&path).unwrap();
+        assert_eq!(date, Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).unwrap());
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[1].time(), 60);
+        assert_eq!(bars[1].price(), 1.0666);
+
+        let bad = dir.join("notadate.zip");
+        std::fs::copy(&path, &bad).unwrap();
+        assert!(Lean::read_day(&bad).is_err());
+        // a missing file or one that is not a zip
+        assert!(Lean::read_day(&dir.join("20230103.zip")).is_err());
+        let text = dir.join("20230104.zip");
+        std::fs::write(&text, "0,1.0650\n").unwrap();
+        assert!(Lean::read_day(&text).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn peek() {
+        let dir = std::env::temp_dir().join(format!("lean_peek_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("20230102.zip");
+        std::fs::write(&path, day_zip(b"0,1.0650,1.0660,1.0640,1.0655,1.0652,1.0662,1.0642,1.0657\n")).unwrap();
+
+        let mut days = DayBars::new(vec![path.clone(), dir.join("20230103.zip")]);
+        assert_eq!(days.peek(), Some(&path));
+        assert_eq!(days.peek_date(), NaiveDate::from_ymd_opt(2023, 1, 2));
+        // peeking does not advance
+        assert_eq!(days.peek_date(), NaiveDate::from_ymd_opt(2023, 1, 2));
+
+        assert_eq!(days.next_day().unwrap().1.len(), 1);
+        assert_eq!(days.peek(), Some(&dir.join("20230103.zip")));
+        assert_eq!(days.peek_date(), NaiveDate::from_ymd_opt(2023, 1, 3));
+
+        // a zip not named after a day is skipped
+        let mut days = DayBars::new(vec![dir.join("notes.zip"), path.clone()]);
+        assert_eq!(days.next_day().unwrap().0.single(), Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).single());
+
+        let days = DayBars::new(vec![PathBuf::from("/data/notes.txt")]);
+        assert_eq!(days.peek_date(), None);
+        assert_eq!(DayBars::empty().peek(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
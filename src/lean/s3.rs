@@ -0,0 +1,201 @@
+use super::Lean;
+
+use aws_sdk_s3::Client;
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/*
+Access to Lean data on S3 with aws-sdk-s3: the zip objects under a prefix are listed (ListObjectsV2)
+and downloaded to cache_dir/bucket/key with the ETag of the object next to it (key.etag).
+An object is downloaded again only when S3 answers its ETag changed (GetObject with If-None-Match).
+*/
+pub struct AwsConfig {
+    // region, credentials and endpoint, e.g. aws_sdk_s3::Config::from(&aws_config::load_from_env().await)
+    pub s3_config: aws_sdk_s3::Config,
+    pub cache_dir: PathBuf,
+}
+
+type S3Result<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+impl Lean {
+    // Lean reading the zip files under prefix from the local cache, updated from the bucket first:
+    // list_entries(prefix) then lists the cached days. A key that is not a relative path without "..",
+    // so would be written outside of the cache, is an error and nothing is downloaded.
+    // The SDK runs on a runtime of its own thread, so this can be called from async code too.
+    pub fn from_s3_prefix(bucket: &str, prefix: &str, aws_config: AwsConfig) -> Result<Lean, Box<dyn Error>> {
+        let root = aws_config.cache_dir.join(bucket);
+        let client = Client::from_conf(aws_config.s3_config);
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| -> S3Result<()> {
+                    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+                    runtime.block_on(sync_prefix(&client, bucket, prefix, &root))
+                })
+                .join()
+        })
+        .map_err(|_| "the S3 download thread panicked")?
+        .map_err(|e| e as Box<dyn Error>)?;
+        Ok(Lean { dir: root.to_string_lossy().into_owned() })
+    }
+}
+
+// list the zip objects under prefix, then fetch the ones changed since they were cached
+async fn sync_prefix(client: &Client, bucket: &str, prefix: &str, root: &Path) -> S3Result<()> {
+    let mut keys: Vec<String> = vec![];
+    let mut pages = client.list_objects_v2().bucket(bucket).prefix(prefix).into_paginator().send();
+    while let Some(page) = pages.next().await {
+        keys.extend(page?.contents().iter().filter_map(|o| o.key()).filter(|k| k.ends_with(".zip")).map(str::to_owned));
+    }
+    if let Some(key) = keys.iter().find(|k| !Path::new(k.as_str()).components().all(|c| matches!(c, Component::Normal(_)))) {
+        return Err(format!("object key {} is outside of the cache", key).into());
+    }
+    for key in keys {
+        fetch_cached(client, bucket, &key, &root.join(&key)).await?;
+    }
+    Ok(())
+}
+
+fn etag_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".etag");
+    PathBuf::from(name)
+}
+
+// download the object to path unless the cached copy has the same ETag, true if downloaded
+async fn fetch_cached(client: &Client, bucket: &str, key: &str, path: &Path) -> S3Result<bool> {
+    let etag_path = etag_path(path);
+    let cached_etag = fs::read_to_string(&etag_path).ok().filter(|_| path.exists());
+
+    let request = client.get_object().bucket(bucket).key(key).set_if_none_match(cached_etag);
+    let object = match request.send().await {
+        Ok(object) => object,
+        Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 304) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let etag = object.e_tag().map(str::to_owned);
+    let bytes = object.body.collect().await?.into_bytes();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // written aside then renamed, an interrupted download never looks cached
+    let partial = path.with_extension("part");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)?;
+    match etag {
+        Some(etag) => fs::write(&etag_path, etag)?,
+        None => {
+            let _ = fs::remove_file(&etag_path);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::day_zip;
+    use super::super::Lean;
+    use super::AwsConfig;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    // S3 stand in for path style requests on any bucket: lists keys with the ETag "v1",
+    // answers 304 to a GetObject with that ETag, the object otherwise
+    fn serve(keys: Arc<Mutex<Vec<String>>>, downloads: Arc<AtomicUsize>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let body = day_zip(b"0,1.0650,1.0660,1.0640,1.0655,1.0652,1.0662,1.0642,1.0657\n");
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut cached = false;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    cached |= line.to_lowercase().starts_with("if-none-match: \"v1\"");
+                }
+                let target = request.split(' ').nth(1).unwrap_or_default();
+                let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                if query.contains("list-type=2") {
+                    let prefix = query
+                        .split('&')
+                        .find_map(|p| p.strip_prefix("prefix="))
+                        .unwrap_or_default()
+                        .replace("%2F", "/");
+                    let contents: String = keys
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|k| k.starts_with(&prefix))
+                        .map(|k| format!("<Contents><Key>{}</Key><ETag>&quot;v1&quot;</ETag><Size>{}</Size></Contents>", k, body.len()))
+                        .collect();
+                    let xml = format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+                         <Name>leandata</Name><Prefix>{}</Prefix><MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                        prefix, contents
+                    );
+                    let header = format!("HTTP/1.1 200 OK\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", xml.len());
+                    stream.write_all(header.as_bytes()).unwrap();
+                    stream.write_all(xml.as_bytes()).unwrap();
+                } else if path.split('/').nth(2).is_none() {
+                    stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+                } else if cached {
+                    stream.write_all(b"HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n").unwrap();
+                } else {
+                    downloads.fetch_add(1, Ordering::SeqCst);
+                    let header = format!("HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                    stream.write_all(header.as_bytes()).unwrap();
+                    stream.write_all(&body).unwrap();
+                }
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn s3_cache() {
+        let key = "forex/oanda/minute/eurusd/20230102.zip";
+        // outside of the prefix, never requested
+        let keys = Arc::new(Mutex::new(vec![key.to_string(), "equity/usa/minute/spy/20230102.zip".to_string()]));
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let port = serve(keys.clone(), downloads.clone());
+        let cache_dir = std::env::temp_dir().join(format!("lean_s3_{}", std::process::id()));
+        let config = || AwsConfig {
+            s3_config: aws_sdk_s3::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("us-east-1"))
+                .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+                .endpoint_url(format!("http://127.0.0.1:{}", port))
+                .force_path_style(true)
+                .build(),
+            cache_dir: cache_dir.clone(),
+        };
+
+        let lean = Lean::from_s3_prefix("leandata", "forex/oanda", config()).unwrap();
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        let mut days = lean.list_entries(&"forex/oanda/minute/eurusd".to_string());
+        assert_eq!(days.next_day().unwrap().1.len(), 1);
+
+        // unchanged ETag, read from the cache
+        Lean::from_s3_prefix("leandata", "forex/oanda", config()).unwrap();
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        assert_eq!(std::fs::read_to_string(cache_dir.join("leandata").join(format!("{}.etag", key))).unwrap(), "\"v1\"");
+
+        // a key escaping the cache is rejected before any download
+        keys.lock().unwrap().push("forex/oanda/../../../escape.zip".to_string());
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+        assert!(Lean::from_s3_prefix("leandata", "forex/oanda", config()).is_err());
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        assert!(!cache_dir.exists());
+    }
+}
@@ -4,6 +4,35 @@ pub mod hff;
 pub mod oanda;
 pub mod lean;
 
+// degenerate arithmetic on gears and PL, reported by the checked_ variants
+#[derive(Debug, Clone, PartialEq)]
+pub enum MathError {
+    // an input or the result is NaN or infinite
+    NonFinite(&'static str),
+    // a price range with an end not above its start
+    EmptyRange { start: f64, end: f64 },
+    // a flat position has no average price
+    ZeroExposure,
+}
+
+impl std::fmt::Display for MathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MathError::NonFinite(what) => write!(f, "{} is not finite", what),
+            MathError::EmptyRange { start, end } => write!(f, "empty price range [{}, {}]", start, end),
+            MathError::ZeroExposure => write!(f, "the average price of a flat position is undefined"),
+        }
+    }
+}
+
+impl std::error::Error for MathError {}
+
+pub type Result<T> = std::result::Result<T, MathError>;
+
+pub(crate) fn finite(x: f64, what: &'static str) -> Result<f64> {
+    if x.is_finite() { Ok(x) } else { Err(MathError::NonFinite(what)) }
+}
+
 // GearRange defines exposure gear linear between price limits
 #[derive(Debug,Deserialize,Serialize, Clone)]
 pub struct GearRange {
@@ -17,6 +46,34 @@ impl GearRange {
     fn g(&self, x: f64) -> f64 {
         self.g_start + (x - self.p_start)*(self.g_end - self.g_start)/(self.p_end - self.p_start)
     }
+
+    // g, failing on an empty range or a non finite price
+    pub fn checked_g(&self, x: f64) -> Result<f64> {
+        finite(self.p_start, "p_start")?;
+        finite(self.p_end, "p_end")?;
+        if self.p_end <= self.p_start {
+            return Err(MathError::EmptyRange { start: self.p_start, end: self.p_end });
+        }
+        finite(x, "price")?;
+        finite(self.g(x), "gear")
+    }
+}
+
+// gear below p_0 and from p_n:
+// Clamp holds g_0 and g_n, Linear extends the slope of the first and last ranges (past |g| = 1 if steep enough),
+// DecayToZero goes from g_0 and g_n to 0 as exp(-rate * distance to the range)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+pub enum ExtrapMode {
+    #[default]
+    Clamp,
+    Linear,
+    DecayToZero { rate: f64 },
+}
+
+impl ExtrapMode {
+    pub fn is_clamp(&self) -> bool {
+        *self == ExtrapMode::Clamp
+    }
 }
 
 // Gear defines gear below and above extreme prices and a vector of gears for specified intervals
@@ -33,6 +90,10 @@ pub struct Gear {
     pub p_n: f64,
     pub g_n: f64,
 
+    // gear outside [p_0, p_n], Clamp for the files written before the modes
+    #[serde(default, skip_serializing_if = "ExtrapMode::is_clamp")]
+    pub extrap: ExtrapMode,
+
 }
 
 impl Gear {
@@ -49,6 +110,7 @@ impl Gear {
             }],
             p_n: price1,
             g_n: 0.0,
+            extrap: ExtrapMode::Clamp,
         }
     }
 
@@ -64,6 +126,7 @@ impl Gear {
             }],
             p_n: price1,
             g_n: -1.0,
+            extrap: ExtrapMode::Clamp,
         }
     }
 
@@ -74,6 +137,7 @@ impl Gear {
             g_i: vec![],
             p_n: 1.0,
             g_n: if dir > 0 { 1.0} else { -1.0 },
+            extrap: ExtrapMode::Clamp,
         }
     }
 
@@ -90,6 +154,7 @@ impl Gear {
             }],
             p_n: price1,
             g_n: -1.0,
+            extrap: ExtrapMode::Clamp,
         }
     }
 
@@ -105,9 +170,22 @@ impl Gear {
             }],
             p_n: price1,
             g_n: g_1,
+            extrap: ExtrapMode::Clamp,
         }
     }
 
+    // segment, failing on non finite inputs or price1 not above price0
+    pub fn checked_segment(price0: f64, g_0: f64, price1: f64, g_1: f64) -> Result<Self> {
+        finite(price0, "price0")?;
+        finite(price1, "price1")?;
+        finite(g_0, "g_0")?;
+        finite(g_1, "g_1")?;
+        if price1 <= price0 {
+            return Err(MathError::EmptyRange { start: price0, end: price1 });
+        }
+        Ok(Self::segment(price0, g_0, price1, g_1))
+    }
+
     pub fn jump(price0: f64, g_0: f64, g_n: f64) -> Self {
         Self {
             p_0: price0,
@@ -115,6 +193,7 @@ impl Gear {
             g_i: vec![],
             p_n: price0,
             g_n: g_n,
+            extrap: ExtrapMode::Clamp,
         }
     }
 
@@ -131,6 +210,7 @@ impl Gear {
                 }],
                 p_n: price0 + scale,
                 g_n: 0.0,
+                extrap: ExtrapMode::Clamp,
             }
         } else {
             Self {
@@ -144,16 +224,294 @@ impl Gear {
                 }],
                 p_n: price0 + imax*scale,
                 g_n: -1.0,
+                extrap: ExtrapMode::Clamp,
             }
         }
     }
 
+    // g = price0 / x on [price0, price1] (negated for a negative direction), exact at every scale step
+    // from price0 and linear in between, held at the limits outside: g * max_exposure keeps
+    // max_exposure * price0 of notional
+    pub fn fixed_notional(direction: i64, price0: f64, price1: f64, scale: f64) -> Self {
+        let sign = if direction < 0 { -1.0 } else { 1.0 };
+        let steps = if scale > 0.0 { ((price1 - price0) / scale).ceil().max(1.0) as usize } else { 1 };
+        let prices: Vec<f64> = (0..=steps)
+            .map(|k| if k == steps { price1 } else { price0 + k as f64 * scale })
+            .collect();
+        Self {
+            p_0: price0,
+            g_0: sign,
+            g_i: prices.windows(2).map(|w| GearRange {
+                p_start: w[0],
+                g_start: sign * price0 / w[0],
+                p_end: w[1],
+                g_end: sign * price0 / w[1],
+            }).collect(),
+            p_n: price1,
+            g_n: sign * price0 / price1,
+            extrap: ExtrapMode::Clamp,
+        }
+    }
+
+    // g_i is sorted asc, binary search the last range starting at or below x
     pub fn g(&self, x: f64) -> f64 {
-        if x < self.p_0 {return self.g_0;}
-        if x >= self.p_n {return self.g_n;}
+        if let Some(g) = self.g_outside(x) {return g;}
+
+        let i = self.g_i.partition_point(|g| g.p_start <= x);
+        self.g_in_range(x, i.wrapping_sub(1))
+    }
+
+    // same as g, with the index of the last range hit: consecutive prices are usually in the same range
+    pub fn g_with_hint(&self, x: f64, hint: &mut usize) -> f64 {
+        if let Some(g) = self.g_outside(x) {return g;}
+
+        if let Some(g) = self.g_i.get(*hint) {
+            if x >= g.p_start && x < g.p_end {
+                return g.g(x);
+            }
+        }
+        *hint = self.g_i.partition_point(|g| g.p_start <= x).wrapping_sub(1);
+        self.g_in_range(x, *hint)
+    }
+
+    pub fn with_extrap(mut self, extrap: ExtrapMode) -> Self {
+        self.extrap = extrap;
+        self
+    }
+
+    // the price, gear and slope the gear is extrapolated from, below p_0 or from p_n
+    fn edge(&self, x: f64) -> Option<(f64, f64, f64)> {
+        let range_slope = |g: &GearRange| (g.g_end - g.g_start) / (g.p_end - g.p_start);
+        if x < self.p_0 {
+            Some((self.p_0, self.g_0, self.g_i.first().map_or(0.0, range_slope)))
+        } else if x >= self.p_n {
+            Some((self.p_n, self.g_n, self.g_i.last().map_or(0.0, range_slope)))
+        } else {
+            None
+        }
+    }
+
+    // g outside [p_0, p_n[ following the extrapolation mode, None inside
+    fn g_outside(&self, x: f64) -> Option<f64> {
+        self.edge(x).map(|edge| self.extrapolate(edge, x))
+    }
+
+    fn extrapolate(&self, (p, g, slope): (f64, f64, f64), x: f64) -> f64 {
+        match self.extrap {
+            ExtrapMode::Clamp => g,
+            ExtrapMode::Linear => g + slope * (x - p),
+            ExtrapMode::DecayToZero { rate } => g * (-rate * (x - p).abs()).exp(),
+        }
+    }
+
+    // derivative of g at x, 0 between ranges and outside [p_0, p_n[ unless extrapolated
+    pub fn slope(&self, x: f64) -> f64 {
+        if let Some((p, _, slope)) = self.edge(x) {
+            return match self.extrap {
+                ExtrapMode::Clamp => 0.0,
+                ExtrapMode::Linear => slope,
+                // the gear moves toward 0 away from the range
+                ExtrapMode::DecayToZero { rate } => -rate * (x - p).signum() * self.g(x),
+            };
+        }
+
+        let i = self.g_i.partition_point(|g| g.p_start <= x);
+        match self.g_i.get(i.wrapping_sub(1)) {
+            Some(g) if x < g.p_end => (g.g_end - g.g_start) / (g.p_end - g.p_start),
+            _ => 0.0,
+        }
+    }
+
+    fn g_in_range(&self, x: f64, i: usize) -> f64 {
+        match self.g_i.get(i) {
+            Some(g) if x < g.p_end => g.g(x),
+            _ => 0.0,
+        }
+    }
+
+    // lowest price where the gear reaches zero, None if it never does
+    pub fn zero_price(&self) -> Option<f64> {
+        // a linear extrapolation may cross zero below p_0, the other modes never do
+        if let (ExtrapMode::Linear, Some((p, g, slope))) = (self.extrap, self.edge(f64::NEG_INFINITY)) {
+            if g * slope > 0.0 {return Some(p - g / slope);}
+        }
+        if self.g_0 == 0.0 {return Some(self.p_0);}
+
+        // last price and gear value scanned
+        let (mut p, mut g) = (self.p_0, self.g_0);
+        for r in self.g_i.iter() {
+            // no range between p and p_start: the gear is zero there
+            if r.p_start > p {return Some(p);}
+            // the gear is zero or jumps across zero at p_start
+            if r.g_start == 0.0 || r.g_start.signum() != g.signum() {return Some(r.p_start);}
+            // the gear crosses zero in the range
+            if r.g_end == 0.0 || r.g_end.signum() != r.g_start.signum() {
+                return Some(r.p_start - r.g_start * (r.p_end - r.p_start) / (r.g_end - r.g_start));
+            }
+            (p, g) = (r.p_end, r.g_end);
+        }
+        if self.p_n > p {return Some(p);}
+        if self.g_n == 0.0 || self.g_n.signum() != g.signum() {return Some(self.p_n);}
+        // or above p_n
+        if let (ExtrapMode::Linear, Some((p, g, slope))) = (self.extrap, self.edge(f64::INFINITY)) {
+            if g * slope < 0.0 {return Some(p - g / slope);}
+        }
+        None
+    }
+
+    // staircase approximation: gear values rounded to the nearest of levels equal steps
+    // between the lowest and highest gear, ranges are split where the rounded value changes
+    pub fn quantize(&self, levels: usize) -> Gear {
+        let values = self.g_i.iter().flat_map(|r| [r.g_start, r.g_end]).chain([self.g_0, self.g_n]);
+        let g_min = values.clone().fold(f64::INFINITY, f64::min);
+        let g_max = values.fold(f64::NEG_INFINITY, f64::max);
+        if levels == 0 || g_max <= g_min {
+            return self.clone();
+        }
+        let step = (g_max - g_min) / levels as f64;
+        let q = |g: f64| g_min + ((g - g_min) / step).round() * step;
+
+        let mut g_i = vec![];
+        for r in self.g_i.iter() {
+            let mut prices = vec![r.p_start];
+            if r.g_start != r.g_end {
+                // prices where the range crosses half way between two levels
+                let (lo, hi) = (r.g_start.min(r.g_end), r.g_start.max(r.g_end));
+                prices.extend(
+                    (0..levels)
+                        .map(|k| g_min + (k as f64 + 0.5) * step)
+                        .filter(|t| *t > lo && *t < hi)
+                        .map(|t| r.p_start + (t - r.g_start) * (r.p_end - r.p_start) / (r.g_end - r.g_start)),
+                );
+                prices.sort_by(|a, b| a.total_cmp(b));
+            }
+            prices.push(r.p_end);
+            for w in prices.windows(2) {
+                let g = q(r.g((w[0] + w[1]) / 2.0));
+                g_i.push(GearRange { p_start: w[0], g_start: g, p_end: w[1], g_end: g });
+            }
+        }
+        Gear { p_0: self.p_0, g_0: q(self.g_0), g_i, p_n: self.p_n, g_n: q(self.g_n), extrap: self.extrap }
+    }
+
+    // piecewise linear least squares fit of (price, gear) samples with at most segments ranges:
+    // the samples are split in consecutive groups of at least 2 (dynamic programming on the total
+    // squared error), each group is fitted by its own line, ranging to the first price of the next group
+    pub fn fit(points: &[(f64, f64)], segments: usize) -> Gear {
+        let mut points = points.to_vec();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let n = points.len();
+        if n == 0 {
+            return Gear::constant(1);
+        }
+        let segments = segments.clamp(1, (n / 2).max(1));
+
+        // line (intercept, slope) and squared error of the samples i..=j
+        let mut lines = vec![vec![(0.0, 0.0, 0.0); n]; n];
+        for (i, row) in lines.iter_mut().enumerate() {
+            let (mut sx, mut sy, mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for (j, &(x, y)) in points.iter().enumerate().skip(i) {
+                sx += x;
+                sy += y;
+                sxx += x * x;
+                sxy += x * y;
+                syy += y * y;
+                let k = (j - i + 1) as f64;
+                let d = k * sxx - sx * sx;
+                let b = if d.abs() > f64::EPSILON * k * sxx { (k * sxy - sx * sy) / d } else { 0.0 };
+                let a = (sy - b * sx) / k;
+                let sse = syy - 2.0 * a * sy - 2.0 * b * sxy + k * a * a + 2.0 * a * b * sx + b * b * sxx;
+                row[j] = (a, b, sse.max(0.0));
+            }
+        }
+
+        // best[s][j]: lowest error of the first j samples in s groups, start of the last group
+        let mut best = vec![vec![(f64::INFINITY, 0); n + 1]; segments + 1];
+        best[0][0] = (0.0, 0);
+        for s in 1..=segments {
+            for j in 2..=n {
+                for i in 0..=j - 2 {
+                    let cost = best[s - 1][i].0 + lines[i][j - 1].2;
+                    if cost < best[s][j].0 {
+                        best[s][j] = (cost, i);
+                    }
+                }
+            }
+        }
+        // fewer groups can fit as well, keep the first reaching the lowest error
+        let used = (1..=segments)
+            .fold(1, |used, s| if best[s][n].0 < best[used][n].0 { s } else { used });
+
+        let mut starts = vec![];
+        let mut j = n;
+        for s in (1..=used).rev() {
+            let i = best[s][j].1;
+            starts.push((i, j - 1));
+            j = i;
+        }
+        starts.reverse();
+
+        let p_n = points[n - 1].0;
+        let g_i: Vec<GearRange> = starts
+            .iter()
+            .enumerate()
+            .map(|(k, (i, j))| {
+                let (a, b, _) = lines[*i][*j];
+                let p_start = points[*i].0;
+                let p_end = starts.get(k + 1).map_or(p_n, |next| points[next.0].0);
+                GearRange { p_start, g_start: a + b * p_start, p_end, g_end: a + b * p_end }
+            })
+            .collect();
+        Gear {
+            p_0: points[0].0,
+            g_0: g_i[0].g_start,
+            p_n,
+            g_n: g_i[g_i.len() - 1].g_end,
+            extrap: ExtrapMode::Clamp,
+            g_i,
+        }
+    }
+
+    // signed integral of the gear between prices from and to, negative when to < from
+    pub fn integrate(&self, from: f64, to: f64) -> f64 {
+        if to < from {
+            return -self.integrate(to, from);
+        }
+        let mut sum = self.integrate_outside(from, to.min(self.p_0)) + self.integrate_outside(from.max(self.p_n), to);
+        for r in self.g_i.iter() {
+            let lo = from.max(r.p_start).max(self.p_0);
+            let hi = to.min(r.p_end).min(self.p_n);
+            if hi > lo {
+                sum += (r.g(lo) + r.g(hi)) / 2.0 * (hi - lo);
+            }
+        }
+        sum
+    }
+
+    // integral of g on [lo, hi] lying on one side of the range, 0 if empty
+    fn integrate_outside(&self, lo: f64, hi: f64) -> f64 {
+        let edge = match self.edge(lo) {
+            Some(edge) if hi > lo => edge,
+            _ => return 0.0,
+        };
+        let (lo_g, hi_g) = (self.extrapolate(edge, lo), self.extrapolate(edge, hi));
+        match self.extrap {
+            // the antiderivative of g * exp(-rate * |x - p|) is -(x - p).signum() * g_x / rate
+            ExtrapMode::DecayToZero { rate } if rate > 0.0 => {
+                let sign = if hi <= self.p_0 { -1.0 } else { 1.0 };
+                sign * (lo_g - hi_g) / rate
+            }
+            // constant or linear: exact with the trapezoid
+            _ => (lo_g + hi_g) / 2.0 * (hi - lo),
+        }
+    }
+
+    // reference linear scan of the ranges, kept to check and benchmark g
+    pub fn g_linear(&self, x: f64) -> f64 {
+        if let Some(g) = self.g_outside(x) {return g;}
 
         for g in self.g_i.iter() {
-            if (x >= g.p_start && x < g.p_end) {
+            if x >= g.p_start && x < g.p_end {
                 return  g.g(x);
             }
         }
@@ -166,7 +524,8 @@ impl Gear {
 
 #[cfg(test)]
 mod tests {
-    use super::Gear;
+    use super::{ExtrapMode, Gear, GearRange, MathError};
+    use proptest::prelude::*;
     #[test]
     fn exploration() {
         assert_eq!(2 + 2, 4);
@@ -197,5 +556,214 @@ mod tests {
         assert_eq!(gear.g(1.5), 1.0);
     }
 
+    #[test]
+    fn checked_arithmetic() {
+        let range = GearRange { p_start: 1.0, g_start: 1.0, p_end: 1.0, g_end: -1.0 };
+        assert_eq!(range.checked_g(1.0), Err(MathError::EmptyRange { start: 1.0, end: 1.0 }));
+        let range = GearRange { p_start: 0.5, g_start: 1.0, p_end: 1.5, g_end: -1.0 };
+        assert_eq!(range.checked_g(1.0), Ok(0.0));
+        assert_eq!(range.checked_g(f64::NAN), Err(MathError::NonFinite("price")));
+        let range = GearRange { p_start: 0.5, g_start: f64::INFINITY, p_end: 1.5, g_end: -1.0 };
+        assert_eq!(range.checked_g(1.0), Err(MathError::NonFinite("gear")));
+
+        assert!(Gear::checked_segment(0.5, 1.0, 1.5, -1.0).is_ok());
+        assert_eq!(Gear::checked_segment(1.5, 1.0, 0.5, -1.0).unwrap_err(), MathError::EmptyRange { start: 1.5, end: 0.5 });
+        assert_eq!(Gear::checked_segment(0.5, f64::NAN, 1.5, -1.0).unwrap_err(), MathError::NonFinite("g_0"));
+    }
+
+    #[test]
+    fn fit() {
+        // a line is recovered whatever the number of segments
+        let points: Vec<(f64, f64)> = (0..=20).map(|i| 0.9 + i as f64 * 0.01).map(|p| (p, 1.0 - (p - 0.9) * 10.0)).collect();
+        for segments in [1, 3] {
+            let gear = Gear::fit(&points, segments);
+            for (p, g) in points.iter() {
+                assert!((gear.g(*p) - g).abs() < 1e-9);
+            }
+            assert!((gear.g(0.5) - 1.0).abs() < 1e-9);
+            assert!((gear.g(1.5) + 1.0).abs() < 1e-9);
+        }
+
+        // a parabola, closer with more segments
+        let points: Vec<(f64, f64)> = (0..=40).map(|i| i as f64 * 0.05).map(|p| (p, 1.0 - (p - 1.0) * (p - 1.0))).collect();
+        let max_error = |gear: &Gear| points.iter().map(|(p, g)| (gear.g(*p) - g).abs()).fold(0.0, f64::max);
+        let four = Gear::fit(&points, 4);
+        assert_eq!(four.g_i.len(), 4);
+        assert!(max_error(&four) < 0.05);
+        assert!(max_error(&Gear::fit(&points, 8)) < max_error(&four));
+        assert!(max_error(&Gear::fit(&points, 1)) > 0.3);
+    }
+
+    #[test]
+    fn slope() {
+        let gear = Gear {
+            p_0: 0.5,
+            g_0: 1.0,
+            g_i: vec![
+                GearRange { p_start: 0.5, g_start: 1.0, p_end: 1.0, g_end: 0.5 },
+                GearRange { p_start: 1.2, g_start: 0.0, p_end: 1.5, g_end: -1.0 },
+            ],
+            p_n: 1.5,
+            g_n: -1.0,
+            extrap: ExtrapMode::Clamp,
+        };
+        let h = 1e-6;
+        for x in [0.6, 0.75, 0.99, 1.25, 1.4] {
+            let finite_difference = (gear.g(x + h) - gear.g(x - h)) / (2.0 * h);
+            assert!((gear.slope(x) - finite_difference).abs() < 1e-6, "{}", x);
+        }
+        assert_eq!(gear.slope(0.75), -1.0);
+        assert!((gear.slope(1.3) + 1.0 / 0.3).abs() < 1e-12);
+        // outside the ranges
+        assert_eq!(gear.slope(0.4), 0.0);
+        assert_eq!(gear.slope(1.1), 0.0);
+        assert_eq!(gear.slope(1.5), 0.0);
+        assert_eq!(Gear::constant(1).slope(1.0), 0.0);
+    }
+
+    #[test]
+    fn extrapolation() {
+        // 1 at 1.0 down to -1 at 2.0
+        let clamp = Gear::segment(1.0, 1.0, 2.0, -1.0);
+        assert_eq!(clamp.g(-10.0), 1.0);
+        assert_eq!(clamp.g(10.0), -1.0);
+        assert_eq!(clamp.slope(10.0), 0.0);
 
-}
\ No newline at end of file
+        let linear = clamp.clone().with_extrap(ExtrapMode::Linear);
+        assert_eq!(linear.g(0.0), 3.0);
+        assert_eq!(linear.g(10.0), -17.0);
+        assert_eq!(linear.g(1.5), clamp.g(1.5));
+        assert_eq!(linear.slope(-5.0), -2.0);
+
+        let decay = clamp.clone().with_extrap(ExtrapMode::DecayToZero { rate: 2.0 });
+        assert!((decay.g(0.0) - (-2.0f64).exp()).abs() < 1e-12);
+        assert!((decay.g(3.0) + (-2.0f64).exp()).abs() < 1e-12);
+        assert!(decay.g(100.0).abs() < 1e-12 && decay.g(-100.0).abs() < 1e-12);
+        assert_eq!(decay.g(2.0), -1.0);
+        assert!(decay.slope(3.0) > 0.0 && decay.slope(0.0) > 0.0);
+
+        // zero crossings of the extrapolation
+        let falling = Gear::segment(1.0, 1.0, 2.0, 0.5);
+        assert_eq!(falling.zero_price(), None);
+        assert_eq!(falling.clone().with_extrap(ExtrapMode::Linear).zero_price(), Some(3.0));
+        assert_eq!(falling.clone().with_extrap(ExtrapMode::DecayToZero { rate: 2.0 }).zero_price(), None);
+        let rising = Gear::segment(1.0, 0.5, 2.0, 1.0).with_extrap(ExtrapMode::Linear);
+        assert_eq!(rising.zero_price(), Some(0.0));
+        assert_eq!(linear.zero_price(), Some(1.5));
+
+        // integrals outside the range, against a midpoint sum
+        for gear in [&clamp, &linear, &decay] {
+            for (from, to) in [(-1.0, 1.0), (2.0, 4.0), (0.0, 3.5)] {
+                let n = 100_000;
+                let h = (to - from) / n as f64;
+                let sum: f64 = (0..n).map(|i| gear.g(from + (i as f64 + 0.5) * h) * h).sum();
+                assert!((gear.integrate(from, to) - sum).abs() < 1e-6, "{:?} {} {}", gear.extrap, from, to);
+            }
+        }
+
+        // written only when not the default, files without it are clamped
+        assert!(!serde_json::to_string(&clamp).unwrap().contains("extrap"));
+        let json = serde_json::to_string(&linear).unwrap();
+        assert_eq!(serde_json::from_str::<Gear>(&json).unwrap().extrap, ExtrapMode::Linear);
+        let json = json.replace(",\"extrap\":\"Linear\"", "");
+        assert_eq!(serde_json::from_str::<Gear>(&json).unwrap().extrap, ExtrapMode::Clamp);
+    }
+
+    #[test]
+    fn integrate() {
+        let gear = Gear::symmetric(0.5, 1.5);
+        assert_eq!(gear.integrate(0.5, 1.5), 0.0);
+        assert_eq!(gear.integrate(0.5, 1.0), 0.25);
+        assert_eq!(gear.integrate(1.0, 0.5), -0.25);
+        // constant gear outside the ranges
+        assert_eq!(gear.integrate(0.0, 0.5), 0.5);
+        assert_eq!(gear.integrate(1.5, 2.0), -0.5);
+        assert_eq!(Gear::positive(1.0, 2.0).integrate(1.0, 2.0), 0.5);
+        assert_eq!(Gear::jump(1.0, 1.0, -1.0).integrate(0.0, 3.0), -1.0);
+    }
+
+    #[test]
+    fn constant_round_trip() {
+        let gear: Gear = serde_json::from_str(&serde_json::to_string(&Gear::constant(1)).unwrap()).unwrap();
+        assert!(gear.g_i.is_empty());
+        // p_0 == p_n, no interpolation range
+        assert_eq!(gear.g(0.5), 1.0);
+        assert_eq!(gear.g(1.0), 1.0);
+        assert_eq!(gear.g(1.5), 1.0);
+
+        let gear: Gear = serde_json::from_str(r#"{"p_0":1.0,"g_0":0.0,"g_i":[],"p_n":1.0,"g_n":-1.0}"#).unwrap();
+        assert_eq!(gear.g(0.5), 0.0);
+        assert_eq!(gear.g(1.0), -1.0);
+    }
+
+    fn gear_strategy() -> impl Strategy<Value = Gear> {
+        (0.1f64..10.0, 0.0f64..5.0, -1.0f64..1.0, -1.0f64..1.0, 0usize..3).prop_map(|(p_0, span, g_0, g_n, n)| {
+            let p_n = p_0 + span;
+            let g_i = (0..n)
+                .map(|i| {
+                    let p_start = p_0 + span * i as f64 / n as f64;
+                    let p_end = p_0 + span * (i + 1) as f64 / n as f64;
+                    GearRange { p_start, g_start: g_0, p_end, g_end: g_n }
+                })
+                .collect();
+            Gear { p_0, g_0, g_i, p_n, g_n, extrap: ExtrapMode::Clamp }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn serde_round_trip(gear in gear_strategy(), x in 0.0f64..20.0) {
+            let json = serde_json::to_string(&gear).unwrap();
+            let back: Gear = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(back.g_i.len(), gear.g_i.len());
+            prop_assert_eq!(serde_json::to_string(&back).unwrap(), json);
+            prop_assert!(back.g(x).is_finite());
+            prop_assert_eq!(back.g(x), gear.g(x));
+        }
+    }
+
+    fn multi_range_strategy() -> impl Strategy<Value = Gear> {
+        // sorted ranges, possibly with gaps between them
+        (0.5f64..1.5, prop::collection::vec((0.0f64..0.1, 0.001f64..0.1, -1.0f64..1.0, -1.0f64..1.0), 0..20)).prop_map(|(p_0, steps)| {
+            let mut p = p_0;
+            let g_i: Vec<GearRange> = steps
+                .iter()
+                .map(|(gap, width, g_start, g_end)| {
+                    let p_start = p + gap;
+                    p = p_start + width;
+                    GearRange { p_start, g_start: *g_start, p_end: p, g_end: *g_end }
+                })
+                .collect();
+            Gear { p_0, g_0: 1.0, g_i, p_n: p, g_n: -1.0, extrap: ExtrapMode::Clamp }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn binary_search_matches_linear(gear in multi_range_strategy(), xs in prop::collection::vec(0.0f64..5.0, 1..50)) {
+            let mut hint = 0;
+            for x in xs {
+                prop_assert_eq!(gear.g(x), gear.g_linear(x));
+                prop_assert_eq!(gear.g_with_hint(x, &mut hint), gear.g_linear(x));
+            }
+        }
+    }
+
+    #[test]
+    fn quantize() {
+        let gear = Gear::symmetric(0.5, 1.5);
+        let stairs = gear.quantize(4);
+        // levels -1, -0.5, 0, 0.5, 1
+        let nearest = |g: f64| (g * 2.0).round() / 2.0;
+        for x in [0.4, 0.55, 0.63, 0.7, 0.9, 1.0, 1.1, 1.2, 1.3, 1.45, 1.6] {
+            assert_eq!(stairs.g(x), nearest(gear.g(x)), "at {}", x);
+        }
+        assert_eq!(stairs.g_i.len(), 5);
+        assert!(stairs.g_i.iter().all(|r| r.g_start == r.g_end));
+
+        let buyer = Gear::positive(0.9, 1.1).quantize(4);
+        assert_eq!(buyer.g(0.99), 0.5);
+        assert_eq!(buyer.g(0.96), 0.75);
+        assert_eq!(Gear::constant(1).quantize(4).g(1.0), 1.0);
+    }
+}
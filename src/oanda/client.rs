@@ -1,12 +1,102 @@
 use super::*;
-use std::error::Error;
 use serde_json::json;
+use std::future::Future;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// the calls to the Oanda API needed by the trading loop, so a mock can stand in for the Client
+pub trait OandaApi {
+    fn get_pricing(&self, instrument: String) -> impl Future<Output = Option<PricingResponse>>;
+    fn get_open_positions(&self) -> impl Future<Output = Result<Vec<Position>, OandaError>>;
+    fn post_order_request(&self, order: &OrderRequest) -> impl Future<Output = Option<PostOrderResponse>>;
+    // the fill of the order posted with this client id, None if it was not executed
+    fn find_order_fill(&self, client_id: &str) -> impl Future<Output = Result<Option<OrderFill>, OandaError>>;
+
+    // post the order with a client id; when no response comes back the order may still have been
+    // executed, it is looked up by its client id before posting again, so it never fills twice
+    fn submit_confirmed(&self, order: &OrderRequest, client_id: &str) -> impl Future<Output = Result<OrderFill, OandaError>> {
+        self.submit_confirmed_with(order, client_id, SUBMIT_ATTEMPTS)
+    }
+
+    fn submit_confirmed_with(
+        &self,
+        order: &OrderRequest,
+        client_id: &str,
+        attempts: usize,
+    ) -> impl Future<Output = Result<OrderFill, OandaError>> {
+        async move {
+            let order = order.clone().with_client_id(client_id);
+            for _ in 0..attempts {
+                match self.post_order_request(&order).await {
+                    Some(response) => {
+                        return match response.outcome() {
+                            Some(OrderOutcome::Filled(fill)) => Ok(fill),
+                            // answered: the order is on the book, it must not be posted again
+                            Some(OrderOutcome::Pending(id)) => Err(OandaError::Pending(id)),
+                            Some(OrderOutcome::Cancelled(_)) | None => Err(OandaError::NotFilled),
+                        }
+                    }
+                    // a lookup error leaves the order status unknown: no blind retry
+                    None => {
+                        if let Some(fill) = self.find_order_fill(client_id).await? {
+                            return Ok(fill);
+                        }
+                    }
+                }
+            }
+            Err(OandaError::Unconfirmed(client_id.to_string()))
+        }
+    }
+}
+
+// posts of an order by submit_confirmed
+pub const SUBMIT_ATTEMPTS: usize = 3;
+
+pub const LIVE_URL: &str = "https://api-fxtrade.oanda.com";
+pub const PRACTICE_URL: &str = "https://api-fxpractice.oanda.com";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OandaEnv {
+    Live,
+    Practice,
+}
+
+impl OandaEnv {
+    pub fn url(&self) -> &'static str {
+        match self {
+            OandaEnv::Live => LIVE_URL,
+            OandaEnv::Practice => PRACTICE_URL,
+        }
+    }
+}
+
+// default timeout of a call to the API
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// one http client for all the calls: connections are kept alive and reused
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Duration::from_secs(60))
+        .build()
+        .unwrap_or_else(|_| reqwest::Client::new())
+}
+
+// tick and home conversions of an instrument, with the time they were cached
+type CachedPrice = (Tick, Option<HomeConversions>, Instant);
 
 pub struct Client {
     token: String,
     url: String,
     account: String,
     client: reqwest::Client,
+    // last tick and home conversions per instrument, reused by get_pricing for price_cache_ttl (0: no cache)
+    price_cache: Mutex<HashMap<String, CachedPrice>>,
+    price_cache_ttl: Duration,
+    // orders reach a real money account
+    is_live: bool,
 }
 
 impl Client {
@@ -14,14 +104,61 @@ impl Client {
         let ret = Client {
             token: token,
             account: account,
+            // a custom url is live unless it is the practice api
+            is_live: !url.contains("api-fxpractice"),
             url: url,
-            client: reqwest::Client::new(),
+            client: http_client(DEFAULT_TIMEOUT),
+            price_cache: Mutex::new(HashMap::new()),
+            price_cache_ttl: Duration::ZERO,
         };
         ret
     }
 
+    pub fn for_env(env: OandaEnv, account: String, api_key: String) -> Client {
+        let mut ret = Client::new(env.url().to_string(), account, api_key);
+        ret.is_live = env == OandaEnv::Live;
+        ret
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.is_live
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.client = http_client(timeout);
+        self
+    }
+
+    pub fn with_price_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.price_cache_ttl = ttl;
+        self
+    }
+
+    fn cached_price(&self, instrument: &str) -> Option<(Tick, Option<HomeConversions>)> {
+        if self.price_cache_ttl.is_zero() {
+            return None;
+        }
+        let cache = self.price_cache.lock().ok()?;
+        cache
+            .get(instrument)
+            .filter(|(_, _, cached_at)| cached_at.elapsed() < self.price_cache_ttl)
+            .map(|(tick, conversions, _)| (tick.clone(), *conversions))
+    }
+
+    fn cache_price(&self, instrument: String, tick: Tick, conversions: Option<HomeConversions>) {
+        if self.price_cache_ttl.is_zero() {
+            return;
+        }
+        if let Ok(mut cache) = self.price_cache.lock() {
+            cache.insert(instrument, (tick, conversions, Instant::now()));
+        }
+    }
+
     pub async fn get_pricing(&self, instrument: String) -> Option<PricingResponse> {
-        let request_url = format!("{}/v3/accounts/{}/pricing?instruments={}",self.url.clone(), self.account, instrument);
+        if let Some((tick, conversions)) = self.cached_price(&instrument) {
+            return Some(PricingResponse::from_tick_with_conversions(&tick, &instrument, conversions));
+        }
+        let request_url = format!("{}/v3/accounts/{}/pricing?instruments={}&includeHomeConversions=true",self.url.clone(), self.account, instrument);
 
         let response: Result<reqwest::Response, reqwest::Error> = self.client
             .get(request_url)
@@ -32,32 +169,71 @@ impl Client {
         //let ret: Result<PricingResponse, _> = response.unwrap().json().await;
         //ret.ok()
         if let Some(res) = response.ok() {
-            return res.json().await.ok();
+            let pricing: Option<PricingResponse> = res.json().await.ok();
+            if let Some(p) = pricing.as_ref() {
+                self.cache_price(instrument, p.get_tick(), p.home_conversions());
+            }
+            return pricing;
         }
 
         None
     }
 
-    pub async fn get_open_positions(&self,) -> Option<OpenPositionsResponse> {
+    pub async fn get_open_positions(&self) -> Result<Vec<Position>, OandaError> {
         let request_url = format!("{}/v3/accounts/{}/openPositions",self.url.clone(), self.account);
 
-        let response: Result<reqwest::Response, reqwest::Error> = self.client
+        let response: OpenPositionsResponse = self.client
             .get(request_url)
             .bearer_auth(self.token.clone())
             .send()
-            .await;
-//        if response.is_err() {
-//            response.err().map(|e|   println!("{}", e.to_string()));
-//
-//        }
-//        println!("open positions call result... {:?}", response);
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        response.into_positions()
+    }
 
-        if let Some(res) = response.ok() {
-            return res.json().await.ok();
+    pub async fn get_open_trades(&self) -> Result<Vec<OpenTrade>, OandaError> {
+        let request_url = format!("{}/v3/accounts/{}/openTrades",self.url.clone(), self.account);
+
+        let response: OpenTradesResponse = self.client
+            .get(request_url)
+            .bearer_auth(self.token.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        response.to_open_trades()
+    }
+
+    pub async fn find_order_fill(&self, client_id: &str) -> Result<Option<OrderFill>, OandaError> {
+        let request_url = format!("{}/v3/accounts/{}/orders/@{}", self.url, self.account, client_id);
+        let response = self.client
+            .get(request_url)
+            .bearer_auth(self.token.clone())
+            .send()
+            .await?;
+        // the order never reached Oanda
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
         }
-    //    let ret: Result<OpenPositionsResponse, _> = response.unwrap().json().await;
-    //    ret.ok()
-        None
+        let details: OrderDetailsResponse = response.error_for_status()?.json().await?;
+        let transaction_id = match details.filling_transaction_id() {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let request_url = format!("{}/v3/accounts/{}/transactions/{}", self.url, self.account, transaction_id);
+        let transaction: TransactionResponse = self.client
+            .get(request_url)
+            .bearer_auth(self.token.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(transaction.get_order_fill())
     }
 
     pub async fn post_order_request(&self, order: &OrderRequest) -> Option<PostOrderResponse> {
@@ -79,4 +255,111 @@ impl Client {
     }
 
 
-}
\ No newline at end of file
+}
+
+impl OandaApi for Client {
+    async fn get_pricing(&self, instrument: String) -> Option<PricingResponse> {
+        Client::get_pricing(self, instrument).await
+    }
+
+    async fn get_open_positions(&self) -> Result<Vec<Position>, OandaError> {
+        Client::get_open_positions(self).await
+    }
+
+    async fn post_order_request(&self, order: &OrderRequest) -> Option<PostOrderResponse> {
+        Client::post_order_request(self, order).await
+    }
+
+    async fn find_order_fill(&self, client_id: &str) -> Result<Option<OrderFill>, OandaError> {
+        Client::find_order_fill(self, client_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hff::quote::Tick;
+    use crate::hff::instrument::{HomeConversions, HomeFactor};
+    use super::{Client, OandaEnv};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn price_cache() {
+        // nothing listens there, any request fails
+        let client = Client::new("http://127.0.0.1:9".to_string(), "account".to_string(), "token".to_string());
+        let tick = Tick { time: 1672653600, bid: 1.0650, ask: 1.0652 };
+        client.cache_price("EUR_USD".to_string(), tick.clone(), None);
+        assert!(client.get_pricing("EUR_USD".to_string()).await.is_none());
+
+        let client = client.with_price_cache_ttl(Duration::from_secs(60));
+        let conversions = HomeConversions {
+            base_home: HomeFactor { gain: 1.0650, loss: 1.0652 },
+            quote_home: HomeFactor { gain: 1.0, loss: 1.0 },
+        };
+        client.cache_price("EUR_USD".to_string(), tick, Some(conversions));
+        let cached = client.get_pricing("EUR_USD".to_string()).await.unwrap();
+        let tick = cached.get_tick();
+        assert_eq!((tick.time, tick.bid, tick.ask), (1672653600, 1.0650, 1.0652));
+        // the conversions are kept with the tick
+        assert_eq!(cached.home_conversions(), Some(conversions));
+        assert!(client.get_pricing("GBP_USD".to_string()).await.is_none());
+
+        let client = client.with_price_cache_ttl(Duration::from_nanos(1));
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(client.get_pricing("EUR_USD".to_string()).await.is_none());
+    }
+
+    #[test]
+    fn environments() {
+        let live = Client::for_env(OandaEnv::Live, "account".to_string(), "token".to_string());
+        assert!(live.is_live());
+        assert_eq!(live.url, "https://api-fxtrade.oanda.com");
+        let practice = Client::for_env(OandaEnv::Practice, "account".to_string(), "token".to_string());
+        assert!(!practice.is_live());
+        assert_eq!(practice.url, "https://api-fxpractice.oanda.com");
+
+        assert!(!Client::new("https://api-fxpractice.oanda.com".to_string(), "a".to_string(), "t".to_string()).is_live());
+        assert!(Client::new("https://api-fxtrade.oanda.com".to_string(), "a".to_string(), "t".to_string()).is_live());
+    }
+
+    // http server answering every request with a price, counting its connections
+    fn pricing_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let body = r#"{"time":"2023-01-02T10:00:00Z","prices":[{"time":"2023-01-02T10:00:00Z","bids":[{"price":"1.0650","liquidity":1000000}],"asks":[{"price":"1.0652","liquidity":1000000}]}]}"#;
+                    let mut buf = [0u8; 4096];
+                    // one GET request per read, without body
+                    while let Ok(n) = stream.read(&mut buf) {
+                        if n == 0 {
+                            break;
+                        }
+                        let response = format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+                        stream.write_all(response.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        (url, connections)
+    }
+
+    #[tokio::test]
+    async fn reuses_connections() {
+        let (url, connections) = pricing_server();
+        let client = Client::new(url, "account".to_string(), "token".to_string()).with_timeout(Duration::from_secs(5));
+        for _ in 0..2 {
+            let tick = client.get_pricing("EUR_USD".to_string()).await.unwrap().get_tick();
+            assert_eq!((tick.bid, tick.ask), (1.0650, 1.0652));
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,14 +1,58 @@
 use serde::{Serialize,Deserialize};
-use chrono::DateTime;
+use chrono::{DateTime, SecondsFormat, Utc};
 use super::hff::quote::*;
 use super::hff::account::*;
+use super::hff::instrument::{HomeConversions, HomeFactor, Instrument};
 
 pub mod client;
+pub mod replay;
+pub mod runner;
 
 #[derive(Deserialize, Debug)]
 pub struct SideResponse {
     units: String,
     averagePrice: Option<String>,
+    #[serde(default, rename="unrealizedPL")]
+    unrealized_pl: Option<String>,
+}
+
+// failure of a call to the Oanda API
+#[derive(Debug)]
+pub enum OandaError {
+    // the request could not be sent, or the API answered with an error status
+    Http(reqwest::Error),
+    // the API answered with a body we cannot read
+    Parse(String),
+    // the order was answered but not filled
+    NotFilled,
+    // no fill for this client order id after all the attempts
+    Unconfirmed(String),
+    // a limit or stop order was created with this id, it rests on the book until it fills
+    Pending(String),
+}
+
+impl std::fmt::Display for OandaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OandaError::Http(e) => write!(f, "Oanda request failed: {}", e),
+            OandaError::Parse(what) => write!(f, "cannot read the Oanda response: {}", what),
+            OandaError::NotFilled => write!(f, "the order was not filled"),
+            OandaError::Unconfirmed(id) => write!(f, "no fill confirmed for order {}", id),
+            OandaError::Pending(id) => write!(f, "order {} is pending on the book", id),
+        }
+    }
+}
+
+impl std::error::Error for OandaError {}
+
+impl From<reqwest::Error> for OandaError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            OandaError::Parse(e.to_string())
+        } else {
+            OandaError::Http(e)
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -18,35 +62,88 @@ pub struct PositionsResponse {
     short: SideResponse,
 }
 
+// the body of the openPositions call, read into Positions by the client
 #[derive(Deserialize, Debug)]
-pub struct OpenPositionsResponse {
+pub(crate) struct OpenPositionsResponse {
     positions: Vec<PositionsResponse>,
 }
 
 impl OpenPositionsResponse {
-    pub fn to_position_vec(&self) -> Vec<Position> {
+    pub(crate) fn into_positions(self) -> Result<Vec<Position>, OandaError> {
+        let number = |field: &str, value: &str| {
+            value.parse::<f64>().map_err(|_| OandaError::Parse(format!("{} {}", field, value)))
+        };
         let mut ret = Vec::new();
-        for opr in self.positions.iter() {
-            let units = if opr.long.units != "0"
-            { opr.long.units.parse().unwrap()}
-            else {  opr.short.units.parse().unwrap() };
+        for opr in self.positions.into_iter() {
+            // the long side, unless flat
+            let side = if opr.long.units != "0" { &opr.long } else { &opr.short };
+            let units = side
+                .units
+                .parse::<i64>()
+                .map_err(|_| OandaError::Parse(format!("units {}", side.units)))?;
+            let price = side.averagePrice.as_deref().map(|p| number("averagePrice", p)).transpose()?;
+            let unrealized_pl = side.unrealized_pl.as_ref().and_then(|p| p.parse().ok()).unwrap_or(0.0);
 
-            let price = if opr.long.units != "0"
-            { opr.long.averagePrice.as_ref().map(|p| p.parse().unwrap())}
-            else { opr.short.averagePrice.as_ref().map(|p| p.parse().unwrap()) };
-
-            let position = Position {
+            ret.push(Position {
                 instrument: opr.instrument.clone(),
-                units: units,
-                price: price
-            };
-            ret.push(position);
+                units,
+                price,
+                unrealized_pl,
+            });
         }
-        ret
+        Ok(ret)
     }
 }
 
 
+#[derive(Deserialize, Debug)]
+pub struct TradeResponse {
+    id: String,
+    instrument: String,
+    price: String,
+    #[serde(rename="currentUnits")]
+    current_units: String,
+    #[serde(rename="unrealizedPL")]
+    unrealized_pl: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OpenTradesResponse {
+    trades: Vec<TradeResponse>,
+}
+
+// an open trade of the account, as opposed to the net position on an instrument
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenTrade {
+    pub id: String,
+    pub instrument: String,
+    pub units: i64,
+    pub price: f64,
+    pub unrealized_pl: f64,
+}
+
+impl OpenTradesResponse {
+    pub fn to_open_trades(&self) -> Result<Vec<OpenTrade>, OandaError> {
+        let number = |field: &str, value: &str| {
+            value.parse::<f64>().map_err(|_| OandaError::Parse(format!("{} {}", field, value)))
+        };
+        let mut ret = Vec::new();
+        for trade in self.trades.iter() {
+            ret.push(OpenTrade {
+                id: trade.id.clone(),
+                instrument: trade.instrument.clone(),
+                units: trade
+                    .current_units
+                    .parse()
+                    .map_err(|_| OandaError::Parse(format!("currentUnits {}", trade.current_units)))?,
+                price: number("price", &trade.price)?,
+                unrealized_pl: trade.unrealized_pl.as_deref().map_or(Ok(0.0), |p| number("unrealizedPL", p))?,
+            });
+        }
+        Ok(ret)
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct LiquidityResponse {
     price: String,
@@ -55,15 +152,74 @@ pub struct LiquidityResponse {
 #[derive(Deserialize, Debug)]
 pub struct PriceResponse {
     time: String,
+    #[serde(default)]
+    instrument: String,
     bids: Vec<LiquidityResponse>,
     asks: Vec<LiquidityResponse>,
 }
+// factors converting an amount of a currency to the account currency, asked with includeHomeConversions
+#[derive(Deserialize, Debug)]
+pub struct HomeConversionResponse {
+    currency: String,
+    #[serde(rename="accountGain")]
+    account_gain: String,
+    #[serde(rename="accountLoss")]
+    account_loss: String,
+}
 #[derive(Deserialize, Debug)]
 pub struct PricingResponse {
     time: String,
     prices: Vec<PriceResponse>,
+    #[serde(default, rename="homeConversions")]
+    home_conversions: Vec<HomeConversionResponse>,
 }
+
 impl PricingResponse {
+    // a response quoting a single tick, as the API would have returned it
+    pub fn from_tick(tick: &Tick) -> Self {
+        let time = DateTime::from_timestamp(tick.time, 0).unwrap_or_default().to_rfc3339();
+        Self {
+            time: time.clone(),
+            prices: vec![PriceResponse {
+                time,
+                instrument: String::new(),
+                bids: vec![LiquidityResponse { price: tick.bid.to_string(), liquidity: 0 }],
+                asks: vec![LiquidityResponse { price: tick.ask.to_string(), liquidity: 0 }],
+            }],
+            home_conversions: vec![],
+        }
+    }
+
+    // a single tick response of instrument with its home conversions, as the API would have returned it
+    pub fn from_tick_with_conversions(tick: &Tick, instrument: &str, conversions: Option<HomeConversions>) -> Self {
+        let mut response = Self::from_tick(tick);
+        response.prices[0].instrument = instrument.to_string();
+        if let (Some(c), Some((base, quote))) = (conversions, instrument.split_once('_')) {
+            response.home_conversions = [(base, c.base_home), (quote, c.quote_home)]
+                .into_iter()
+                .map(|(currency, f)| HomeConversionResponse {
+                    currency: currency.to_string(),
+                    account_gain: f.gain.to_string(),
+                    account_loss: f.loss.to_string(),
+                })
+                .collect();
+        }
+        response
+    }
+
+    // Oanda factors for the base and quote currencies of the first priced instrument,
+    // None without home conversions in the response or for a currency missing from them
+    pub fn home_conversions(&self) -> Option<HomeConversions> {
+        let instrument = &self.prices.first()?.instrument;
+        let (base, quote) = instrument.split_once('_')?;
+        Some(HomeConversions { base_home: self.home_factor(base)?, quote_home: self.home_factor(quote)? })
+    }
+
+    fn home_factor(&self, currency: &str) -> Option<HomeFactor> {
+        let c = self.home_conversions.iter().find(|c| c.currency == currency)?;
+        Some(HomeFactor { gain: c.account_gain.parse().ok()?, loss: c.account_loss.parse().ok()? })
+    }
+
     pub fn get_tick(&self) -> Tick {
         Tick{
             time:  DateTime::parse_from_rfc3339(self.prices.first().map(|p| p.time.clone()).unwrap().as_str()).unwrap().timestamp(),
@@ -73,7 +229,12 @@ impl PricingResponse {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
+pub struct ClientExtensions {
+    id: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
 pub struct OrderRequestInner {
     units: String,
     instrument: String,
@@ -81,12 +242,57 @@ pub struct OrderRequestInner {
     #[serde(rename="type")]
     ordertype: String,
     positionFill: String,
+    // our id of the order, to find it back when the response is lost
+    #[serde(rename="clientExtensions", skip_serializing_if="Option::is_none")]
+    client_extensions: Option<ClientExtensions>,
+    // limit or stop price
+    #[serde(skip_serializing_if="Option::is_none")]
+    price: Option<String>,
+    // expiry of a GTD order
+    #[serde(rename="gtdTime", skip_serializing_if="Option::is_none")]
+    gtd_time: Option<String>,
+}
+
+// how long a pending order stays on the book: until cancelled, until a date,
+// filled now as much as possible or filled now in full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeInForce {
+    GTC,
+    GTD(DateTime<Utc>),
+    IOC,
+    FOK,
+}
+
+impl TimeInForce {
+    fn code(&self) -> &'static str {
+        match self {
+            TimeInForce::GTC => "GTC",
+            TimeInForce::GTD(_) => "GTD",
+            TimeInForce::IOC => "IOC",
+            TimeInForce::FOK => "FOK",
+        }
+    }
+}
 
+// market orders are filled or killed now, stop orders wait on the book until cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OrderType {
+    #[default]
+    Market,
+    Limit { price: f64, time_in_force: TimeInForce },
+    Stop { price: f64 },
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct OrderRequest {
     pub order: OrderRequestInner,
+    // a reduce only order can only decrease the position, never flip it,
+    // set with with_reduce_only, which writes positionFill
+    #[serde(skip)]
+    reduce_only: bool,
+    // set with with_order_type, which writes the Oanda fields of the type
+    #[serde(skip)]
+    order_type: OrderType,
 }
 
 impl OrderRequest {
@@ -98,9 +304,90 @@ impl OrderRequest {
                 timeInForce: "FOK".to_owned(),
                 ordertype: "MARKET".to_owned(),
                 positionFill: "DEFAULT".to_owned(),
-            }
+                client_extensions: None,
+                price: None,
+                gtd_time: None,
+            },
+            reduce_only: false,
+            order_type: OrderType::Market,
         }
     }
+
+    pub fn limit(units: i64, instrument: String, price: f64, time_in_force: TimeInForce) -> Self {
+        Self::new(units, instrument).with_order_type(OrderType::Limit { price, time_in_force })
+    }
+
+    pub fn stop(units: i64, instrument: String, price: f64) -> Self {
+        Self::new(units, instrument).with_order_type(OrderType::Stop { price })
+    }
+
+    pub fn with_order_type(mut self, order_type: OrderType) -> Self {
+        let (ordertype, time_in_force, price) = match order_type {
+            OrderType::Market => ("MARKET", TimeInForce::FOK, None),
+            OrderType::Limit { price, time_in_force } => ("LIMIT", time_in_force, Some(price)),
+            OrderType::Stop { price } => ("STOP", TimeInForce::GTC, Some(price)),
+        };
+        self.order.ordertype = ordertype.to_owned();
+        self.order.timeInForce = time_in_force.code().to_owned();
+        self.order.price = price.map(|p| format_price(&self.order.instrument, p));
+        self.order.gtd_time = match time_in_force {
+            TimeInForce::GTD(time) => Some(time.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            _ => None,
+        };
+        self.order_type = order_type;
+        self
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn with_client_id(mut self, client_id: &str) -> Self {
+        self.order.client_extensions = Some(ClientExtensions { id: client_id.to_owned() });
+        self
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.order.client_extensions.as_ref().map(|c| c.id.as_str())
+    }
+
+    // set the order as reduce only: Oanda caps it at the current position size
+    pub fn with_reduce_only(mut self, reduce_only: bool) -> Self {
+        self.reduce_only = reduce_only;
+        self.order.positionFill = if reduce_only { "REDUCE_ONLY" } else { "DEFAULT" }.to_owned();
+        self
+    }
+
+    pub fn reduce_only(&self) -> bool {
+        self.reduce_only
+    }
+}
+
+// price at the display precision of the instrument, Oanda rejects prices with more digits
+fn format_price(instrument: &str, price: f64) -> String {
+    match Instrument::from_name(instrument) {
+        Some(instrument) => format!("{:.*}", instrument.display_precision(), price),
+        None => price.to_string(),
+    }
+}
+
+// last line of defense: clamp the order units so the resulting position stays within +/- max_position
+// returns the (possibly reduced) units to submit, zero if the position is already at or past the ceiling
+pub fn clamp_to_max_position(account_exposure: i64, units: i64, max_position: i64) -> i64 {
+    let max_position = max_position.abs();
+    let target = account_exposure + units;
+    // within the ceiling, or reducing an already over-limit position without crossing zero
+    if target.abs() <= max_position || (target.signum() == account_exposure.signum() && target.abs() <= account_exposure.abs()) {
+        return units;
+    }
+    let target = target.clamp(-max_position, max_position);
+    let clamped = target - account_exposure;
+    // never trade further away when we are already beyond the ceiling
+    if clamped.signum() != units.signum() {
+        0
+    } else {
+        clamped
+    }
 }
 
 
@@ -112,20 +399,292 @@ pub struct OrderFillTransactionResponse {
     pub filltype: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct OrderCreateTransactionResponse {
+    pub id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OrderCancelTransactionResponse {
+    pub reason: String,
+}
+
+// a market order is created and filled (or cancelled) at once, a limit or stop order is only created
 #[derive(Deserialize, Debug)]
 pub struct PostOrderResponse {
-    orderFillTransaction: OrderFillTransactionResponse
+    #[serde(default)]
+    orderFillTransaction: Option<OrderFillTransactionResponse>,
+    #[serde(default, rename="orderCreateTransaction")]
+    order_create_transaction: Option<OrderCreateTransactionResponse>,
+    #[serde(default, rename="orderCancelTransaction")]
+    order_cancel_transaction: Option<OrderCancelTransactionResponse>,
+}
+
+// what Oanda did with a posted order
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderOutcome {
+    Filled(OrderFill),
+    // created with this order id and waiting on the book
+    Pending(String),
+    // cancelled with this reason, e.g. a FOK market order that could not be filled
+    Cancelled(String),
+}
+
+// an order found by its client id, with the transaction that filled it
+#[derive(Deserialize, Debug)]
+pub struct OrderDetails {
+    state: String,
+    #[serde(rename="fillingTransactionID")]
+    filling_transaction_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OrderDetailsResponse {
+    order: OrderDetails,
+}
+
+impl OrderDetailsResponse {
+    // id of the fill transaction of a filled order
+    pub fn filling_transaction_id(&self) -> Option<&str> {
+        (self.order.state == "FILLED").then_some(self.order.filling_transaction_id.as_deref()).flatten()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionResponse {
+    transaction: OrderFillTransactionResponse,
+}
+
+impl TransactionResponse {
+    pub fn get_order_fill(&self) -> Option<OrderFill> {
+        if self.transaction.filltype != "ORDER_FILL" {
+            return None;
+        }
+        Some(OrderFill {
+            price: self.transaction.price.parse().ok()?,
+            units: self.transaction.units.parse().ok()?,
+        })
+    }
 }
 
 impl PostOrderResponse {
+    // a fill response from its parts, as a mock or a replay answers
+    pub fn filled(price: f64, units: i64) -> Self {
+        Self {
+            orderFillTransaction: Some(OrderFillTransactionResponse {
+                price: price.to_string(),
+                units: units.to_string(),
+                filltype: "ORDER_FILL".to_string(),
+            }),
+            order_create_transaction: None,
+            order_cancel_transaction: None,
+        }
+    }
+
     pub fn get_order_fill(&self) -> Option<OrderFill> {
-        if self.orderFillTransaction.filltype != "ORDER_FILL" {
+        let fill = self.orderFillTransaction.as_ref()?;
+        if fill.filltype != "ORDER_FILL" {
             None
         } else {
             Some(OrderFill {
-                price: self.orderFillTransaction.price.parse::<f64>().unwrap(),
-                units: self.orderFillTransaction.units.parse::<i64>().unwrap(),
+                price: fill.price.parse::<f64>().ok()?,
+                units: fill.units.parse::<i64>().ok()?,
             })
         }
     }
-}
\ No newline at end of file
+
+    // None for a response with neither a fill, a cancel nor a created order
+    pub fn outcome(&self) -> Option<OrderOutcome> {
+        if let Some(fill) = self.get_order_fill() {
+            return Some(OrderOutcome::Filled(fill));
+        }
+        if let Some(cancel) = &self.order_cancel_transaction {
+            return Some(OrderOutcome::Cancelled(cancel.reason.clone()));
+        }
+        self.order_create_transaction.as_ref().map(|c| OrderOutcome::Pending(c.id.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clamp_to_max_position, HomeFactor, OandaError, OpenPositionsResponse, OpenTrade, OpenTradesResponse, OrderOutcome, OrderRequest, OrderType, PostOrderResponse, PricingResponse, TimeInForce};
+    use crate::hff::account::OrderFill;
+    use crate::hff::quote::Tick;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn reduce_only_order() {
+        let order = OrderRequest::new(-1000, "EUR_USD".to_string()).with_reduce_only(true);
+        let json = serde_json::to_value(&order).unwrap();
+        assert_eq!(json["order"]["positionFill"], "REDUCE_ONLY");
+        assert_eq!(json["order"]["type"], "MARKET");
+        assert_eq!(json["order"]["units"], "-1000");
+        assert!(json.get("reduce_only").is_none());
+        assert!(order.reduce_only());
+
+        let order = OrderRequest::new(1000, "EUR_USD".to_string());
+        let json = serde_json::to_value(&order).unwrap();
+        assert_eq!(json["order"]["positionFill"], "DEFAULT");
+    }
+
+    #[test]
+    fn order_types() {
+        let json = serde_json::to_value(OrderRequest::new(1000, "EUR_USD".to_string())).unwrap();
+        assert_eq!(json["order"]["type"], "MARKET");
+        assert_eq!(json["order"]["timeInForce"], "FOK");
+        assert!(json["order"].get("price").is_none());
+
+        let order = OrderRequest::limit(1000, "EUR_USD".to_string(), 1.0525, TimeInForce::GTC);
+        assert_eq!(order.order_type, OrderType::Limit { price: 1.0525, time_in_force: TimeInForce::GTC });
+        let json = serde_json::to_value(&order).unwrap();
+        assert_eq!(json["order"]["type"], "LIMIT");
+        assert_eq!(json["order"]["price"], "1.05250");
+        assert_eq!(json["order"]["timeInForce"], "GTC");
+        assert!(json["order"].get("gtdTime").is_none());
+
+        let expiry = Utc.with_ymd_and_hms(2023, 1, 2, 10, 0, 0).unwrap();
+        let json = serde_json::to_value(OrderRequest::limit(-1000, "EUR_USD".to_string(), 1.06, TimeInForce::GTD(expiry))).unwrap();
+        assert_eq!(json["order"]["timeInForce"], "GTD");
+        assert_eq!(json["order"]["gtdTime"], "2023-01-02T10:00:00Z");
+        assert_eq!(json["order"]["units"], "-1000");
+
+        let json = serde_json::to_value(OrderRequest::stop(-1000, "EUR_USD".to_string(), 1.04)).unwrap();
+        assert_eq!(json["order"]["type"], "STOP");
+        assert_eq!(json["order"]["price"], "1.04000");
+        assert_eq!(json["order"]["timeInForce"], "GTC");
+
+        // back to a market order
+        let order = OrderRequest::stop(1000, "EUR_USD".to_string(), 1.04).with_order_type(OrderType::Market);
+        let json = serde_json::to_value(order).unwrap();
+        assert_eq!(json["order"]["type"], "MARKET");
+        assert!(json["order"].get("price").is_none());
+
+        // prices at the instrument precision
+        let json = serde_json::to_value(OrderRequest::stop(1000, "USD_JPY".to_string(), 150.1234567)).unwrap();
+        assert_eq!(json["order"]["price"], "150.123");
+        let json = serde_json::to_value(OrderRequest::limit(1000, "EUR_USD".to_string(), 0.1 + 0.2, TimeInForce::GTC)).unwrap();
+        assert_eq!(json["order"]["price"], "0.30000");
+    }
+
+    #[test]
+    fn order_outcomes() {
+        let filled: PostOrderResponse = serde_json::from_str(r#"{
+            "orderCreateTransaction": {"id": "6400", "type": "MARKET_ORDER"},
+            "orderFillTransaction": {"id": "6401", "type": "ORDER_FILL", "price": "1.05250", "units": "1000"}
+        }"#).unwrap();
+        assert_eq!(filled.outcome(), Some(OrderOutcome::Filled(OrderFill { price: 1.0525, units: 1000 })));
+
+        // a limit order rests on the book
+        let pending: PostOrderResponse = serde_json::from_str(r#"{
+            "orderCreateTransaction": {"id": "6402", "type": "LIMIT_ORDER", "price": "1.05000", "units": "1000"},
+            "relatedTransactionIDs": ["6402"]
+        }"#).unwrap();
+        assert!(pending.get_order_fill().is_none());
+        assert_eq!(pending.outcome(), Some(OrderOutcome::Pending("6402".to_string())));
+
+        let cancelled: PostOrderResponse = serde_json::from_str(r#"{
+            "orderCreateTransaction": {"id": "6403", "type": "MARKET_ORDER"},
+            "orderCancelTransaction": {"id": "6404", "type": "ORDER_CANCEL", "reason": "MARKET_HALTED"}
+        }"#).unwrap();
+        assert_eq!(cancelled.outcome(), Some(OrderOutcome::Cancelled("MARKET_HALTED".to_string())));
+    }
+
+    #[test]
+    fn home_conversions() {
+        let response: PricingResponse = serde_json::from_str(r#"{
+            "time": "2023-01-02T10:00:01.000000000Z",
+            "prices": [
+                {"type": "PRICE", "instrument": "EUR_GBP", "time": "2023-01-02T10:00:00.000000000Z",
+                 "bids": [{"price": "0.88500", "liquidity": 1000000}], "asks": [{"price": "0.88520", "liquidity": 1000000}]}
+            ],
+            "homeConversions": [
+                {"currency": "EUR", "accountGain": "1.06500", "accountLoss": "1.06520", "positionValue": "1.06510"},
+                {"currency": "GBP", "accountGain": "1.20300", "accountLoss": "1.20330", "positionValue": "1.20315"}
+            ]
+        }"#).unwrap();
+        let conversions = response.home_conversions().unwrap();
+        assert_eq!(conversions.base_home, HomeFactor { gain: 1.065, loss: 1.0652 });
+        assert_eq!(conversions.quote_home, HomeFactor { gain: 1.203, loss: 1.2033 });
+        assert!((conversions.quote_home.convert(10.0) - 12.03).abs() < 1e-12);
+        assert!((conversions.quote_home.convert(-10.0) + 12.033).abs() < 1e-12);
+        assert_eq!(response.get_tick().bid, 0.885);
+
+        // no conversions asked, or built from a tick
+        let response: PricingResponse = serde_json::from_str(r#"{
+            "time": "2023-01-02T10:00:01.000000000Z",
+            "prices": [{"instrument": "EUR_USD", "time": "2023-01-02T10:00:00.000000000Z",
+                        "bids": [{"price": "1.0", "liquidity": 1}], "asks": [{"price": "1.0", "liquidity": 1}]}]
+        }"#).unwrap();
+        assert!(response.home_conversions().is_none());
+        assert!(PricingResponse::from_tick(&Tick { time: 0, bid: 1.0, ask: 1.0 }).home_conversions().is_none());
+    }
+
+    #[test]
+    fn max_position_clamp() {
+        // within limits, untouched
+        assert_eq!(clamp_to_max_position(0, 5000, 10000), 5000);
+        assert_eq!(clamp_to_max_position(0, -5000, 10000), -5000);
+        // over limit, clamped to the ceiling
+        assert_eq!(clamp_to_max_position(2000, 1000000, 10000), 8000);
+        assert_eq!(clamp_to_max_position(-2000, -1000000, 10000), -8000);
+        // flipping through zero is allowed up to the ceiling on the other side
+        assert_eq!(clamp_to_max_position(5000, -1000000, 10000), -15000);
+        // already beyond the ceiling: never increase, but reducing is fine
+        assert_eq!(clamp_to_max_position(12000, 1000, 10000), 0);
+        assert_eq!(clamp_to_max_position(12000, -1000, 10000), -1000);
+        // reducing past zero stops at the ceiling on the other side
+        assert_eq!(clamp_to_max_position(12000, -24000, 10000), -22000);
+        assert_eq!(clamp_to_max_position(-12000, 24000, 10000), 22000);
+    }
+
+    #[test]
+    fn open_trades() {
+        let response: OpenTradesResponse = serde_json::from_str(r#"{
+            "trades": [
+                {"id": "6397", "instrument": "EUR_USD", "price": "1.05250", "openTime": "2023-01-02T10:00:00.000000000Z",
+                 "initialUnits": "1000", "currentUnits": "800", "state": "OPEN", "realizedPL": "1.2", "unrealizedPL": "-0.4500"},
+                {"id": "6401", "instrument": "EUR_USD", "price": "1.06000", "openTime": "2023-01-03T10:00:00.000000000Z",
+                 "initialUnits": "-500", "currentUnits": "-500", "state": "OPEN", "realizedPL": "0.0"}
+            ],
+            "lastTransactionID": "6402"
+        }"#).unwrap();
+        let trades = response.to_open_trades().unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0], OpenTrade { id: "6397".to_string(), instrument: "EUR_USD".to_string(), units: 800, price: 1.0525, unrealized_pl: -0.45 });
+        assert_eq!(trades[1].units, -500);
+        assert_eq!(trades[1].unrealized_pl, 0.0);
+
+        let response: OpenTradesResponse = serde_json::from_str(r#"{
+            "trades": [
+                {"id": "6397", "instrument": "EUR_USD", "price": "1.05250", "openTime": "2023-01-02T10:00:00.000000000Z",
+                 "initialUnits": "1000", "currentUnits": "800.5", "state": "OPEN", "realizedPL": "1.2"}
+            ],
+            "lastTransactionID": "6402"
+        }"#).unwrap();
+        assert!(matches!(response.to_open_trades(), Err(OandaError::Parse(what)) if what == "currentUnits 800.5"));
+    }
+
+    #[test]
+    fn open_positions() {
+        let response: OpenPositionsResponse = serde_json::from_str(r#"{
+            "positions": [
+                {"instrument": "EUR_USD",
+                 "long": {"units": "0", "pl": "1.2"},
+                 "short": {"units": "-800", "averagePrice": "1.06000", "unrealizedPL": "2.5"}},
+                {"instrument": "GBP_USD",
+                 "long": {"units": "1000", "averagePrice": "1.20500"},
+                 "short": {"units": "0"}}
+            ],
+            "lastTransactionID": "6402"
+        }"#).unwrap();
+        let positions = response.into_positions().unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!((positions[0].units, positions[0].price, positions[0].unrealized_pl), (-800, Some(1.06), 2.5));
+        assert_eq!((positions[1].instrument.as_str(), positions[1].units, positions[1].price), ("GBP_USD", 1000, Some(1.205)));
+
+        let response: OpenPositionsResponse = serde_json::from_str(r#"{
+            "positions": [{"instrument": "EUR_USD", "long": {"units": "ten"}, "short": {"units": "0"}}]
+        }"#).unwrap();
+        assert!(matches!(response.into_positions(), Err(OandaError::Parse(_))));
+    }
+}
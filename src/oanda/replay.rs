@@ -0,0 +1,186 @@
+use super::*;
+use super::client::OandaApi;
+use super::super::lean::Lean;
+use std::cell::Cell;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/*
+ReplayClient serves historical bars as the Oanda API would, one bar per pricing call,
+and fills every market order at the closing side of the current bar (ask to buy, bid to sell).
+It lets the live trading loop run against known data, at a multiple of real time or as fast as possible.
+*/
+pub struct ReplayClient {
+    instrument: String,
+    bars: Vec<Bar>,
+    // index of the next bar to serve
+    next: Cell<usize>,
+    position: Cell<i64>,
+    // None: no wait between bars, Some(x): x times real time
+    speed: Option<f64>,
+}
+
+impl ReplayClient {
+    pub fn new(instrument: String, bars: Vec<Bar>) -> Self {
+        Self {
+            instrument,
+            bars,
+            next: Cell::new(0),
+            position: Cell::new(0),
+            speed: None,
+        }
+    }
+
+    // the bars of a Lean YYYYMMDD.zip file, or of all the zip files of a directory in date order
+    pub fn from_path(instrument: String, path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut bars = vec![];
+        if path.is_dir() {
+            let mut days: Vec<PathBuf> = fs::read_dir(path)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<_, _>>()?;
+            days.retain(|p| p.extension().and_then(OsStr::to_str) == Some("zip"));
+            days.sort();
+            for day in days {
+                bars.extend(Self::day_bars(&day)?);
+            }
+        } else {
+            bars = Self::day_bars(path)?;
+        }
+        Ok(Self::new(instrument, bars))
+    }
+
+    // Lean bar times are milliseconds from midnight, shifted to epoch milliseconds
+    fn day_bars(path: &Path) -> Result<Vec<Bar>, Box<dyn Error>> {
+        let (date, mut bars) = Lean::read_day(path)?;
+        let midnight = date.timestamp_millis() as u64;
+        for bar in bars.iter_mut() {
+            bar.time += midnight;
+        }
+        Ok(bars)
+    }
+
+    pub fn with_speed(mut self, speed: Option<f64>) -> Self {
+        self.speed = speed.filter(|s| *s > 0.0);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.bars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bars.is_empty()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.next.get() >= self.bars.len()
+    }
+
+    pub fn position(&self) -> i64 {
+        self.position.get()
+    }
+
+    // wall clock wait before serving bar i, the gap to the previous bar scaled down by the speed
+    pub fn delay_before(&self, i: usize) -> Duration {
+        match (self.speed, i.checked_sub(1).and_then(|p| self.bars.get(p)), self.bars.get(i)) {
+            (Some(speed), Some(prev), Some(bar)) => {
+                Duration::from_secs_f64(bar.time.saturating_sub(prev.time) as f64 / 1000.0 / speed)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    // the bar served last, orders are filled on it
+    fn current_tick(&self) -> Option<Tick> {
+        let bar = self.bars.get(self.next.get().checked_sub(1)?)?;
+        Some(Tick { time: bar.time() as i64, bid: bar.cbid, ask: bar.cask })
+    }
+}
+
+impl OandaApi for ReplayClient {
+    async fn get_pricing(&self, _instrument: String) -> Option<PricingResponse> {
+        let i = self.next.get();
+        if i >= self.bars.len() {
+            return None;
+        }
+        let delay = self.delay_before(i);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        self.next.set(i + 1);
+        self.current_tick().map(|tick| PricingResponse::from_tick(&tick))
+    }
+
+    async fn get_open_positions(&self) -> Result<Vec<Position>, OandaError> {
+        Ok(vec![Position { instrument: self.instrument.clone(), units: self.position.get(), price: None, unrealized_pl: 0.0 }])
+    }
+
+    async fn post_order_request(&self, order: &OrderRequest) -> Option<PostOrderResponse> {
+        let tick = self.current_tick()?;
+        let units: i64 = order.order.units.parse().ok()?;
+        let price = if units > 0 { tick.ask } else { tick.bid };
+        self.position.set(self.position.get() + units);
+        Some(PostOrderResponse::filled(price, units))
+    }
+
+    // every post is answered, there is nothing to look up
+    async fn find_order_fill(&self, _client_id: &str) -> Result<Option<OrderFill>, OandaError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::runner::{StepOutcome, TradingRunner};
+    use super::ReplayClient;
+    use crate::hff::agents::*;
+    use crate::hff::backtest::tests::bars;
+    use crate::hff::quote::Bar;
+    use std::time::Duration;
+
+    // one minute bars
+    fn fixture() -> Vec<Bar> {
+        bars(&[1.0, 0.98, 0.98, 1.0])
+    }
+
+    #[tokio::test]
+    async fn replay_loop() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("buyer".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        let client = ReplayClient::new("EUR_USD".to_string(), fixture());
+        let bars = client.len() as u64;
+        let mut runner = TradingRunner::new(client, "EUR_USD".to_string(), inventory, bars);
+
+        let mut trades = 0;
+        loop {
+            match runner.step().await {
+                StepOutcome::Finished => break,
+                StepOutcome::Traded(_) => trades += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(trades, 3);
+        assert!(runner.client().is_exhausted());
+        assert_eq!(runner.client().position(), 5000);
+        assert_eq!(runner.inventory().exposure(), 5000);
+
+        // bought 1000 more at 0.98 and sold them back at 1.0
+        let pl: f64 = runner.inventory().agents.values().map(|a| a.agentPL.cum_profit).sum();
+        let average = (5000.0 * 1.0 + 1000.0 * 0.98) / 6000.0;
+        assert!((pl - 1000.0 * (1.0 / average - 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn replay_speed() {
+        let client = ReplayClient::new("EUR_USD".to_string(), fixture());
+        assert_eq!(client.delay_before(1), Duration::ZERO);
+        let client = client.with_speed(Some(100.0));
+        assert_eq!(client.delay_before(0), Duration::ZERO);
+        assert_eq!(client.delay_before(1), Duration::from_millis(600));
+        assert_eq!(client.delay_before(4), Duration::ZERO);
+        assert_eq!(client.with_speed(Some(0.0)).delay_before(1), Duration::ZERO);
+    }
+}
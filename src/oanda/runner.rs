@@ -0,0 +1,503 @@
+use super::*;
+use super::client::OandaApi;
+use super::super::hff::agents::*;
+use super::super::hff::instrument::{HomeConversions, Instrument};
+use std::time::{Duration, Instant};
+
+// outcome of a single cycle of the trading loop
+#[derive(Debug)]
+pub enum StepOutcome {
+    // the maximum number of iterations is reached, the loop should stop
+    Finished,
+    // no price from the API, will try again next cycle
+    NoTick,
+    // no positions from the API, will try again next cycle
+    NoPositions,
+    // the account is already at the target exposure
+    NoTrade,
+    // the order could not be posted or was not filled
+    OrderFailed,
+    // an order was filled and booked in the inventory
+    Traded(OrderFill),
+}
+
+// cadence of the sign of life logged while the loop has nothing to trade:
+// every `cycles` quiet cycles in a row, and/or every `interval` since the last one (or the first quiet cycle)
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat {
+    cycles: Option<u64>,
+    interval: Option<Duration>,
+    quiet_cycles: u64,
+    last: Option<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new(cycles: Option<u64>, interval: Option<Duration>) -> Self {
+        Self {
+            cycles: cycles.filter(|n| *n > 0),
+            interval: interval.filter(|d| !d.is_zero()),
+            quiet_cycles: 0,
+            last: None,
+        }
+    }
+
+    pub fn quiet_cycles(&self) -> u64 {
+        self.quiet_cycles
+    }
+
+    // count a cycle without trade at now, true if the heartbeat is due
+    pub fn on_quiet_cycle(&mut self, now: Instant) -> bool {
+        self.quiet_cycles += 1;
+        let last = *self.last.get_or_insert(now);
+        let due = self.cycles.is_some_and(|n| self.quiet_cycles.is_multiple_of(n))
+            || self.interval.is_some_and(|d| now.duration_since(last) >= d);
+        if due {
+            self.last = Some(now);
+        }
+        due
+    }
+
+    // a trade is a sign of life, the count starts over
+    pub fn on_trade(&mut self) {
+        self.quiet_cycles = 0;
+        self.last = None;
+    }
+}
+
+/*
+TradingRunner holds the state of the trading loop on a single instrument:
+- the inventory of agents and the API client
+- the iteration counter and its cap, and the last tick seen
+each call to step runs one cycle, the caller is in charge of timing between steps.
+*/
+pub struct TradingRunner<C: OandaApi> {
+    client: C,
+    instrument: String,
+    inventory: AgentInventory<GearHedger>,
+    iteration: u64,
+    max_iterations: u64,
+    last_tick: Option<Tick>,
+    max_position: Option<i64>,
+    heartbeat: Heartbeat,
+    // last Oanda conversions of the instrument currencies to the account currency
+    home_conversions: Option<HomeConversions>,
+}
+
+impl<C: OandaApi> TradingRunner<C> {
+    pub fn new(client: C, instrument: String, inventory: AgentInventory<GearHedger>, max_iterations: u64) -> Self {
+        Self {
+            client,
+            instrument,
+            inventory,
+            iteration: 0,
+            max_iterations,
+            last_tick: None,
+            max_position: None,
+            heartbeat: Heartbeat::default(),
+            home_conversions: None,
+        }
+    }
+
+    // log exposure, PL and tick on quiet periods, none by default
+    pub fn with_heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    // hard limit on the account position, see clamp_to_max_position
+    pub fn with_max_position(mut self, max_position: Option<i64>) -> Self {
+        self.max_position = max_position;
+        self
+    }
+
+    pub fn iteration(&self) -> u64 {
+        self.iteration
+    }
+
+    pub fn max_iterations(&self) -> u64 {
+        self.max_iterations
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.iteration >= self.max_iterations
+    }
+
+    pub fn reset_iteration(&mut self) {
+        self.iteration = 0;
+    }
+
+    pub fn last_tick(&self) -> Option<&Tick> {
+        self.last_tick.as_ref()
+    }
+
+    pub fn home_conversions(&self) -> Option<HomeConversions> {
+        self.home_conversions
+    }
+
+    pub fn instrument(&self) -> &str {
+        &self.instrument
+    }
+
+    pub fn inventory(&self) -> &AgentInventory<GearHedger> {
+        &self.inventory
+    }
+
+    pub fn inventory_mut(&mut self) -> &mut AgentInventory<GearHedger> {
+        &mut self.inventory
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    // run one cycle of the loop: price, positions, target exposure and order
+    pub async fn step(&mut self) -> StepOutcome {
+        if self.is_finished() {
+            return StepOutcome::Finished;
+        }
+        self.iteration += 1;
+
+        // get the market tick
+        let pricing = match self.client.get_pricing(self.instrument.clone()).await {
+            Some(pricing) => pricing,
+            None => return StepOutcome::NoTick,
+        };
+        let tick = pricing.get_tick();
+        if let Some(conversions) = pricing.home_conversions() {
+            self.home_conversions = Some(conversions);
+        }
+        self.last_tick = Some(tick.clone());
+
+        // check account positions
+        let positions = match self.client.get_open_positions().await {
+            Ok(positions) => positions,
+            Err(e) => {
+                eprintln!("Cannot get the open positions, will try again next cycle: {}", e);
+                return StepOutcome::NoPositions;
+            }
+        };
+
+        // compare target exposure with actual, the account may be off the agents even when they hold
+        let target_exposure = self
+            .inventory
+            .next_exposure_if_changed(&tick)
+            .unwrap_or_else(|| self.inventory.exposure());
+        let account_exposure = positions
+            .iter()
+            .rev()
+            .find(|p| p.instrument == self.instrument)
+            .map_or_else(|| 0, |p| p.units);
+
+        // no trade
+        if target_exposure == account_exposure {
+            self.quiet_cycle(&tick);
+            return StepOutcome::NoTrade;
+        }
+
+        // enforce the position ceiling, whatever the agents computed
+        let mut units = target_exposure - account_exposure;
+        let mut clamped_order = false;
+        if let Some(max_position) = self.max_position {
+            let clamped = clamp_to_max_position(account_exposure, units, max_position);
+            if clamped != units {
+                eprintln!("Order of {} units would breach max position {}, clamped to {}", units, max_position, clamped);
+                units = clamped;
+                clamped_order = true;
+            }
+            if units == 0 {
+                // the agents keep what they hold
+                self.inventory.update_on_partial_fill(&OrderFill { price: tick.price(), units: 0 });
+                self.quiet_cycle(&tick);
+                return StepOutcome::NoTrade;
+            }
+        }
+
+        // create order
+        let order = OrderRequest::new(units, self.instrument.clone());
+
+        eprintln!("Trading : {} to reach {} at price", units, target_exposure);
+
+        // a lost response is looked up by the client id before posting again
+        let client_id = format!("gear-{}-{}-{}", self.instrument, tick.time, self.iteration);
+        match self.client.submit_confirmed(&order, &client_id).await {
+            Ok(order_fill) => {
+                // the agents share the clamped units, not their whole trades
+                if clamped_order {
+                    self.inventory.update_on_partial_fill(&order_fill);
+                } else {
+                    self.inventory.update_on_fill(&order_fill);
+                }
+                self.heartbeat.on_trade();
+                StepOutcome::Traded(order_fill)
+            }
+            Err(e) => {
+                eprintln!("Order {} not filled, will try again next cycle: {}", client_id, e);
+                StepOutcome::OrderFailed
+            }
+        }
+    }
+
+    fn quiet_cycle(&mut self, tick: &Tick) {
+        if self.heartbeat.on_quiet_cycle(Instant::now()) {
+            // PL in account currency once Oanda gave the conversions
+            let (summary, currency) = match (self.home_conversions, Instrument::from_name(&self.instrument)) {
+                (Some(conversions), Some(instrument)) => {
+                    (self.inventory.summarize_in_account(&instrument, tick, &conversions), "account")
+                }
+                _ => (self.inventory.summarize(&self.instrument, tick), "agent"),
+            };
+            eprintln!(
+                "Alive: {} cycles without trade, exposure {}, realized PL {:.2}, unrealized PL {:.2} ({} currency), last tick {:?}",
+                self.heartbeat.quiet_cycles(),
+                summary.total_exposure,
+                summary.realized_pl,
+                summary.unrealized_pl,
+                currency,
+                tick
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::super::client::OandaApi;
+    use super::super::*;
+    use super::{Heartbeat, StepOutcome, TradingRunner};
+    use std::time::{Duration, Instant};
+    use crate::hff::agents::*;
+    use serde_json::json;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    // in memory Oanda account filling every order at the current price
+    pub(crate) struct MockClient {
+        pub price: Cell<f64>,
+        pub position: Cell<i64>,
+        pub orders: RefCell<Vec<i64>>,
+        // the next posts lost before reaching the account
+        pub lost_posts: Cell<usize>,
+        // the next posts executed, but without response
+        pub timed_out_posts: Cell<usize>,
+        // fills by client order id
+        pub fills: RefCell<HashMap<String, OrderFill>>,
+    }
+
+    impl MockClient {
+        pub(crate) fn new(price: f64) -> Self {
+            Self {
+                price: Cell::new(price),
+                position: Cell::new(0),
+                orders: RefCell::new(vec![]),
+                lost_posts: Cell::new(0),
+                timed_out_posts: Cell::new(0),
+                fills: RefCell::new(HashMap::new()),
+            }
+        }
+    }
+
+    impl OandaApi for MockClient {
+        async fn get_pricing(&self, _instrument: String) -> Option<PricingResponse> {
+            let price = self.price.get().to_string();
+            serde_json::from_value(json!({
+                "time": "2023-01-02T10:00:00Z",
+                "prices": [{
+                    "instrument": "EUR_USD",
+                    "time": "2023-01-02T10:00:00Z",
+                    "bids": [{"price": price, "liquidity": 1000000}],
+                    "asks": [{"price": price, "liquidity": 1000000}],
+                }],
+                "homeConversions": [
+                    {"currency": "EUR", "accountGain": price, "accountLoss": price},
+                    {"currency": "USD", "accountGain": "1", "accountLoss": "1"},
+                ],
+            }))
+            .ok()
+        }
+
+        async fn get_open_positions(&self) -> Result<Vec<Position>, OandaError> {
+            Ok(vec![Position { instrument: "EUR_USD".to_string(), units: self.position.get(), price: None, unrealized_pl: 0.0 }])
+        }
+
+        async fn post_order_request(&self, order: &OrderRequest) -> Option<PostOrderResponse> {
+            if self.lost_posts.get() > 0 {
+                self.lost_posts.set(self.lost_posts.get() - 1);
+                return None;
+            }
+            let units: i64 = serde_json::to_value(order).ok()?["order"]["units"].as_str()?.parse().ok()?;
+            self.orders.borrow_mut().push(units);
+            // limit and stop orders wait on the book
+            if order.order_type() != OrderType::Market {
+                return serde_json::from_value(json!({
+                    "orderCreateTransaction": {"id": self.orders.borrow().len().to_string(), "type": "LIMIT_ORDER"},
+                }))
+                .ok();
+            }
+            self.position.set(self.position.get() + units);
+            if let Some(id) = order.client_id() {
+                self.fills.borrow_mut().insert(id.to_string(), OrderFill { price: self.price.get(), units });
+            }
+            if self.timed_out_posts.get() > 0 {
+                self.timed_out_posts.set(self.timed_out_posts.get() - 1);
+                return None;
+            }
+            serde_json::from_value(json!({
+                "orderFillTransaction": {
+                    "price": self.price.get().to_string(),
+                    "units": units.to_string(),
+                    "type": "ORDER_FILL",
+                },
+            }))
+            .ok()
+        }
+
+        async fn find_order_fill(&self, client_id: &str) -> Result<Option<OrderFill>, OandaError> {
+            Ok(self.fills.borrow().get(client_id).cloned())
+        }
+    }
+
+    #[tokio::test]
+    async fn submit_confirmed() {
+        let client = MockClient::new(1.0000);
+        let order = OrderRequest::new(1000, "EUR_USD".to_string());
+
+        // executed, but the response is lost: found by its client id, not posted again
+        client.timed_out_posts.set(1);
+        let fill = client.submit_confirmed(&order, "order-1").await.unwrap();
+        assert_eq!((fill.price, fill.units), (1.0000, 1000));
+        assert_eq!(*client.orders.borrow(), vec![1000]);
+        assert_eq!(client.position.get(), 1000);
+
+        // lost before execution: posted again
+        client.lost_posts.set(1);
+        let fill = client.submit_confirmed(&order, "order-2").await.unwrap();
+        assert_eq!(fill.units, 1000);
+        assert_eq!(*client.orders.borrow(), vec![1000, 1000]);
+
+        // never confirmed
+        client.lost_posts.set(5);
+        assert!(matches!(client.submit_confirmed(&order, "order-3").await, Err(OandaError::Unconfirmed(_))));
+        assert_eq!(client.position.get(), 2000);
+
+        // a limit order is answered as pending and posted once
+        let limit = OrderRequest::limit(1000, "EUR_USD".to_string(), 0.99, TimeInForce::GTC);
+        assert!(matches!(client.submit_confirmed(&limit, "order-4").await, Err(OandaError::Pending(id)) if id == "3"));
+        assert_eq!(*client.orders.borrow(), vec![1000, 1000, 1000]);
+        assert_eq!(client.position.get(), 2000);
+    }
+
+    #[tokio::test]
+    async fn runner_steps() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("buyer".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        let mut runner = TradingRunner::new(MockClient::new(1.0000), "EUR_USD".to_string(), inventory, 3);
+        assert!(runner.last_tick().is_none());
+
+        assert!(matches!(runner.step().await, StepOutcome::Traded(_)));
+        assert_eq!(runner.iteration(), 1);
+        assert_eq!(runner.last_tick().unwrap().bid, 1.0000);
+        assert_eq!(runner.client().position.get(), 5000);
+        // a USD account
+        assert_eq!(runner.home_conversions().unwrap().quote_home.gain, 1.0);
+
+        // price did not move, nothing to do
+        assert!(matches!(runner.step().await, StepOutcome::NoTrade));
+        assert_eq!(runner.iteration(), 2);
+
+        runner.client().price.set(0.9500);
+        assert!(matches!(runner.step().await, StepOutcome::Traded(_)));
+        assert_eq!(runner.client().position.get(), 7500);
+        assert_eq!(runner.inventory().exposure(), 7500);
+        assert_eq!(runner.last_tick().unwrap().bid, 0.9500);
+
+        assert!(runner.is_finished());
+        assert!(matches!(runner.step().await, StepOutcome::Finished));
+        assert_eq!(runner.iteration(), 3);
+        runner.reset_iteration();
+        assert!(!runner.is_finished());
+    }
+
+    #[tokio::test]
+    async fn runner_lost_response() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("buyer".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        let mut runner = TradingRunner::new(MockClient::new(1.0000), "EUR_USD".to_string(), inventory, 10);
+
+        // executed without response: the fill is found back and booked, the order is not posted twice
+        runner.client().timed_out_posts.set(1);
+        assert!(matches!(runner.step().await, StepOutcome::Traded(_)));
+        assert_eq!(*runner.client().orders.borrow(), vec![5000]);
+        assert_eq!(runner.client().position.get(), 5000);
+        assert_eq!(runner.inventory().exposure(), 5000);
+        assert!(matches!(runner.step().await, StepOutcome::NoTrade));
+
+        // never reaching the account: nothing is booked, the next cycle trades
+        runner.client().price.set(0.9500);
+        runner.client().lost_posts.set(10);
+        assert!(matches!(runner.step().await, StepOutcome::OrderFailed));
+        assert_eq!(runner.client().position.get(), 5000);
+        runner.client().lost_posts.set(0);
+        assert!(matches!(runner.step().await, StepOutcome::Traded(_)));
+        assert_eq!(runner.client().position.get(), 7500);
+    }
+
+    #[tokio::test]
+    async fn runner_max_position() {
+        let mut inventory: AgentInventory<GearHedger> = AgentInventory::new();
+        inventory.agents.insert("a".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        inventory.agents.insert("b".to_string(), GearHedger::buyer(0.90, 1.10, 0.0010, 0.0010, 10000.0));
+        let mut runner = TradingRunner::new(MockClient::new(1.0000), "EUR_USD".to_string(), inventory, 10).with_max_position(Some(4000));
+
+        // 10000 asked, 4000 traded and shared by the agents
+        assert!(matches!(runner.step().await, StepOutcome::Traded(_)));
+        assert_eq!(runner.client().position.get(), 4000);
+        assert_eq!(runner.inventory().exposure(), runner.client().position.get());
+        assert_eq!(runner.inventory().agents["a"].exposure(), 2000);
+        assert!(matches!(runner.step().await, StepOutcome::NoTrade));
+
+        // at the ceiling, nothing more is booked
+        runner.client().price.set(0.9500);
+        assert!(matches!(runner.step().await, StepOutcome::NoTrade));
+        assert_eq!(runner.inventory().exposure(), runner.client().position.get());
+
+        // back within the limit, the agents and the account agree
+        runner.client().price.set(1.0800);
+        assert!(matches!(runner.step().await, StepOutcome::Traded(_)));
+        assert_eq!(runner.client().position.get(), 2000);
+        assert_eq!(runner.inventory().exposure(), runner.client().position.get());
+    }
+
+    #[test]
+    fn heartbeat_cycles() {
+        let now = Instant::now();
+        let mut heartbeat = Heartbeat::new(Some(3), None);
+        let due: Vec<bool> = (0..7).map(|_| heartbeat.on_quiet_cycle(now)).collect();
+        assert_eq!(due, vec![false, false, true, false, false, true, false]);
+        // a trade starts the count over
+        heartbeat.on_trade();
+        assert_eq!(heartbeat.quiet_cycles(), 0);
+        assert!(!heartbeat.on_quiet_cycle(now));
+        assert!(!heartbeat.on_quiet_cycle(now));
+        assert!(heartbeat.on_quiet_cycle(now));
+
+        // none by default
+        let mut heartbeat = Heartbeat::new(Some(0), Some(Duration::ZERO));
+        assert!((0..10).all(|_| !heartbeat.on_quiet_cycle(now)));
+    }
+
+    #[test]
+    fn heartbeat_interval() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut heartbeat = Heartbeat::new(None, Some(Duration::from_secs(60)));
+        assert!(!heartbeat.on_quiet_cycle(at(0)));
+        assert!(!heartbeat.on_quiet_cycle(at(30)));
+        assert!(heartbeat.on_quiet_cycle(at(60)));
+        assert!(!heartbeat.on_quiet_cycle(at(90)));
+        assert!(heartbeat.on_quiet_cycle(at(125)));
+        // the clock starts again on the first quiet cycle after a trade
+        heartbeat.on_trade();
+        assert!(!heartbeat.on_quiet_cycle(at(200)));
+        assert!(heartbeat.on_quiet_cycle(at(260)));
+    }
+}